@@ -0,0 +1,603 @@
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use k256::ecdsa::{SigningKey, VerifyingKey};
+use rand_core::OsRng;
+use risc0_zkvm::sha::Digest;
+use rwz_pof_core::{
+    aggregate_proof, check_revision, commitment_hash, create_bundle, create_signed_message,
+    create_signed_withdrawal, get_deterministic_signing_key, local_policy, preflight,
+    prove_commitment, registry_root, validate_commitments, verify_bundle, AcceptancePolicy,
+    FinancingRequirement, GuestVersion, JournalFormat, ProofBundle, ProofOptions, ReceiptKind,
+    SignedMessage, SignedRequirement, RWZ_POF_COMMITMENT_ID,
+};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Proof of financing without revealing the lending banks.
+#[derive(Debug, Parser)]
+#[command(name = "rwz-pof", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Generate a lending bank signing key (LB)
+    Keygen {
+        /// Where to write the hex-encoded secret key
+        #[arg(long)]
+        out: PathBuf,
+        /// Derive the demo registry key for this bank index instead of a random key
+        #[arg(long)]
+        index: Option<u64>,
+        /// Replace an existing key file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print the SEC1 public key of a signing key (LB)
+    ExportPubkey {
+        #[arg(long)]
+        key: PathBuf,
+    },
+    /// Sign a lending commitment for a deal (LB)
+    SignCommitment {
+        #[arg(long)]
+        key: PathBuf,
+        #[arg(long)]
+        amount: u64,
+        #[arg(long)]
+        deal_id: String,
+        #[arg(long)]
+        buyer: String,
+        /// Where to write the signed commitment JSON
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Sign a withdrawal of one of your commitments (LB)
+    WithdrawCommitment {
+        #[arg(long)]
+        key: PathBuf,
+        /// The signed commitment JSON to withdraw
+        #[arg(long)]
+        commitment: PathBuf,
+        /// Where to write the signed withdrawal JSON
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Sign the financing a seller requires for an auction and deal (SB)
+    SignRequirement {
+        #[arg(long)]
+        key: PathBuf,
+        #[arg(long)]
+        auction_id: String,
+        #[arg(long)]
+        deal_id: String,
+        #[arg(long)]
+        amount: u64,
+        /// Where to write the signed requirement JSON
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Prove commitments meet the required amount and write a proof bundle (BB)
+    Prove {
+        /// Signed commitment JSON files, one per lending bank
+        #[arg(long = "commitment", required = true)]
+        commitments: Vec<PathBuf>,
+        /// Required when no seller requirement is given
+        #[arg(long, required_unless_present = "requirement")]
+        required_amount: Option<u64>,
+        /// Prove the amount in this seller-signed requirement JSON
+        #[arg(long)]
+        requirement: Option<PathBuf>,
+        /// Raise the bid proven by this earlier bundle of the same buyer and deal
+        #[arg(long)]
+        revises: Option<PathBuf>,
+        /// composite, succinct or groth16
+        #[arg(long, default_value = "composite", value_parser = parse_receipt_kind)]
+        receipt_kind: ReceiptKind,
+        /// Bind the proof to this Seller's Bank
+        #[arg(long)]
+        recipient: Option<String>,
+        /// Answer this auction challenge nonce
+        #[arg(long)]
+        challenge: Option<String>,
+        /// Sign the bundle with the buyer's bank key
+        #[arg(long)]
+        sign_key: Option<PathBuf>,
+        /// Where to write the .pof bundle
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Verify a proof bundle (SB)
+    Verify {
+        #[arg(long)]
+        bundle: PathBuf,
+        /// Also accept this guest image ID (hex) besides the ones built into this binary
+        #[arg(long)]
+        image_id: Option<String>,
+        /// Journal format of the guest given with --image-id (only v6 so far)
+        #[arg(long, default_value = "v6", requires = "image_id", value_parser = parse_journal_format)]
+        journal_format: JournalFormat,
+        /// Accepted registry root, defaults to the one built into this binary
+        #[arg(long)]
+        registry_root: Option<String>,
+        /// Require the bundle to be signed by this buyer's bank public key
+        #[arg(long)]
+        signer: Option<String>,
+        /// Acceptance policy file (TOML or JSON) to evaluate the proof against
+        #[arg(long)]
+        policy: Option<PathBuf>,
+        /// The buyer's bid, for the policy's minimum amount rule
+        #[arg(long)]
+        bid_amount: Option<u64>,
+        /// Require the proof to meet this signed requirement JSON
+        #[arg(long)]
+        requirement: Option<PathBuf>,
+        /// Require the proof to raise the bid proven by this earlier bundle
+        #[arg(long)]
+        revises: Option<PathBuf>,
+    },
+}
+
+fn parse_receipt_kind(value: &str) -> Result<ReceiptKind, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|_| format!("unknown receipt kind {}", value))
+}
+
+fn parse_journal_format(value: &str) -> Result<JournalFormat, String> {
+    match value {
+        "v6" => Ok(JournalFormat::V6),
+        _ => Err(format!("unknown journal format {}", value)),
+    }
+}
+
+fn read_key(path: &Path) -> Result<SigningKey> {
+    let hex_key = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read key {}", path.display()))?;
+    let bytes = hex::decode(hex_key.trim()).context("Key file is not hex")?;
+    SigningKey::from_slice(&bytes).context("Invalid signing key")
+}
+
+// Only readable by the owner on unix. An existing key is only replaced with
+// `force`, and then recreated so it gets the same permissions.
+fn write_key(path: &Path, key: &SigningKey, force: bool) -> Result<()> {
+    if force {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("Failed to replace key {}", path.display()))
+            }
+            _ => {}
+        }
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = match options.open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            bail!(
+                "{} already exists, pass --force to replace it",
+                path.display()
+            )
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to write key {}", path.display())),
+    };
+    writeln!(file, "{}", hex::encode(key.to_bytes()))
+        .with_context(|| format!("Failed to write key {}", path.display()))
+}
+
+fn read_commitment(path: &Path) -> Result<SignedMessage> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read commitment {}", path.display()))?;
+    serde_json::from_str(&json).with_context(|| format!("Invalid commitment {}", path.display()))
+}
+
+fn load_bundle(path: &Path) -> Result<ProofBundle> {
+    ProofBundle::load(path).with_context(|| format!("Failed to load bundle {}", path.display()))
+}
+
+fn read_requirement(path: &Path) -> Result<SignedRequirement> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read requirement {}", path.display()))?;
+    serde_json::from_str(&json).with_context(|| format!("Invalid requirement {}", path.display()))
+}
+
+// The amount to prove: at least the seller's, if it signed a requirement
+fn resolve_required_amount(
+    required_amount: Option<u64>,
+    requirement: Option<&SignedRequirement>,
+) -> Result<u64> {
+    match (required_amount, requirement) {
+        (Some(amount), Some(signed)) if amount < signed.requirement.amount => bail!(
+            "Required amount {} is below the seller's requirement of {}",
+            amount,
+            signed.requirement.amount
+        ),
+        (Some(amount), _) => Ok(amount),
+        (None, Some(signed)) => Ok(signed.requirement.amount),
+        (None, None) => bail!("Either a required amount or a requirement is needed"),
+    }
+}
+
+fn keygen(out: &Path, index: Option<u64>, force: bool) -> Result<()> {
+    let key = match index {
+        Some(index) => get_deterministic_signing_key(index),
+        None => SigningKey::random(&mut OsRng),
+    };
+    write_key(out, &key, force)?;
+
+    println!("Wrote signing key to {}", out.display());
+    println!(
+        "Public key: {}",
+        hex::encode(VerifyingKey::from(&key).to_sec1_bytes())
+    );
+    Ok(())
+}
+
+fn export_pubkey(key: &Path) -> Result<()> {
+    let key = read_key(key)?;
+    println!("{}", hex::encode(VerifyingKey::from(&key).to_sec1_bytes()));
+    Ok(())
+}
+
+fn sign_commitment(
+    key: &Path,
+    amount: u64,
+    deal_id: String,
+    buyer: String,
+    out: &Path,
+) -> Result<()> {
+    let key = read_key(key)?;
+    let signed = create_signed_message(&key, amount, deal_id, buyer)?;
+
+    std::fs::write(out, serde_json::to_string_pretty(&signed)?)
+        .with_context(|| format!("Failed to write commitment {}", out.display()))?;
+    println!("Wrote signed commitment to {}", out.display());
+    Ok(())
+}
+
+fn withdraw_commitment(key: &Path, commitment: &Path, out: &Path) -> Result<()> {
+    let key = read_key(key)?;
+    let commitment_id = commitment_hash(&read_commitment(commitment)?)?;
+    let withdrawal = create_signed_withdrawal(&key, commitment_id.clone())?;
+
+    std::fs::write(out, serde_json::to_string_pretty(&withdrawal)?)
+        .with_context(|| format!("Failed to write withdrawal {}", out.display()))?;
+    println!(
+        "Wrote withdrawal of commitment {} to {}",
+        commitment_id,
+        out.display()
+    );
+    Ok(())
+}
+
+fn sign_requirement(key: &Path, requirement: FinancingRequirement, out: &Path) -> Result<()> {
+    let key = read_key(key)?;
+    let signed = SignedRequirement::sign(&key, requirement)?;
+
+    std::fs::write(out, serde_json::to_string_pretty(&signed)?)
+        .with_context(|| format!("Failed to write requirement {}", out.display()))?;
+    println!("Wrote signed requirement to {}", out.display());
+    println!("Requirement hash: {}", hex::encode(signed.hash()?));
+    Ok(())
+}
+
+fn prove(
+    commitment_paths: &[PathBuf],
+    required_amount: Option<u64>,
+    options: ProofOptions,
+    sign_key: Option<&Path>,
+    out: &Path,
+) -> Result<()> {
+    let commitments = commitment_paths
+        .iter()
+        .map(|path| read_commitment(path))
+        .collect::<Result<Vec<_>>>()?;
+
+    let required_amount = resolve_required_amount(required_amount, options.requirement.as_ref())?;
+    if let Some(signed) = &options.requirement {
+        signed.verify()?;
+    }
+
+    let issues = validate_commitments(&commitments, Some(required_amount));
+    if !issues.is_empty() {
+        for issue in &issues {
+            eprintln!("  {}", issue);
+        }
+        bail!("{} validation issue(s)", issues.len());
+    }
+
+    let report = preflight(&commitments, required_amount, &options)?;
+    if !report.passed {
+        bail!("Preflight failed: {}", report.failure.unwrap_or_default());
+    }
+    println!(
+        "Preflight passed: {} cycles in {} segments, ~{}s to prove",
+        report.cycles, report.segments, report.estimated_proving_secs
+    );
+
+    let receipts = commitments
+        .iter()
+        .map(prove_commitment)
+        .collect::<Result<Vec<_>, _>>()?;
+    let (receipt, deal_info, verified_amount) =
+        aggregate_proof(&receipts, required_amount, &options)?;
+
+    let mut bundle = create_bundle(receipt)?;
+    if let Some(sign_key) = sign_key {
+        bundle.sign(&read_key(sign_key)?)?;
+    }
+    bundle.save(out)?;
+
+    println!(
+        "Proved {} for deal {} (buyer {})",
+        verified_amount, deal_info.deal_id, deal_info.buyer
+    );
+    println!("Wrote proof bundle to {}", out.display());
+    Ok(())
+}
+
+fn verify(
+    bundle_path: &Path,
+    image_id: Option<(&str, JournalFormat)>,
+    accepted_root: Option<&str>,
+    signer: Option<&str>,
+    acceptance: Option<(&Path, Option<u64>)>,
+    requirement: Option<&Path>,
+    revises: Option<&Path>,
+) -> Result<()> {
+    let bundle = load_bundle(bundle_path)?;
+
+    let mut policy = local_policy();
+    if let Some((hex_id, journal)) = image_id {
+        let bytes = hex::decode(hex_id).context("Image ID is not hex")?;
+        let digest = Digest::try_from(bytes.as_slice()).context("Invalid image ID")?;
+        let words: [u32; 8] = digest.as_words().try_into().context("Invalid image ID")?;
+        // Must aggregate this binary's commitment guest, whose registry is known
+        policy.guest_versions.push(GuestVersion {
+            label: format!("custom-{}", hex_id),
+            image_id: words,
+            commitment_image_id: Some(RWZ_POF_COMMITMENT_ID),
+            registry_root: Some(registry_root()),
+            journal,
+            deprecated_at: None,
+        });
+    }
+    if let Some(root) = accepted_root {
+        policy.accepted_registry_roots = vec![root.to_string()];
+    }
+    if let Some(signer) = signer {
+        policy.required_signer = Some(hex::decode(signer).context("Signer is not hex")?);
+    }
+
+    let verified = verify_bundle(&bundle, &policy)?;
+
+    println!("Proof verified");
+    println!("  deal_id: {}", verified.deal_info.deal_id);
+    println!("  buyer: {}", verified.deal_info.buyer);
+    println!("  verified_amount: {}", verified.verified_amount);
+    println!("  image_id: {}", Digest::from(verified.image_id));
+    println!("  guest_version: {}", verified.guest_version);
+    println!(
+        "  created_at: {}{}",
+        bundle.created_at,
+        if verified.created_at.is_some() {
+            ""
+        } else {
+            " (unsigned, not authenticated)"
+        }
+    );
+    if let Some(root) = &verified.registry_root {
+        println!("  registry_root: {}", root);
+    }
+    println!("  prover_version: {}", bundle.prover_version);
+    println!("  bank_count: {}", verified.bank_count);
+    println!("  journal_digest: {}", hex::encode(verified.journal_digest));
+    if let Some(digest) = &verified.previous_journal_digest {
+        println!("  revises: {}", hex::encode(digest));
+    }
+    if let Some(recipient) = &verified.recipient {
+        println!("  recipient: {}", recipient);
+    }
+    if let Some(challenge) = &verified.challenge {
+        println!("  challenge: {}", challenge);
+    }
+    if let Some(hash) = &verified.requirement_hash {
+        println!("  requirement_hash: {}", hex::encode(hash));
+    }
+    if let Some(pubkey) = &verified.signer {
+        println!("  signed_by: {}", hex::encode(pubkey));
+    }
+
+    if let Some(path) = requirement {
+        let signed = read_requirement(path)?;
+        if verified.requirement_hash != Some(signed.hash()?) {
+            bail!(
+                "Proof doesn't meet requirement {}",
+                hex::encode(signed.hash()?)
+            );
+        }
+        println!("Proof meets the signed requirement");
+    }
+
+    if let Some(path) = revises {
+        let previous = verify_bundle(&load_bundle(path)?, &policy)
+            .with_context(|| format!("Previous bundle {} doesn't verify", path.display()))?;
+        check_revision(&previous, &verified)?;
+        println!(
+            "Proof raises {} to {}",
+            previous.verified_amount, verified.verified_amount
+        );
+    }
+
+    if let Some((policy_path, bid_amount)) = acceptance {
+        let policy = AcceptancePolicy::load(policy_path)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let verdict = policy.evaluate(&verified, bid_amount, now);
+
+        println!("Policy {}:", verdict.policy_id);
+        for rule in &verdict.rules {
+            let outcome = if rule.passed { "pass" } else { "FAIL" };
+            println!("  [{}] {}: {}", outcome, rule.rule, rule.detail);
+        }
+        if !verdict.accepted {
+            bail!("Proof rejected by policy {}", verdict.policy_id);
+        }
+        println!("Proof accepted");
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::filter::EnvFilter::from_default_env())
+        .init();
+
+    match Cli::parse().command {
+        Command::Keygen { out, index, force } => keygen(&out, index, force),
+        Command::ExportPubkey { key } => export_pubkey(&key),
+        Command::SignCommitment {
+            key,
+            amount,
+            deal_id,
+            buyer,
+            out,
+        } => sign_commitment(&key, amount, deal_id, buyer, &out),
+        Command::WithdrawCommitment {
+            key,
+            commitment,
+            out,
+        } => withdraw_commitment(&key, &commitment, &out),
+        Command::SignRequirement {
+            key,
+            auction_id,
+            deal_id,
+            amount,
+            out,
+        } => sign_requirement(
+            &key,
+            FinancingRequirement {
+                auction_id,
+                deal_id,
+                amount,
+            },
+            &out,
+        ),
+        Command::Prove {
+            commitments,
+            required_amount,
+            requirement,
+            revises,
+            receipt_kind,
+            recipient,
+            challenge,
+            sign_key,
+            out,
+        } => prove(
+            &commitments,
+            required_amount,
+            ProofOptions {
+                receipt_kind,
+                recipient,
+                challenge,
+                requirement: requirement.as_deref().map(read_requirement).transpose()?,
+                previous_journal: revises
+                    .as_deref()
+                    .map(load_bundle)
+                    .transpose()?
+                    .map(|bundle| bundle.receipt.journal.bytes),
+            },
+            sign_key.as_deref(),
+            &out,
+        ),
+        Command::Verify {
+            bundle,
+            image_id,
+            journal_format,
+            registry_root,
+            signer,
+            policy,
+            bid_amount,
+            requirement,
+            revises,
+        } => verify(
+            &bundle,
+            image_id.as_deref().map(|id| (id, journal_format)),
+            registry_root.as_deref(),
+            signer.as_deref(),
+            policy.as_deref().map(|path| (path, bid_amount)),
+            requirement.as_deref(),
+            revises.as_deref(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_file_round_trip() {
+        let path = std::env::temp_dir().join(format!("rwz-pof-cli-{}.key", std::process::id()));
+
+        let key = get_deterministic_signing_key(0);
+        write_key(&path, &key, false).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // An existing key is only replaced when forced
+        let other = get_deterministic_signing_key(1);
+        assert!(write_key(&path, &other, false).is_err());
+        assert_eq!(read_key(&path).unwrap().to_bytes(), key.to_bytes());
+        write_key(&path, &other, true).unwrap();
+        let loaded = read_key(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.to_bytes(), other.to_bytes());
+    }
+
+    #[test]
+    fn test_parse_receipt_kind() {
+        assert_eq!(parse_receipt_kind("succinct"), Ok(ReceiptKind::Succinct));
+        assert!(parse_receipt_kind("tiny").is_err());
+    }
+
+    #[test]
+    fn test_parse_journal_format() {
+        assert_eq!(parse_journal_format("v6"), Ok(JournalFormat::V6));
+        assert!(parse_journal_format("v2").is_err());
+    }
+
+    #[test]
+    fn test_resolve_required_amount() {
+        let signed = SignedRequirement::sign(
+            &get_deterministic_signing_key(0),
+            FinancingRequirement {
+                auction_id: "AUCTION1".into(),
+                deal_id: "DEAL123".into(),
+                amount: 60,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(resolve_required_amount(Some(50), None).unwrap(), 50);
+        assert_eq!(resolve_required_amount(None, Some(&signed)).unwrap(), 60);
+        assert_eq!(
+            resolve_required_amount(Some(60), Some(&signed)).unwrap(),
+            60
+        );
+        assert_eq!(
+            resolve_required_amount(Some(80), Some(&signed)).unwrap(),
+            80
+        );
+        assert!(resolve_required_amount(Some(50), Some(&signed)).is_err());
+    }
+}
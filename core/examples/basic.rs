@@ -1,5 +1,4 @@
 use rwz_pof_core::{create_signed_message, generate_proof, get_deterministic_signing_key};
-use tracing_subscriber;
 
 fn main() {
     // Initialize tracing
@@ -0,0 +1,31 @@
+use risc0_zkvm::sha::Digest;
+use rwz_pof_core::{registry_root, RWZ_POF_COMMITMENT_ID, RWZ_POF_GUEST_ID};
+
+// Prints verifier/src/image_ids.rs for the guests built from this tree
+fn main() {
+    // Without built guests every receipt would be rejected by the verifier
+    if RWZ_POF_GUEST_ID == [0; 8] || RWZ_POF_COMMITMENT_ID == [0; 8] {
+        eprintln!("Image IDs are zero, build the guests without RISC0_SKIP_BUILD");
+        std::process::exit(1);
+    }
+
+    println!("// Image IDs of the guests this verifier accepts by default, embedded so that");
+    println!("// verifying never requires building the guest. Regenerate after changing a");
+    println!("// guest with `cargo run -p rwz-pof-core --example image_ids`; a test in");
+    println!("// rwz-pof-core fails while they are out of date.");
+    println!();
+    println!(
+        "// {}\npub const RWZ_POF_GUEST_ID: [u32; 8] = {:?};",
+        Digest::from(RWZ_POF_GUEST_ID),
+        RWZ_POF_GUEST_ID
+    );
+    println!();
+    println!(
+        "// {}\npub const RWZ_POF_COMMITMENT_ID: [u32; 8] = {:?};",
+        Digest::from(RWZ_POF_COMMITMENT_ID),
+        RWZ_POF_COMMITMENT_ID
+    );
+    println!();
+    println!("// Digest over the lending bank registry compiled into the commitment guest");
+    println!("pub const REGISTRY_ROOT: &str = \"{}\";", registry_root());
+}
@@ -0,0 +1,73 @@
+use risc0_zkvm::Receipt;
+use rwz_pof_verifier::{
+    previous_guest_versions, GuestVersion, JournalFormat, ProofBundle, VerificationPolicy,
+    GUEST_VERSION,
+};
+
+use crate::engine::decode_journal;
+use crate::registry::registry_root;
+use crate::types::Result;
+
+/// Wraps a receipt of the aggregation guest built from this tree in a bundle
+/// for the Seller's Bank.
+pub fn create_bundle(receipt: Receipt) -> Result<ProofBundle> {
+    // Fails early if the receipt didn't aggregate our commitment guest
+    decode_journal(&receipt)?;
+
+    Ok(ProofBundle::new(
+        receipt,
+        crate::RWZ_POF_GUEST_ID,
+        registry_root(),
+    )?)
+}
+
+/// The guest built from this tree, which may be newer than the one embedded
+/// in the verifier crate.
+pub fn local_guest_version() -> GuestVersion {
+    GuestVersion {
+        label: GUEST_VERSION.to_string(),
+        image_id: crate::RWZ_POF_GUEST_ID,
+        commitment_image_id: Some(crate::RWZ_POF_COMMITMENT_ID),
+        registry_root: Some(registry_root()),
+        journal: JournalFormat::V6,
+        deprecated_at: None,
+    }
+}
+
+/// Policy accepting the guest and registry built from this tree, plus the
+/// previous guests that aren't deprecated yet.
+pub fn local_policy() -> VerificationPolicy {
+    let mut guest_versions = vec![local_guest_version()];
+    guest_versions.extend(previous_guest_versions());
+
+    VerificationPolicy {
+        guest_versions,
+        accepted_registry_roots: vec![registry_root()],
+        required_signer: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded_ids_are_current() {
+        // A build with RISC0_SKIP_BUILD has zero image IDs, which would make
+        // zero IDs embedded in the verifier look current
+        assert_ne!(
+            crate::RWZ_POF_GUEST_ID,
+            [0; 8],
+            "Guests weren't built (RISC0_SKIP_BUILD?), can't check the embedded image IDs"
+        );
+        assert_ne!(crate::RWZ_POF_COMMITMENT_ID, [0; 8]);
+
+        // Regenerate with `cargo run -p rwz-pof-core --example image_ids`
+        assert_eq!(rwz_pof_verifier::RWZ_POF_GUEST_ID, crate::RWZ_POF_GUEST_ID);
+        assert_eq!(
+            rwz_pof_verifier::RWZ_POF_COMMITMENT_ID,
+            crate::RWZ_POF_COMMITMENT_ID
+        );
+        assert_eq!(rwz_pof_verifier::REGISTRY_ROOT, registry_root());
+    }
+}
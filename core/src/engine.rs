@@ -1,16 +1,24 @@
 use k256::ecdsa::{signature::Signer, SigningKey, VerifyingKey};
-use k256::{elliptic_curve::generic_array::GenericArray, SecretKey};
-use risc0_zkvm::{default_prover, ExecutorEnv, Receipt};
+use k256::SecretKey;
+use risc0_zkvm::sha::{Impl, Sha256};
+use risc0_zkvm::{
+    default_executor, default_prover, AssumptionReceipt, ExecutorEnv, Receipt, SessionInfo,
+};
 
-use crate::types::{CoreError, DealInfo, Result, SignedMessage, SEED};
+use crate::types::{
+    CoreError, DealInfo, PreflightReport, ProofOptions, Result, SignedMessage, SignedWithdrawal,
+    StageCycles, VerifiedCommitment, SEED,
+};
+
+// Rough CPU proving throughput, used to estimate proving time from padded cycles
+const ESTIMATED_CYCLES_PER_SEC: u64 = 100_000;
 
 pub fn get_deterministic_signing_key(offset: u64) -> SigningKey {
     let seed_bytes = (SEED.wrapping_add(offset)).to_le_bytes();
     let mut key_bytes = [0u8; 32];
     key_bytes[..8].copy_from_slice(&seed_bytes);
 
-    let generic_bytes = GenericArray::from_slice(&key_bytes);
-    let secret_key = SecretKey::from_bytes(generic_bytes).expect("Invalid key bytes");
+    let secret_key = SecretKey::from_slice(&key_bytes).expect("Invalid key bytes");
     SigningKey::from(secret_key)
 }
 
@@ -37,41 +45,257 @@ pub fn create_signed_message(
     })
 }
 
+/// Bytes a lending bank signs to withdraw a commitment. The prefix keeps a
+/// withdrawal signature from ever being read as a commitment signature.
+pub fn withdrawal_message(commitment_id: &str) -> Result<Vec<u8>> {
+    Ok(bincode::serialize(&("rwz-pof-withdraw", commitment_id))?)
+}
+
+pub fn create_signed_withdrawal(
+    signing_key: &SigningKey,
+    commitment_id: String,
+) -> Result<SignedWithdrawal> {
+    let signature: k256::ecdsa::Signature = signing_key.sign(&withdrawal_message(&commitment_id)?);
+    let verifying_key = VerifyingKey::from(signing_key);
+
+    Ok(SignedWithdrawal {
+        pubkey: verifying_key.to_sec1_bytes().to_vec(),
+        commitment_id,
+        signature: signature.to_bytes().to_vec(),
+    })
+}
+
+/// Hash identifying a signed commitment, used to cache its sub-receipt.
+pub fn commitment_hash(signed: &SignedMessage) -> Result<String> {
+    let bytes = bincode::serialize(signed)?;
+    Ok(Impl::hash_bytes(&bytes).to_string())
+}
+
+/// Proves a single commitment with the commitment guest. The receipt attests
+/// that the signature is valid and the signer is in the registry, and can be
+/// reused in any later aggregation that includes this commitment.
+pub fn prove_commitment(signed: &SignedMessage) -> Result<Receipt> {
+    let mut stdout = Vec::new();
+    let env = commitment_env(signed, &mut stdout)?;
+
+    let prover = default_prover();
+
+    let prove_info = prover
+        .prove(env, crate::RWZ_POF_COMMITMENT_ELF)
+        .map_err(|e| CoreError::ProofError(e.to_string()))?;
+
+    log_stage_cycles("commitment", &stdout);
+
+    Ok(prove_info.receipt)
+}
+
+/// Aggregates commitment sub-receipts into the final proof of financing,
+/// producing the receipt kind selected in `options`.
+pub fn aggregate_proof(
+    commitment_receipts: &[Receipt],
+    proof_amount: u64,
+    options: &ProofOptions,
+) -> Result<(Receipt, DealInfo, u64)> {
+    let commitments = commitment_receipts
+        .iter()
+        .map(|receipt| {
+            receipt
+                .journal
+                .decode::<VerifiedCommitment>()
+                .map_err(|e| CoreError::Risc0Error(e.to_string()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let assumptions = commitment_receipts
+        .iter()
+        .cloned()
+        .map(AssumptionReceipt::from)
+        .collect();
+
+    let mut stdout = Vec::new();
+    let env = aggregation_env(
+        assumptions,
+        &commitments,
+        proof_amount,
+        options,
+        &mut stdout,
+    )?;
+
+    let prover = default_prover();
+
+    let prove_info = prover
+        .prove_with_opts(env, crate::RWZ_POF_GUEST_ELF, &options.prover_opts())
+        .map_err(|e| CoreError::ProofError(e.to_string()))?;
+
+    log_stage_cycles("aggregation", &stdout);
+
+    let receipt = prove_info.receipt;
+    let (deal_info, verified_amount) = decode_journal(&receipt)?;
+
+    Ok((receipt, deal_info, verified_amount))
+}
+
 pub fn generate_proof(
     lb1_signed: SignedMessage,
     lb2_signed: SignedMessage,
     proof_amount: u64,
 ) -> Result<(Receipt, DealInfo, u64)> {
-    let env = ExecutorEnv::builder()
-        .write(&lb1_signed)
+    let commitment_receipts = [
+        prove_commitment(&lb1_signed)?,
+        prove_commitment(&lb2_signed)?,
+    ];
+
+    aggregate_proof(&commitment_receipts, proof_amount, &ProofOptions::default())
+}
+
+/// Size of the receipt as shipped to the seller, in bytes.
+pub fn receipt_size(receipt: &Receipt) -> Result<usize> {
+    Ok(bincode::serialized_size(receipt)? as usize)
+}
+
+/// SHA-256 of the serialized receipt, identifying it for caching and downloads.
+pub fn receipt_digest(receipt: &Receipt) -> Result<String> {
+    let bytes = bincode::serialize(receipt)?;
+    Ok(Impl::hash_bytes(&bytes).to_string())
+}
+
+/// Runs the guests with the executor only, without proving. Catches invalid
+/// commitments or an insufficient amount before paying for a proof, and
+/// estimates how long the proof will take. Takes the same options as the proof
+/// so a revision that doesn't raise the amount, or a requirement the amount
+/// doesn't meet, fails here too.
+pub fn preflight(
+    commitments: &[SignedMessage],
+    proof_amount: u64,
+    options: &ProofOptions,
+) -> Result<PreflightReport> {
+    let executor = default_executor();
+    let mut report = PreflightReport::default();
+    let mut padded_cycles = 0;
+
+    let mut verified = Vec::with_capacity(commitments.len());
+    let mut assumptions = Vec::with_capacity(commitments.len());
+
+    for signed in commitments {
+        let mut stdout = Vec::new();
+        let env = commitment_env(signed, &mut stdout)?;
+
+        let session = match executor.execute(env, crate::RWZ_POF_COMMITMENT_ELF) {
+            Ok(session) => session,
+            Err(e) => return Ok(report.fail(e.to_string())),
+        };
+        padded_cycles += add_session(&mut report, &session);
+
+        // Execution without proving leaves an unresolved assumption for the aggregation
+        let claim = session
+            .receipt_claim
+            .ok_or_else(|| CoreError::Risc0Error("Missing receipt claim".to_string()))?;
+        assumptions.push(AssumptionReceipt::from(claim));
+        verified.push(
+            session
+                .journal
+                .decode::<VerifiedCommitment>()
+                .map_err(|e| CoreError::Risc0Error(e.to_string()))?,
+        );
+    }
+
+    let mut stdout = Vec::new();
+    let env = aggregation_env(assumptions, &verified, proof_amount, options, &mut stdout)?;
+
+    match executor.execute(env, crate::RWZ_POF_GUEST_ELF) {
+        Ok(session) => padded_cycles += add_session(&mut report, &session),
+        Err(e) => return Ok(report.fail(e.to_string())),
+    }
+
+    report.passed = true;
+    report.estimated_proving_secs = padded_cycles.div_ceil(ESTIMATED_CYCLES_PER_SEC);
+    Ok(report)
+}
+
+// Adds the session's user cycles and segments to the report, returning padded cycles
+fn add_session(report: &mut PreflightReport, session: &SessionInfo) -> u64 {
+    report.cycles += session.cycles();
+    report.segments += session.segments.len();
+    session.segments.iter().map(|s| 1u64 << s.po2).sum()
+}
+
+fn commitment_env<'a>(signed: &SignedMessage, stdout: &'a mut Vec<u8>) -> Result<ExecutorEnv<'a>> {
+    ExecutorEnv::builder()
+        .write(signed)
+        .map_err(|e| CoreError::Risc0Error(e.to_string()))?
+        .stdout(stdout)
+        .build()
+        .map_err(|e| CoreError::Risc0Error(e.to_string()))
+}
+
+fn aggregation_env<'a>(
+    assumptions: Vec<AssumptionReceipt>,
+    commitments: &[VerifiedCommitment],
+    proof_amount: u64,
+    options: &ProofOptions,
+    stdout: &'a mut Vec<u8>,
+) -> Result<ExecutorEnv<'a>> {
+    let mut builder = ExecutorEnv::builder();
+    for assumption in assumptions {
+        builder.add_assumption(assumption);
+    }
+
+    builder
+        .stdout(stdout)
+        .write(&crate::RWZ_POF_COMMITMENT_ID)
         .map_err(|e| CoreError::Risc0Error(e.to_string()))?
-        .write(&lb2_signed)
+        .write(&commitments)
         .map_err(|e| CoreError::Risc0Error(e.to_string()))?
         .write(&proof_amount)
         .map_err(|e| CoreError::Risc0Error(e.to_string()))?
+        .write(&options.recipient)
+        .map_err(|e| CoreError::Risc0Error(e.to_string()))?
+        .write(&options.challenge)
+        .map_err(|e| CoreError::Risc0Error(e.to_string()))?
+        .write(&options.requirement)
+        .map_err(|e| CoreError::Risc0Error(e.to_string()))?
+        .write(&options.previous_journal)
+        .map_err(|e| CoreError::Risc0Error(e.to_string()))?
         .build()
-        .map_err(|e| CoreError::Risc0Error(e.to_string()))?;
+        .map_err(|e| CoreError::Risc0Error(e.to_string()))
+}
 
-    let prover = default_prover();
+/// Decodes the per-stage cycle counts a guest writes to its stdout.
+pub fn read_stage_cycles(stdout: &[u8]) -> Result<Vec<StageCycles>> {
+    risc0_zkvm::serde::from_slice(stdout).map_err(|e| CoreError::Risc0Error(e.to_string()))
+}
 
-    let prove_info = prover
-        .prove(env, crate::RWZ_POF_GUEST_ELF)
-        .map_err(|e| CoreError::ProofError(e.to_string()))?;
+fn log_stage_cycles(guest: &str, stdout: &[u8]) {
+    match read_stage_cycles(stdout) {
+        Ok(stages) => {
+            for stage in &stages {
+                tracing::info!(guest, stage = %stage.stage, cycles = stage.cycles, "guest stage");
+            }
+            let total: u64 = stages.iter().map(|s| s.cycles).sum();
+            tracing::info!(guest, total_cycles = total, "guest finished");
+        }
+        Err(e) => tracing::warn!(guest, "Failed to read stage cycles: {}", e),
+    }
+}
 
-    let receipt = prove_info.receipt;
+/// Extracts the public deal info from a proof, checking that it was aggregated
+/// from receipts of the expected commitment guest.
+pub fn decode_journal(receipt: &Receipt) -> Result<(DealInfo, u64)> {
+    let journal = rwz_pof_verifier::decode_journal(receipt)?;
 
-    // Extract journal data
-    let (deal_info, verified_amount): (DealInfo, u64) = receipt
-        .journal
-        .decode()
-        .map_err(|e| CoreError::Risc0Error(e.to_string()))?;
+    if journal.commitment_image_id != crate::RWZ_POF_COMMITMENT_ID {
+        return Err(CoreError::CommitmentImageMismatch(format!(
+            "{:?}",
+            journal.commitment_image_id
+        )));
+    }
 
-    Ok((receipt, deal_info, verified_amount))
+    Ok((journal.deal_info, journal.verified_amount))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::ReceiptKind;
     use k256::ecdsa::signature::Verifier;
 
     #[test]
@@ -105,4 +329,168 @@ mod tests {
         // Verify the receipt
         receipt.verify(crate::RWZ_POF_GUEST_ID).unwrap();
     }
+
+    #[test]
+    fn test_commitment_hash() {
+        let key = get_deterministic_signing_key(0);
+        let msg = create_signed_message(&key, 50, "DEAL123".into(), "buyer123".into()).unwrap();
+        let amended = create_signed_message(&key, 60, "DEAL123".into(), "buyer123".into()).unwrap();
+
+        assert_eq!(
+            commitment_hash(&msg).unwrap(),
+            commitment_hash(&msg.clone()).unwrap()
+        );
+        assert_ne!(
+            commitment_hash(&msg).unwrap(),
+            commitment_hash(&amended).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_incremental_proof() {
+        let lb1_key = get_deterministic_signing_key(0);
+        let lb2_key = get_deterministic_signing_key(1);
+
+        let lb1_signed =
+            create_signed_message(&lb1_key, 50, "DEAL123".into(), "buyer123".into()).unwrap();
+        let lb2_signed =
+            create_signed_message(&lb2_key, 30, "DEAL123".into(), "buyer123".into()).unwrap();
+
+        // Sub-receipts are proven once and can be aggregated at different amounts
+        let receipts = [
+            prove_commitment(&lb1_signed).unwrap(),
+            prove_commitment(&lb2_signed).unwrap(),
+        ];
+
+        let options = ProofOptions::default();
+        let (first, _, verified_amount) = aggregate_proof(&receipts, 60, &options).unwrap();
+        assert_eq!(verified_amount, 60);
+
+        // The higher amount as a revision of the first proof
+        let options = ProofOptions {
+            previous_journal: Some(first.journal.bytes.clone()),
+            ..Default::default()
+        };
+        let (receipt, _, verified_amount) = aggregate_proof(&receipts, 80, &options).unwrap();
+        assert_eq!(verified_amount, 80);
+        receipt.verify(crate::RWZ_POF_GUEST_ID).unwrap();
+        let journal = rwz_pof_verifier::decode_journal(&receipt).unwrap();
+        assert_eq!(
+            journal.previous_journal_digest,
+            Some(crate::journal_digest(&first))
+        );
+
+        // A revision has to raise the amount
+        assert!(aggregate_proof(&receipts, 60, &options).is_err());
+    }
+
+    #[test]
+    fn test_succinct_receipt() {
+        let lb1_key = get_deterministic_signing_key(0);
+        let lb2_key = get_deterministic_signing_key(1);
+
+        let receipts = [
+            prove_commitment(
+                &create_signed_message(&lb1_key, 50, "DEAL123".into(), "buyer123".into()).unwrap(),
+            )
+            .unwrap(),
+            prove_commitment(
+                &create_signed_message(&lb2_key, 30, "DEAL123".into(), "buyer123".into()).unwrap(),
+            )
+            .unwrap(),
+        ];
+
+        let requirement = crate::SignedRequirement::sign(
+            &get_deterministic_signing_key(9),
+            crate::FinancingRequirement {
+                auction_id: "AUCTION1".into(),
+                deal_id: "DEAL123".into(),
+                amount: 60,
+            },
+        )
+        .unwrap();
+        let options = ProofOptions {
+            receipt_kind: ReceiptKind::Succinct,
+            recipient: Some("SB1".into()),
+            challenge: Some("nonce-1".into()),
+            requirement: Some(requirement.clone()),
+            previous_journal: None,
+        };
+        let (receipt, _, _) = aggregate_proof(&receipts, 60, &options).unwrap();
+
+        assert!(receipt.inner.succinct().is_ok());
+        receipt.verify(crate::RWZ_POF_GUEST_ID).unwrap();
+        let journal = rwz_pof_verifier::decode_journal(&receipt).unwrap();
+        assert_eq!(journal.bank_count, 2);
+        assert_eq!(journal.recipient.as_deref(), Some("SB1"));
+        assert_eq!(journal.challenge.as_deref(), Some("nonce-1"));
+        assert_eq!(journal.nullifiers.len(), 2);
+        assert_eq!(journal.requirement_hash, Some(requirement.hash().unwrap()));
+        assert!(receipt_size(&receipt).unwrap() > 0);
+    }
+
+    #[test]
+    fn test_preflight() {
+        let lb1_key = get_deterministic_signing_key(0);
+        let lb2_key = get_deterministic_signing_key(1);
+
+        let lb1_signed =
+            create_signed_message(&lb1_key, 50, "DEAL123".into(), "buyer123".into()).unwrap();
+        let lb2_signed =
+            create_signed_message(&lb2_key, 30, "DEAL123".into(), "buyer123".into()).unwrap();
+        let commitments = [lb1_signed, lb2_signed];
+
+        let report = preflight(&commitments, 60, &ProofOptions::default()).unwrap();
+        assert!(report.passed);
+        assert!(report.cycles > 0);
+        assert!(report.segments >= 3);
+
+        // Insufficient total is caught without proving
+        let report = preflight(&commitments, 100, &ProofOptions::default()).unwrap();
+        assert!(!report.passed);
+        assert!(report
+            .failure
+            .unwrap()
+            .contains("less than required amount"));
+    }
+
+    #[test]
+    fn test_preflight_revision() {
+        let lb1_key = get_deterministic_signing_key(0);
+        let lb2_key = get_deterministic_signing_key(1);
+
+        let commitments = [
+            create_signed_message(&lb1_key, 50, "DEAL123".into(), "buyer123".into()).unwrap(),
+            create_signed_message(&lb2_key, 30, "DEAL123".into(), "buyer123".into()).unwrap(),
+        ];
+        let receipts = commitments
+            .iter()
+            .map(prove_commitment)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let (first, _, _) = aggregate_proof(&receipts, 60, &ProofOptions::default()).unwrap();
+
+        let options = ProofOptions {
+            previous_journal: Some(first.journal.bytes.clone()),
+            ..Default::default()
+        };
+        assert!(preflight(&commitments, 80, &options).unwrap().passed);
+
+        // A revision that doesn't raise the amount is caught without proving
+        let report = preflight(&commitments, 60, &options).unwrap();
+        assert!(!report.passed);
+        assert!(report.failure.unwrap().contains("doesn't exceed"));
+    }
+
+    #[test]
+    fn test_read_stage_cycles() {
+        let words =
+            risc0_zkvm::serde::to_vec(&vec![("registry", 1200u64), ("prehash", 300u64)]).unwrap();
+        let stdout: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+
+        let stages = read_stage_cycles(&stdout).unwrap();
+        assert_eq!(stages.len(), 2);
+        assert_eq!(stages[0].stage, "registry");
+        assert_eq!(stages[1].cycles, 300);
+    }
 }
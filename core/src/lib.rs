@@ -1,8 +1,32 @@
+pub mod bundle;
 pub mod engine;
+pub mod registry;
+pub mod selection;
 pub mod types;
+pub mod validation;
 
-pub use engine::{create_signed_message, generate_proof, get_deterministic_signing_key};
-pub use types::{DealInfo, SignedMessage};
+pub use bundle::{create_bundle, local_guest_version, local_policy};
+pub use engine::{
+    aggregate_proof, commitment_hash, create_signed_message, create_signed_withdrawal,
+    decode_journal, generate_proof, get_deterministic_signing_key, preflight, prove_commitment,
+    read_stage_cycles, receipt_digest, receipt_size, withdrawal_message,
+};
+pub use registry::{is_registered, registry_pubkeys, registry_root};
+pub use selection::{select_commitments, SelectionStrategy};
+pub use types::{
+    CoreError, DealInfo, PreflightReport, ProofOptions, ReceiptKind, SignedMessage,
+    SignedWithdrawal, StageCycles, ValidationIssue, VerifiedCommitment,
+};
+pub use validation::{validate_commitments, withdrawal_is_valid};
+
+pub use rwz_pof_verifier::{
+    check_revision, journal_digest, verify_bundle, verify_receipt, AcceptancePolicy,
+    BundleSignature, FinancingRequirement, GuestVersion, JournalFormat, PofJournal, PolicyVerdict,
+    ProofBundle, RuleResult, SignedRequirement, VerificationPolicy, VerifiedDeal, BUNDLE_EXTENSION,
+    BUNDLE_MAGIC,
+};
 
 // Re-export essential RISC0 components that consumers might need
-pub use methods::{RWZ_POF_GUEST_ELF, RWZ_POF_GUEST_ID};
+pub use methods::{
+    RWZ_POF_COMMITMENT_ELF, RWZ_POF_COMMITMENT_ID, RWZ_POF_GUEST_ELF, RWZ_POF_GUEST_ID,
+};
@@ -0,0 +1,34 @@
+use k256::ecdsa::VerifyingKey;
+use risc0_zkvm::sha::{Impl, Sha256};
+
+use crate::engine::get_deterministic_signing_key;
+
+// Number of lending banks known to the guest, LB1 and LB2
+pub const REGISTRY_SIZE: u64 = 2;
+
+/// SEC1-encoded public keys of the lending banks the commitment guest accepts.
+/// Must match `get_valid_pubkeys` in the guest.
+pub fn registry_pubkeys() -> Vec<Vec<u8>> {
+    (0..REGISTRY_SIZE)
+        .map(|offset| {
+            VerifyingKey::from(&get_deterministic_signing_key(offset))
+                .to_sec1_bytes()
+                .to_vec()
+        })
+        .collect()
+}
+
+pub fn is_registered(pubkey: &[u8]) -> bool {
+    registry_pubkeys().iter().any(|valid| valid == pubkey)
+}
+
+/// Digest over the registry public keys, identifying the set of lending banks
+/// a proof was checked against.
+pub fn registry_root() -> String {
+    let mut bytes = Vec::new();
+    for pubkey in registry_pubkeys() {
+        bytes.extend_from_slice(&(pubkey.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&pubkey);
+    }
+    Impl::hash_bytes(&bytes).to_string()
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::registry::registry_pubkeys;
+use crate::types::{SignedMessage, ValidationIssue};
+use crate::validation::validate_commitments;
+
+/// How to pick commitments for a proof when the buyer's bank doesn't name them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum SelectionStrategy {
+    /// The smallest set of commitments that covers the required amount
+    #[default]
+    Fewest,
+    /// Every usable commitment, one per lending bank
+    MostBanks,
+    /// Highest-rated banks first until the required amount is covered.
+    /// Ratings are keyed by registry index, unrated banks count as zero.
+    HighestRated { ratings: HashMap<u64, u32> },
+}
+
+/// Picks commitments covering `required_amount`, returning their indices in
+/// `commitments`. Commitments that would fail validation on their own are
+/// skipped, and only the largest commitment of each bank is considered.
+pub fn select_commitments(
+    commitments: &[SignedMessage],
+    required_amount: u64,
+    strategy: &SelectionStrategy,
+) -> Result<Vec<usize>, ValidationIssue> {
+    let Some(first) = commitments.first() else {
+        return Err(ValidationIssue::NoCommitments);
+    };
+
+    let mut candidates: Vec<usize> = Vec::new();
+    for (index, signed) in commitments.iter().enumerate() {
+        let usable = signed.message.deal_id == first.message.deal_id
+            && signed.message.buyer == first.message.buyer
+            && validate_commitments(std::slice::from_ref(signed), None).is_empty();
+        if !usable {
+            continue;
+        }
+
+        match candidates
+            .iter_mut()
+            .find(|other| commitments[**other].pubkey == signed.pubkey)
+        {
+            Some(other) if commitments[*other].message.amount < signed.message.amount => {
+                *other = index
+            }
+            Some(_) => {}
+            None => candidates.push(index),
+        }
+    }
+
+    let amount = |index: &usize| commitments[*index].message.amount;
+    match strategy {
+        // Taking the largest amounts first needs the fewest commitments
+        SelectionStrategy::Fewest | SelectionStrategy::MostBanks => {
+            candidates.sort_by_key(|index| std::cmp::Reverse(amount(index)))
+        }
+        SelectionStrategy::HighestRated { ratings } => {
+            let registry = registry_pubkeys();
+            let rating = |index: &usize| {
+                registry
+                    .iter()
+                    .position(|pubkey| *pubkey == commitments[*index].pubkey)
+                    .and_then(|bank| ratings.get(&(bank as u64)))
+                    .copied()
+                    .unwrap_or_default()
+            };
+            candidates.sort_by_key(|index| std::cmp::Reverse((rating(index), amount(index))));
+        }
+    }
+
+    let mut selected = Vec::new();
+    let mut total = 0u64;
+    for index in candidates.iter().copied() {
+        if total >= required_amount && *strategy != SelectionStrategy::MostBanks {
+            break;
+        }
+        total = total.saturating_add(amount(&index));
+        selected.push(index);
+    }
+
+    if total < required_amount {
+        return Err(ValidationIssue::InsufficientAmount {
+            total,
+            required: required_amount,
+        });
+    }
+
+    selected.sort_unstable();
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{create_signed_message, get_deterministic_signing_key};
+
+    fn commitment(bank: u64, amount: u64) -> SignedMessage {
+        create_signed_message(
+            &get_deterministic_signing_key(bank),
+            amount,
+            "DEAL123".into(),
+            "buyer123".into(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_fewest_commitments() {
+        let commitments = [commitment(0, 30), commitment(1, 70)];
+
+        let selected = select_commitments(&commitments, 60, &SelectionStrategy::Fewest).unwrap();
+        assert_eq!(selected, [1]);
+
+        let selected = select_commitments(&commitments, 80, &SelectionStrategy::Fewest).unwrap();
+        assert_eq!(selected, [0, 1]);
+    }
+
+    #[test]
+    fn test_skips_unusable_commitments() {
+        // An unregistered bank and a smaller duplicate from bank 0
+        let commitments = [commitment(0, 20), commitment(999, 100), commitment(0, 50)];
+
+        let selected = select_commitments(&commitments, 40, &SelectionStrategy::MostBanks).unwrap();
+        assert_eq!(selected, [2]);
+
+        assert_eq!(
+            select_commitments(&commitments, 60, &SelectionStrategy::Fewest),
+            Err(ValidationIssue::InsufficientAmount {
+                total: 50,
+                required: 60
+            })
+        );
+    }
+
+    #[test]
+    fn test_highest_rated() {
+        let commitments = [commitment(0, 70), commitment(1, 60)];
+        let strategy = SelectionStrategy::HighestRated {
+            ratings: HashMap::from([(1, 5)]),
+        };
+
+        assert_eq!(
+            select_commitments(&commitments, 60, &strategy).unwrap(),
+            [1]
+        );
+        assert_eq!(
+            select_commitments(&commitments, 60, &SelectionStrategy::MostBanks).unwrap(),
+            [0, 1]
+        );
+    }
+}
@@ -1,14 +1,11 @@
+use risc0_zkvm::ProverOpts;
 use serde::{Deserialize, Serialize};
 
 // Constants
 pub const SEED: u64 = 31337;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DealInfo {
-    pub amount: u64,
-    pub deal_id: String,
-    pub buyer: String,
-}
+// Shared with the verifier crate so both sides agree on the journal format
+pub use rwz_pof_verifier::{DealInfo, SignedRequirement};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SignedMessage {
@@ -17,6 +14,122 @@ pub struct SignedMessage {
     pub signature: Vec<u8>,
 }
 
+// A lending bank's signed request to withdraw one of its commitments,
+// identified by commitment hash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedWithdrawal {
+    pub pubkey: Vec<u8>,
+    pub commitment_id: String,
+    pub signature: Vec<u8>,
+}
+
+// Journal of the commitment guest: a signature that has been checked against the registry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifiedCommitment {
+    pub pubkey: Vec<u8>,
+    pub message: DealInfo,
+    pub nullifier: [u8; 32], // SHA-256 of ("rwz-pof-nullifier", pubkey, deal ID)
+}
+
+// Cycles spent in one stage of a guest, as reported on the guest's stdout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageCycles {
+    pub stage: String,
+    pub cycles: u64,
+}
+
+// Kind of receipt produced for the final proof, trading proving time for size
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReceiptKind {
+    // One STARK per segment, cheapest to prove and largest to ship
+    #[default]
+    Composite,
+    // Segments recursively compressed into a single STARK
+    Succinct,
+    // Succinct receipt wrapped in a Groth16 SNARK, smallest but needs the Groth16 prover
+    Groth16,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProofOptions {
+    #[serde(default)]
+    pub receipt_kind: ReceiptKind,
+    // Seller's Bank the proof is made for, committed to the journal
+    #[serde(default)]
+    pub recipient: Option<String>,
+    // Auction challenge nonce the proof answers, committed to the journal
+    #[serde(default)]
+    pub challenge: Option<String>,
+    // Seller's financing requirement, checked by the guest and committed by hash
+    #[serde(default)]
+    pub requirement: Option<SignedRequirement>,
+    // Journal bytes of the proof this one revises, committed by digest
+    #[serde(default)]
+    pub previous_journal: Option<Vec<u8>>,
+}
+
+impl ProofOptions {
+    pub fn prover_opts(&self) -> ProverOpts {
+        match self.receipt_kind {
+            ReceiptKind::Composite => ProverOpts::composite(),
+            ReceiptKind::Succinct => ProverOpts::succinct(),
+            ReceiptKind::Groth16 => ProverOpts::groth16(),
+        }
+    }
+}
+
+// Outcome of executing the guests without proving
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PreflightReport {
+    pub passed: bool,
+    pub failure: Option<String>, // Guest panic reason
+    pub cycles: u64,
+    pub segments: usize,
+    pub estimated_proving_secs: u64,
+}
+
+impl PreflightReport {
+    pub fn fail(mut self, reason: String) -> Self {
+        self.passed = false;
+        self.failure = Some(reason);
+        self
+    }
+}
+
+// A commitment problem that would make the guest reject the proof
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, thiserror::Error)]
+pub enum ValidationIssue {
+    #[error("No commitments provided")]
+    NoCommitments,
+
+    #[error("Commitment {index}: invalid signature")]
+    InvalidSignature { index: usize },
+
+    #[error("Commitment {index}: lending bank is not in the registry")]
+    UnknownBank { index: usize },
+
+    #[error("Commitment {index}: duplicate lending bank")]
+    DuplicateBank { index: usize },
+
+    #[error("Commitment {index}: deal ID {found} doesn't match {expected}")]
+    DealMismatch {
+        index: usize,
+        expected: String,
+        found: String,
+    },
+
+    #[error("Commitment {index}: buyer {found} doesn't match {expected}")]
+    BuyerMismatch {
+        index: usize,
+        expected: String,
+        found: String,
+    },
+
+    #[error("Total amount {total} less than required amount {required}")]
+    InsufficientAmount { total: u64, required: u64 },
+}
+
 // Add result type for error handling
 #[derive(Debug, thiserror::Error)]
 pub enum CoreError {
@@ -28,6 +141,12 @@ pub enum CoreError {
 
     #[error("RISC0 error: {0}")]
     Risc0Error(String),
+
+    #[error("Proof was aggregated from an unexpected commitment guest: {0}")]
+    CommitmentImageMismatch(String),
+
+    #[error(transparent)]
+    Verifier(#[from] rwz_pof_verifier::VerifierError),
 }
 
 pub type Result<T> = std::result::Result<T, CoreError>;
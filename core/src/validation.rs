@@ -0,0 +1,190 @@
+use k256::ecdsa::signature::Verifier;
+use k256::ecdsa::{Signature, VerifyingKey};
+
+use crate::engine::{commitment_hash, withdrawal_message};
+use crate::registry::is_registered;
+use crate::types::{SignedMessage, SignedWithdrawal, ValidationIssue};
+
+/// Checks commitments against the same rules the guests enforce, so problems
+/// are found before proving. Returns every issue found, empty if valid.
+///
+/// The total amount is only checked when `required_amount` is given.
+pub fn validate_commitments(
+    commitments: &[SignedMessage],
+    required_amount: Option<u64>,
+) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+
+    let Some(first) = commitments.first() else {
+        issues.push(ValidationIssue::NoCommitments);
+        return issues;
+    };
+
+    for (index, signed) in commitments.iter().enumerate() {
+        if !is_registered(&signed.pubkey) {
+            issues.push(ValidationIssue::UnknownBank { index });
+        }
+
+        if !signature_is_valid(signed) {
+            issues.push(ValidationIssue::InvalidSignature { index });
+        }
+
+        if commitments[..index]
+            .iter()
+            .any(|other| other.pubkey == signed.pubkey)
+        {
+            issues.push(ValidationIssue::DuplicateBank { index });
+        }
+
+        if signed.message.deal_id != first.message.deal_id {
+            issues.push(ValidationIssue::DealMismatch {
+                index,
+                expected: first.message.deal_id.clone(),
+                found: signed.message.deal_id.clone(),
+            });
+        }
+
+        if signed.message.buyer != first.message.buyer {
+            issues.push(ValidationIssue::BuyerMismatch {
+                index,
+                expected: first.message.buyer.clone(),
+                found: signed.message.buyer.clone(),
+            });
+        }
+    }
+
+    if let Some(required) = required_amount {
+        let total = commitments
+            .iter()
+            .fold(0u64, |total, c| total.saturating_add(c.message.amount));
+        if total < required {
+            issues.push(ValidationIssue::InsufficientAmount { total, required });
+        }
+    }
+
+    issues
+}
+
+/// Whether a withdrawal is for this commitment and signed by the bank that
+/// made it.
+pub fn withdrawal_is_valid(withdrawal: &SignedWithdrawal, commitment: &SignedMessage) -> bool {
+    if withdrawal.pubkey != commitment.pubkey {
+        return false;
+    }
+    if commitment_hash(commitment).ok().as_ref() != Some(&withdrawal.commitment_id) {
+        return false;
+    }
+    let Ok(message_bytes) = withdrawal_message(&withdrawal.commitment_id) else {
+        return false;
+    };
+
+    verify_signature(&withdrawal.pubkey, &message_bytes, &withdrawal.signature)
+}
+
+fn signature_is_valid(signed: &SignedMessage) -> bool {
+    let Ok(message_bytes) = bincode::serialize(&signed.message) else {
+        return false;
+    };
+
+    verify_signature(&signed.pubkey, &message_bytes, &signed.signature)
+}
+
+fn verify_signature(pubkey: &[u8], message_bytes: &[u8], signature: &[u8]) -> bool {
+    let Ok(verifying_key) = VerifyingKey::from_sec1_bytes(pubkey) else {
+        return false;
+    };
+    let Ok(signature) = Signature::from_slice(signature) else {
+        return false;
+    };
+
+    verifying_key.verify(message_bytes, &signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{
+        create_signed_message, create_signed_withdrawal, get_deterministic_signing_key,
+    };
+
+    fn commitment(bank: u64, amount: u64, deal_id: &str, buyer: &str) -> SignedMessage {
+        let key = get_deterministic_signing_key(bank);
+        create_signed_message(&key, amount, deal_id.into(), buyer.into()).unwrap()
+    }
+
+    #[test]
+    fn test_valid_commitments() {
+        let commitments = [
+            commitment(0, 50, "DEAL123", "buyer123"),
+            commitment(1, 30, "DEAL123", "buyer123"),
+        ];
+
+        assert!(validate_commitments(&commitments, Some(60)).is_empty());
+    }
+
+    #[test]
+    fn test_reports_every_issue() {
+        let mut tampered = commitment(1, 30, "DEAL123", "buyer123");
+        tampered.message.amount = 300;
+
+        let commitments = [
+            commitment(0, 50, "DEAL123", "buyer123"),
+            tampered,
+            commitment(0, 10, "DEAL123", "buyer123"),
+            commitment(5, 10, "DEAL999", "buyer999"),
+        ];
+
+        let issues = validate_commitments(&commitments, Some(1000));
+
+        assert_eq!(
+            issues,
+            vec![
+                ValidationIssue::InvalidSignature { index: 1 },
+                ValidationIssue::DuplicateBank { index: 2 },
+                ValidationIssue::UnknownBank { index: 3 },
+                ValidationIssue::DealMismatch {
+                    index: 3,
+                    expected: "DEAL123".into(),
+                    found: "DEAL999".into(),
+                },
+                ValidationIssue::BuyerMismatch {
+                    index: 3,
+                    expected: "buyer123".into(),
+                    found: "buyer999".into(),
+                },
+                ValidationIssue::InsufficientAmount {
+                    total: 370,
+                    required: 1000,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_commitments() {
+        assert_eq!(
+            validate_commitments(&[], None),
+            vec![ValidationIssue::NoCommitments]
+        );
+    }
+
+    #[test]
+    fn test_withdrawal() {
+        let signed = commitment(0, 50, "DEAL123", "buyer123");
+        let id = commitment_hash(&signed).unwrap();
+
+        let withdrawal = create_signed_withdrawal(&get_deterministic_signing_key(0), id.clone());
+        assert!(withdrawal_is_valid(&withdrawal.unwrap(), &signed));
+
+        // Another bank can't withdraw it, nor can a withdrawal of another commitment
+        let other_bank = create_signed_withdrawal(&get_deterministic_signing_key(1), id).unwrap();
+        assert!(!withdrawal_is_valid(&other_bank, &signed));
+
+        let other = commitment(0, 60, "DEAL123", "buyer123");
+        let withdrawal = create_signed_withdrawal(
+            &get_deterministic_signing_key(0),
+            commitment_hash(&other).unwrap(),
+        );
+        assert!(!withdrawal_is_valid(&withdrawal.unwrap(), &signed));
+    }
+}
@@ -1,6 +1,14 @@
 // src/lib/api.ts
 const API_BASE_URL = 'http://localhost:3030';
 
+// The server requires an API key on every route. The demo acts as every role,
+// so it needs an admin key (ADMIN_API_KEY on the server).
+const API_KEY = process.env.NEXT_PUBLIC_API_KEY ?? '';
+
+function headers(extra: Record<string, string> = {}): Record<string, string> {
+    return { Authorization: `Bearer ${API_KEY}`, ...extra };
+}
+
 export interface CommitmentRequest {
     bank_index: number;
     amount: number;
@@ -39,9 +47,9 @@ export const api = {
     async createCommitment(data: CommitmentRequest) {
         const response = await fetch(`${API_BASE_URL}/lb/commitment`, {
             method: 'POST',
-            headers: {
+            headers: headers({
                 'Content-Type': 'application/json',
-            },
+            }),
             body: JSON.stringify(data),
         });
 
@@ -53,9 +61,9 @@ export const api = {
     async generateProofAsync(data: ProofRequest) {
         const response = await fetch(`${API_BASE_URL}/proofs/async`, {
             method: 'POST',
-            headers: {
+            headers: headers({
                 'Content-Type': 'application/json',
-            },
+            }),
             body: JSON.stringify(data),
         });
 
@@ -65,7 +73,9 @@ export const api = {
 
     // Check proof status
     async checkProofStatus(jobId: string): Promise<AsyncJobResponse> {
-        const response = await fetch(`${API_BASE_URL}/proofs/async/${jobId}`);
+        const response = await fetch(`${API_BASE_URL}/proofs/async/${jobId}`, {
+            headers: headers(),
+        });
         if (!response.ok) throw new Error('Failed to check proof status');
         return response.json();
     },
@@ -74,9 +84,9 @@ export const api = {
     async verifyProof(deal_id: string): Promise<ProofResponse> {
         const response = await fetch(`${API_BASE_URL}/sb/verify`, {
             method: 'POST',
-            headers: {
+            headers: headers({
                 'Content-Type': 'application/json',
-            },
+            }),
             body: JSON.stringify({ deal_id }),
         });
 
@@ -88,9 +98,9 @@ export const api = {
     async generateProofSync(data: ProofRequest) {
         const response = await fetch(`${API_BASE_URL}/bb/proof`, {  // Note: using /bb/proof for sync
             method: 'POST',
-            headers: {
+            headers: headers({
                 'Content-Type': 'application/json',
-            },
+            }),
             body: JSON.stringify(data),
         });
 
@@ -100,7 +110,9 @@ export const api = {
 
     // Add job status check
     async checkJobStatus(jobId: string) {
-        const response = await fetch(`${API_BASE_URL}/proofs/async/${jobId}`);
+        const response = await fetch(`${API_BASE_URL}/proofs/async/${jobId}`, {
+            headers: headers(),
+        });
 
         if (!response.ok) throw new Error('Failed to check job status');
         return response.json();
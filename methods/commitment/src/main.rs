@@ -0,0 +1,251 @@
+use k256::ecdsa::signature::hazmat::PrehashVerifier;
+use k256::ecdsa::{Signature, SigningKey, VerifyingKey};
+use k256::SecretKey;
+use risc0_zkvm::guest::env;
+use rwz_pof_cycles::CycleTracker;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const SEED: u64 = 31337;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct DealInfo {
+    amount: u64,
+    deal_id: String,
+    buyer: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SignedMessage {
+    pubkey: Vec<u8>,
+    message: DealInfo,
+    signature: Vec<u8>,
+}
+
+// Committed to the journal so the aggregation guest can check it with `env::verify`
+#[derive(Debug, Serialize, Deserialize)]
+struct VerifiedCommitment {
+    pubkey: Vec<u8>,
+    message: DealInfo,
+    nullifier: [u8; 32],
+}
+
+const NULLIFIER_DOMAIN: &str = "rwz-pof-nullifier";
+
+fn generate_deterministic_pubkey(offset: u64) -> Vec<u8> {
+    let seed_bytes = (SEED.wrapping_add(offset)).to_le_bytes();
+    let mut key_bytes = [0u8; 32];
+    key_bytes[..8].copy_from_slice(&seed_bytes);
+
+    let secret_key = SecretKey::from_slice(&key_bytes).expect("Invalid key bytes");
+    VerifyingKey::from(SigningKey::from(secret_key))
+        .to_sec1_bytes()
+        .to_vec()
+}
+
+fn get_valid_pubkeys() -> [Vec<u8>; 2] {
+    [
+        generate_deterministic_pubkey(0), // LB1
+        generate_deterministic_pubkey(1), // LB2
+    ]
+}
+
+fn is_registered(pubkey: &[u8]) -> bool {
+    // Get valid pubkeys and check if the signature's pubkey is in our set
+    get_valid_pubkeys().iter().any(|valid| valid == pubkey)
+}
+
+// SHA-256 over the same bytes as the bincode encoding the host signs, without
+// serializing the message in the guest
+fn message_digest(message: &DealInfo) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(message.amount.to_le_bytes());
+    hasher.update((message.deal_id.len() as u64).to_le_bytes());
+    hasher.update(message.deal_id.as_bytes());
+    hasher.update((message.buyer.len() as u64).to_le_bytes());
+    hasher.update(message.buyer.as_bytes());
+    hasher.finalize().into()
+}
+
+// SHA-256 over the bincode encoding of `(NULLIFIER_DOMAIN, pubkey, deal_id)`.
+// It depends only on the bank and the deal, so the bank can't get a fresh one
+// by signing again. Anyone holding the registry can compute it, so it also
+// tells the seller which registered bank backs a proof.
+fn nullifier(pubkey: &[u8], deal_id: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update((NULLIFIER_DOMAIN.len() as u64).to_le_bytes());
+    hasher.update(NULLIFIER_DOMAIN.as_bytes());
+    hasher.update((pubkey.len() as u64).to_le_bytes());
+    hasher.update(pubkey);
+    hasher.update((deal_id.len() as u64).to_le_bytes());
+    hasher.update(deal_id.as_bytes());
+    hasher.finalize().into()
+}
+
+fn verify_signature(pubkey: &[u8], signature: &[u8], digest: &[u8; 32]) -> bool {
+    let verifying_key = VerifyingKey::from_sec1_bytes(pubkey).expect("Invalid public key format");
+
+    let signature = Signature::from_slice(signature).expect("Invalid signature format");
+
+    verifying_key.verify_prehash(digest, &signature).is_ok()
+}
+
+fn main() {
+    let mut cycles = CycleTracker::new();
+
+    // Read a single private commitment
+    let signed: SignedMessage = env::read();
+    cycles.stage("read");
+
+    assert!(is_registered(&signed.pubkey), "Unknown lending bank");
+    cycles.stage("registry");
+
+    let digest = message_digest(&signed.message);
+    cycles.stage("prehash");
+
+    assert!(
+        verify_signature(&signed.pubkey, &signed.signature, &digest),
+        "Commitment signature verification failed"
+    );
+    cycles.stage("verify_signature");
+
+    // Drop the signature, the pubkey, message and nullifier are all the
+    // aggregation guest needs
+    env::commit(&VerifiedCommitment {
+        nullifier: nullifier(&signed.pubkey, &signed.message.deal_id),
+        pubkey: signed.pubkey,
+        message: signed.message,
+    });
+    cycles.stage("commit");
+
+    cycles.report();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k256::ecdsa::signature::Signer;
+    use k256::ecdsa::SigningKey;
+    use rand_core::OsRng;
+
+    fn check_commitment(signed: &SignedMessage) -> bool {
+        is_registered(&signed.pubkey)
+            && verify_signature(
+                &signed.pubkey,
+                &signed.signature,
+                &message_digest(&signed.message),
+            )
+    }
+
+    fn get_test_signing_key(offset: u64) -> SigningKey {
+        let seed_bytes = (SEED.wrapping_add(offset)).to_le_bytes();
+        let mut key_bytes = [0u8; 32];
+        key_bytes[..8].copy_from_slice(&seed_bytes);
+
+        let secret_key = SecretKey::from_slice(&key_bytes).expect("Invalid key bytes");
+        SigningKey::from(secret_key)
+    }
+
+    fn create_test_signed_message(
+        signing_key: &SigningKey,
+        amount: u64,
+        deal_id: String,
+        buyer: String,
+    ) -> SignedMessage {
+        let deal_info = DealInfo {
+            amount,
+            deal_id,
+            buyer,
+        };
+
+        let message_bytes = bincode::serialize(&deal_info).unwrap();
+        let signature: Signature = signing_key.sign(&message_bytes);
+        let verifying_key = VerifyingKey::from(signing_key);
+
+        SignedMessage {
+            pubkey: verifying_key.to_sec1_bytes().to_vec(),
+            message: deal_info,
+            signature: signature.to_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let signing_key = get_test_signing_key(0);
+
+        let signed_msg = create_test_signed_message(
+            &signing_key,
+            500,
+            "DEAL001".to_string(),
+            "buyer1".to_string(),
+        );
+
+        assert!(check_commitment(&signed_msg));
+    }
+
+    #[test]
+    fn test_invalid_signature() {
+        let signing_key = get_test_signing_key(0);
+        let wrong_key = SigningKey::random(&mut OsRng);
+
+        let mut signed_msg = create_test_signed_message(
+            &signing_key,
+            500,
+            "DEAL001".to_string(),
+            "buyer1".to_string(),
+        );
+
+        // Replace pubkey with wrong one to make signature invalid
+        signed_msg.pubkey = VerifyingKey::from(&wrong_key).to_sec1_bytes().to_vec();
+
+        assert!(!check_commitment(&signed_msg));
+    }
+
+    #[test]
+    fn test_verify_invalid_signature() {
+        // Generate a key with different seed/offset
+        let invalid_key = get_test_signing_key(999);
+
+        let invalid_msg = create_test_signed_message(
+            &invalid_key,
+            500,
+            "DEAL001".to_string(),
+            "buyer1".to_string(),
+        );
+
+        assert!(!check_commitment(&invalid_msg));
+    }
+
+    #[test]
+    fn test_message_digest_matches_host_encoding() {
+        let deal_info = DealInfo {
+            amount: 500,
+            deal_id: "DEAL001".to_string(),
+            buyer: "buyer1".to_string(),
+        };
+
+        let host_digest: [u8; 32] = Sha256::digest(bincode::serialize(&deal_info).unwrap()).into();
+
+        assert_eq!(message_digest(&deal_info), host_digest);
+    }
+
+    #[test]
+    fn test_nullifier_is_per_bank_and_deal() {
+        let [lb1, lb2] = get_valid_pubkeys();
+
+        // The same whichever buyer the bank backs, or however often it signs
+        assert_eq!(nullifier(&lb1, "DEAL001"), nullifier(&lb1, "DEAL001"));
+        assert_ne!(nullifier(&lb1, "DEAL001"), nullifier(&lb2, "DEAL001"));
+        assert_ne!(nullifier(&lb1, "DEAL001"), nullifier(&lb1, "DEAL002"));
+    }
+
+    #[test]
+    fn test_nullifier_matches_host_encoding() {
+        let pubkey = generate_deterministic_pubkey(0);
+        let host_digest: [u8; 32] =
+            Sha256::digest(bincode::serialize(&(NULLIFIER_DOMAIN, &pubkey, "DEAL001")).unwrap())
+                .into();
+
+        assert_eq!(nullifier(&pubkey, "DEAL001"), host_digest);
+    }
+}
@@ -0,0 +1,43 @@
+use risc0_zkvm::guest::env;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+struct StageCycles {
+    stage: &'static str,
+    cycles: u64,
+}
+
+// Records cycles spent per stage, written to stdout for the host to pick up.
+// Shared by the guests, the host reads the report with `read_stage_cycles`.
+pub struct CycleTracker {
+    last: u64,
+    stages: Vec<StageCycles>,
+}
+
+impl CycleTracker {
+    pub fn new() -> Self {
+        Self {
+            last: env::cycle_count(),
+            stages: Vec::new(),
+        }
+    }
+
+    pub fn stage(&mut self, stage: &'static str) {
+        let now = env::cycle_count();
+        self.stages.push(StageCycles {
+            stage,
+            cycles: now - self.last,
+        });
+        self.last = now;
+    }
+
+    pub fn report(&self) {
+        env::write(&self.stages);
+    }
+}
+
+impl Default for CycleTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,94 +1,133 @@
-use k256::ecdsa::signature::Verifier;
-use k256::ecdsa::{Signature, SigningKey, VerifyingKey};
-use k256::{elliptic_curve::generic_array::GenericArray, SecretKey};
+use k256::ecdsa::signature::hazmat::PrehashVerifier;
+use k256::ecdsa::{Signature, VerifyingKey};
 use risc0_zkvm::guest::env;
+use risc0_zkvm::serde::{from_slice, to_vec};
+use rwz_pof_cycles::CycleTracker;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-const SEED: u64 = 31337;
-
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 struct DealInfo {
     amount: u64,
     deal_id: String,
     buyer: String,
 }
 
+// Journal of the commitment guest, one per lending bank
 #[derive(Debug, Serialize, Deserialize)]
-struct SignedMessage {
+struct VerifiedCommitment {
     pubkey: Vec<u8>,
     message: DealInfo,
-    signature: Vec<u8>,
+    nullifier: [u8; 32],
 }
 
-fn generate_deterministic_pubkey(offset: u64) -> Vec<u8> {
-    let seed_bytes = (SEED.wrapping_add(offset)).to_le_bytes();
-    let mut key_bytes = [0u8; 32];
-    key_bytes[..8].copy_from_slice(&seed_bytes);
+// The financing a seller requires for an auction and deal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FinancingRequirement {
+    auction_id: String,
+    deal_id: String,
+    amount: u64,
+}
+
+// Signed by the Seller's Bank over the bincode-encoded requirement
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SignedRequirement {
+    pubkey: Vec<u8>,
+    requirement: FinancingRequirement,
+    signature: Vec<u8>,
+}
 
-    let generic_bytes = GenericArray::from_slice(&key_bytes);
-    let secret_key = SecretKey::from_bytes(generic_bytes).expect("Invalid key bytes");
-    VerifyingKey::from(SigningKey::from(secret_key))
-        .to_sec1_bytes()
-        .to_vec()
+// Public output, decoded by verifiers as `PofJournal`
+#[derive(Debug, Serialize, Deserialize)]
+struct PofJournal {
+    deal_info: DealInfo,
+    verified_amount: u64,
+    commitment_image_id: [u32; 8],
+    bank_count: u32,
+    recipient: Option<String>,
+    challenge: Option<String>,
+    nullifiers: Vec<[u8; 32]>,
+    requirement_hash: Option<[u8; 32]>,
+    previous_journal_digest: Option<[u8; 32]>,
 }
 
-fn get_valid_pubkeys() -> [Vec<u8>; 2] {
-    [
-        generate_deterministic_pubkey(0), // LB1
-        generate_deterministic_pubkey(1), // LB2
-    ]
+// The bincode encoding of a requirement, without serializing it in the guest
+fn requirement_bytes(requirement: &FinancingRequirement) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend((requirement.auction_id.len() as u64).to_le_bytes());
+    bytes.extend(requirement.auction_id.as_bytes());
+    bytes.extend((requirement.deal_id.len() as u64).to_le_bytes());
+    bytes.extend(requirement.deal_id.as_bytes());
+    bytes.extend(requirement.amount.to_le_bytes());
+    bytes
 }
 
-fn verify_signature(signed: &SignedMessage) -> bool {
-    // Get valid pubkeys and check if the signature's pubkey is in our set
-    let valid_pubkeys = get_valid_pubkeys();
-    if !valid_pubkeys.contains(&signed.pubkey) {
-        return false;
-    }
+// Checks the seller's signature and returns the hash verifiers compare with
+// `SignedRequirement::hash`, over the bincode encoding of `(pubkey, requirement)`
+fn verify_requirement(signed: &SignedRequirement) -> [u8; 32] {
+    let bytes = requirement_bytes(&signed.requirement);
 
     let verifying_key =
-        VerifyingKey::from_sec1_bytes(&signed.pubkey).expect("Invalid public key format");
-
+        VerifyingKey::from_sec1_bytes(&signed.pubkey).expect("Invalid seller key format");
     let signature = Signature::from_slice(&signed.signature).expect("Invalid signature format");
-
-    let message_bytes = bincode::serialize(&signed.message).expect("Failed to serialize message");
-
-    verifying_key.verify(&message_bytes, &signature).is_ok()
+    let digest: [u8; 32] = Sha256::digest(&bytes).into();
+    verifying_key
+        .verify_prehash(&digest, &signature)
+        .expect("Invalid financing requirement signature");
+
+    let mut hasher = Sha256::new();
+    hasher.update((signed.pubkey.len() as u64).to_le_bytes());
+    hasher.update(&signed.pubkey);
+    hasher.update(&bytes);
+    hasher.finalize().into()
 }
 
 fn main() {
-    let start = env::cycle_count();
+    let mut cycles = CycleTracker::new();
 
     // Read private inputs
-    let lb1_signed: SignedMessage = env::read();
-    let lb2_signed: SignedMessage = env::read();
+    let commitment_image_id: [u32; 8] = env::read();
+    let commitments: Vec<VerifiedCommitment> = env::read();
     let proof_amount: u64 = env::read();
-
-    // Verify both signatures
-    assert!(
-        verify_signature(&lb1_signed),
-        "LB1 signature verification failed"
-    );
-    assert!(
-        verify_signature(&lb2_signed),
-        "LB2 signature verification failed"
-    );
+    let recipient: Option<String> = env::read();
+    let challenge: Option<String> = env::read();
+    let requirement: Option<SignedRequirement> = env::read();
+    let previous_journal: Option<Vec<u8>> = env::read();
+    cycles.stage("read");
+
+    assert!(!commitments.is_empty(), "No commitments provided");
+
+    // Each commitment must come with a receipt from the commitment guest, which
+    // has already checked the signature and registry membership. The receipts
+    // are added as assumptions by the host and resolved by the prover.
+    for commitment in &commitments {
+        let journal = to_vec(commitment).expect("Failed to serialize commitment");
+        env::verify(commitment_image_id, &journal).expect("Commitment receipt verification failed");
+    }
+    cycles.stage("verify_receipts");
 
     // Verify public keys are unique so that signatures cannot be repeated
-    assert!(lb1_signed.pubkey != lb2_signed.pubkey);
+    for (i, commitment) in commitments.iter().enumerate() {
+        assert!(
+            commitments[..i]
+                .iter()
+                .all(|other| other.pubkey != commitment.pubkey),
+            "Duplicate lending bank"
+        );
+    }
 
-    // Verify both messages refer to the same deal
-    assert_eq!(
-        lb1_signed.message.deal_id, lb2_signed.message.deal_id,
-        "Deal IDs don't match"
-    );
-    assert_eq!(
-        lb1_signed.message.buyer, lb2_signed.message.buyer,
-        "Buyers don't match"
-    );
+    // Verify all messages refer to the same deal
+    let first = &commitments[0].message;
+    for commitment in &commitments[1..] {
+        assert_eq!(
+            first.deal_id, commitment.message.deal_id,
+            "Deal IDs don't match"
+        );
+        assert_eq!(first.buyer, commitment.message.buyer, "Buyers don't match");
+    }
 
     // Verify total amount meets proof requirement
-    let total_amount = lb1_signed.message.amount + lb2_signed.message.amount;
+    let total_amount: u64 = commitments.iter().map(|c| c.message.amount).sum();
     assert!(
         total_amount >= proof_amount,
         "Total amount {} less than required amount {}",
@@ -96,102 +135,73 @@ fn main() {
         proof_amount
     );
 
-    // Create minimal verification info
-    let verification_info = DealInfo {
-        amount: proof_amount, // Only show required amount (60), not total (80)
-        deal_id: lb1_signed.message.deal_id.clone(),
-        buyer: lb1_signed.message.buyer.clone(),
-    };
-
-    env::commit(&(verification_info, proof_amount));
-
-    let end = env::cycle_count();
-    eprintln!("total_cycle_count: {}", end - start);
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use k256::ecdsa::SigningKey;
-    use rand_core::OsRng;
-
-    fn get_test_signing_key(offset: u64) -> SigningKey {
-        let seed_bytes = (SEED.wrapping_add(offset)).to_le_bytes();
-        let mut key_bytes = [0u8; 32];
-        key_bytes[..8].copy_from_slice(&seed_bytes);
-
-        let generic_bytes = GenericArray::from_slice(&key_bytes);
-        let secret_key = SecretKey::from_bytes(generic_bytes).expect("Invalid key bytes");
-        SigningKey::from(secret_key)
-    }
-
-    fn create_test_signed_message(
-        signing_key: &SigningKey,
-        amount: u64,
-        deal_id: String,
-        buyer: String,
-    ) -> SignedMessage {
-        let deal_info = DealInfo {
-            amount,
-            deal_id,
-            buyer,
-        };
-
-        let message_bytes = bincode::serialize(&deal_info).unwrap();
-        let signature = signing_key.sign(&message_bytes);
-        let verifying_key = VerifyingKey::from(signing_key);
-
-        SignedMessage {
-            pubkey: verifying_key.to_sec1_bytes().to_vec(),
-            message: deal_info,
-            signature: signature.to_bytes().to_vec(),
-        }
-    }
-
-    #[test]
-    fn test_verify_signature() {
-        let signing_key = SigningKey::random(&mut OsRng);
-
-        let signed_msg = create_test_signed_message(
-            &signing_key,
-            500,
-            "DEAL001".to_string(),
-            "buyer1".to_string(),
+    // A seller's requirement sets the minimum, so the buyer can't prove less
+    // than the seller asked for but can bid more
+    let requirement_hash = requirement.as_ref().map(|signed| {
+        assert_eq!(
+            signed.requirement.deal_id, first.deal_id,
+            "Requirement is for another deal"
         );
-
-        assert!(verify_signature(&signed_msg));
-    }
-
-    #[test]
-    fn test_invalid_signature() {
-        let signing_key = SigningKey::random(&mut OsRng);
-        let wrong_key = SigningKey::random(&mut OsRng);
-
-        let mut signed_msg = create_test_signed_message(
-            &signing_key,
-            500,
-            "DEAL001".to_string(),
-            "buyer1".to_string(),
+        assert!(
+            proof_amount >= signed.requirement.amount,
+            "Proof amount is below the seller's requirement"
         );
+        verify_requirement(signed)
+    });
+
+    // A revision raises an earlier bid of the same buyer for the same deal.
+    // Verifiers match the committed digest against the proof they already hold.
+    let previous_journal_digest = previous_journal.as_ref().map(|bytes| {
+        let previous: PofJournal = from_slice(bytes).expect("Invalid previous journal");
+        assert_eq!(
+            previous.deal_info.deal_id, first.deal_id,
+            "Previous proof is for another deal"
+        );
+        assert_eq!(
+            previous.deal_info.buyer, first.buyer,
+            "Previous proof is from another buyer"
+        );
+        assert!(
+            proof_amount > previous.verified_amount,
+            "Revised amount {} doesn't exceed {}",
+            proof_amount,
+            previous.verified_amount
+        );
+        Sha256::digest(bytes).into()
+    });
 
-        // Replace pubkey with wrong one to make signature invalid
-        signed_msg.pubkey = VerifyingKey::from(&wrong_key).to_sec1_bytes().to_vec();
-
-        assert!(!verify_signature(&signed_msg));
-    }
+    // Nullifiers are sorted so their order says nothing about the commitments
+    let mut nullifiers: Vec<[u8; 32]> = commitments.iter().map(|c| c.nullifier).collect();
+    nullifiers.sort_unstable();
 
-    #[test]
-    fn test_verify_invalid_signature() {
-        // Generate a key with different seed/offset
-        let invalid_key = get_test_signing_key(999);
+    cycles.stage("checks");
 
-        let invalid_msg = create_test_signed_message(
-            &invalid_key,
-            500,
-            "DEAL001".to_string(),
-            "buyer1".to_string(),
-        );
+    // Create minimal verification info
+    let verification_info = DealInfo {
+        amount: proof_amount, // Only show required amount (60), not total (80)
+        deal_id: first.deal_id.clone(),
+        buyer: first.buyer.clone(),
+    };
 
-        assert!(!verify_signature(&invalid_msg));
-    }
+    // The commitment image ID is committed so verifiers can check which guest
+    // vouched for the signatures, the recipient so a proof made for one
+    // Seller's Bank can't be replayed to another, the challenge so it can't be
+    // replayed within an auction, and the nullifiers so a seller can spot a
+    // bank backing several bidders for the same deal. The requirement hash
+    // shows the amount is the seller's own, and the previous journal digest
+    // chains a revision to the bid it raises.
+    env::commit(&PofJournal {
+        deal_info: verification_info,
+        verified_amount: proof_amount,
+        commitment_image_id,
+        bank_count: commitments.len() as u32,
+        recipient,
+        challenge,
+        nullifiers,
+        requirement_hash,
+        previous_journal_digest,
+    });
+    cycles.stage("commit");
+
+    cycles.report();
 }
@@ -0,0 +1,506 @@
+use k256::ecdsa::SigningKey;
+use rwz_pof_core::{FinancingRequirement, SignedRequirement, VerifiedDeal};
+use serde::{Deserialize, Serialize};
+use time::serde::iso8601;
+use time::OffsetDateTime;
+
+// A seller-run auction: buyers' banks submit proof bundles against the
+// seller's own requirements, and the seller ranks the verified bids
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Auction {
+    pub id: String,
+    pub deal_id: String, // Deal ID the bidders' commitments are made for
+    pub seller: String,
+    pub min_amount: u64,       // Minimum financing a bid must prove
+    pub registry_root: String, // Accepted lending bank registry
+    // Signed by the seller's bank for `min_amount`, which bids must prove against
+    pub requirement: SignedRequirement,
+    pub requirement_hash: String, // Hex, as committed to bid journals
+    #[serde(with = "iso8601")]
+    pub deadline: OffsetDateTime,
+    pub challenges: Vec<Challenge>,
+    pub bids: Vec<Bid>, // Every submission, in arrival order
+    #[serde(with = "iso8601")]
+    pub created_at: OffsetDateTime,
+}
+
+// Nonce issued to one invited bidder, which its proof must commit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Challenge {
+    pub buyer: String,
+    pub nonce: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BidStatus {
+    Verified,
+    Rejected,
+}
+
+// A submitted bundle and the outcome of verifying it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bid {
+    pub id: String,
+    pub status: BidStatus,
+    pub error: Option<String>, // Why it was rejected
+    pub buyer: Option<String>, // From the journal, if the receipt verified
+    pub verified_amount: Option<u64>,
+    pub bank_count: Option<u32>,
+    pub guest_version: Option<String>,
+    pub challenge: Option<String>,
+    pub nullifiers: Vec<String>, // Hex
+    #[serde(with = "iso8601")]
+    pub submitted_at: OffsetDateTime,
+}
+
+// A verified bid in the seller's ranking
+#[derive(Debug, Clone, Serialize)]
+pub struct RankedBid {
+    pub rank: usize,
+    pub bid_id: String,
+    pub buyer: String,
+    pub verified_amount: u64,
+    pub bank_count: u32,
+    // Verified bids of other buyers backed by a lending bank this bid also uses
+    pub conflicting_bids: Vec<String>,
+    pub shared_nullifiers: Vec<String>,
+}
+
+impl Auction {
+    // One challenge nonce is issued per invited bidder, and the seller's bank
+    // signs the financing requirement
+    pub fn new(
+        deal_id: String,
+        seller: String,
+        min_amount: u64,
+        registry_root: String,
+        deadline: OffsetDateTime,
+        bidders: Vec<String>,
+        seller_key: &SigningKey,
+    ) -> Result<Self, String> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let requirement = SignedRequirement::sign(
+            seller_key,
+            FinancingRequirement {
+                auction_id: id.clone(),
+                deal_id: deal_id.clone(),
+                amount: min_amount,
+            },
+        )
+        .map_err(|e| e.to_string())?;
+        let requirement_hash = hex::encode(requirement.hash().map_err(|e| e.to_string())?);
+
+        Ok(Self {
+            id,
+            deal_id,
+            seller,
+            min_amount,
+            registry_root,
+            requirement,
+            requirement_hash,
+            deadline,
+            challenges: bidders
+                .into_iter()
+                .map(|buyer| Challenge {
+                    buyer,
+                    nonce: uuid::Uuid::new_v4().to_string(),
+                })
+                .collect(),
+            bids: Vec::new(),
+            created_at: OffsetDateTime::now_utc(),
+        })
+    }
+
+    pub fn is_open(&self, now: OffsetDateTime) -> bool {
+        now < self.deadline
+    }
+
+    // Checks a verified proof against the auction's own requirements
+    pub fn check_bid(&self, deal: &VerifiedDeal) -> Result<(), String> {
+        if deal.deal_info.deal_id != self.deal_id {
+            return Err(format!(
+                "Proof is for deal {}, not {}",
+                deal.deal_info.deal_id, self.deal_id
+            ));
+        }
+        if deal.requirement_hash.map(hex::encode).as_ref() != Some(&self.requirement_hash) {
+            return Err(format!(
+                "Proof wasn't made against the auction's requirement {}",
+                self.requirement_hash
+            ));
+        }
+        if deal.verified_amount < self.min_amount {
+            return Err(format!(
+                "Verified amount {} is below the minimum {}",
+                deal.verified_amount, self.min_amount
+            ));
+        }
+
+        let Some(nonce) = &deal.challenge else {
+            return Err("Proof doesn't answer a challenge".to_string());
+        };
+        if !self
+            .challenges
+            .iter()
+            .any(|challenge| challenge.nonce == *nonce && challenge.buyer == deal.deal_info.buyer)
+        {
+            return Err(format!(
+                "Challenge {} wasn't issued to {}",
+                nonce, deal.deal_info.buyer
+            ));
+        }
+        if self
+            .verified_bids()
+            .any(|bid| bid.challenge.as_ref() == Some(nonce))
+        {
+            return Err(format!("Challenge {} was already answered", nonce));
+        }
+        Ok(())
+    }
+
+    // What a bidder may see: the auction with only its own challenge and bids
+    pub fn bidder_view(&self, buyer: Option<&str>) -> Auction {
+        let own = |party: Option<&str>| buyer.is_some() && party == buyer;
+        Auction {
+            challenges: self
+                .challenges
+                .iter()
+                .filter(|challenge| own(Some(&challenge.buyer)))
+                .cloned()
+                .collect(),
+            bids: self
+                .bids
+                .iter()
+                .filter(|bid| own(bid.buyer.as_deref()))
+                .cloned()
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    pub fn verified_bids(&self) -> impl Iterator<Item = &Bid> {
+        self.bids
+            .iter()
+            .filter(|bid| bid.status == BidStatus::Verified)
+    }
+
+    // Verified bids, highest amount first and earliest first on a tie
+    pub fn ranking(&self) -> Vec<RankedBid> {
+        let mut bids: Vec<_> = self.verified_bids().collect();
+        bids.sort_by_key(|bid| {
+            (
+                std::cmp::Reverse(bid.verified_amount.unwrap_or_default()),
+                bid.submitted_at,
+            )
+        });
+
+        bids.iter()
+            .enumerate()
+            .map(|(index, bid)| {
+                let mut conflicting_bids = Vec::new();
+                let mut shared_nullifiers = Vec::new();
+                for other in self
+                    .verified_bids()
+                    .filter(|other| other.buyer != bid.buyer)
+                {
+                    let shared: Vec<_> = bid
+                        .nullifiers
+                        .iter()
+                        .filter(|nullifier| other.nullifiers.contains(nullifier))
+                        .collect();
+                    if !shared.is_empty() {
+                        conflicting_bids.push(other.id.clone());
+                    }
+                    for nullifier in shared {
+                        if !shared_nullifiers.contains(nullifier) {
+                            shared_nullifiers.push(nullifier.clone());
+                        }
+                    }
+                }
+
+                RankedBid {
+                    rank: index + 1,
+                    bid_id: bid.id.clone(),
+                    buyer: bid.buyer.clone().unwrap_or_default(),
+                    verified_amount: bid.verified_amount.unwrap_or_default(),
+                    bank_count: bid.bank_count.unwrap_or_default(),
+                    conflicting_bids,
+                    shared_nullifiers,
+                }
+            })
+            .collect()
+    }
+
+    // Verification results as CSV, one row per submission
+    pub fn to_csv(&self) -> String {
+        let ranking = self.ranking();
+        let mut csv = String::from(
+            "bid_id,status,rank,buyer,verified_amount,bank_count,guest_version,challenge,conflicting_bids,error,submitted_at\n",
+        );
+        for bid in &self.bids {
+            let ranked = ranking.iter().find(|ranked| ranked.bid_id == bid.id);
+            let row = [
+                bid.id.clone(),
+                format!("{:?}", bid.status).to_lowercase(),
+                ranked.map(|r| r.rank.to_string()).unwrap_or_default(),
+                bid.buyer.clone().unwrap_or_default(),
+                bid.verified_amount
+                    .map(|amount| amount.to_string())
+                    .unwrap_or_default(),
+                bid.bank_count
+                    .map(|count| count.to_string())
+                    .unwrap_or_default(),
+                bid.guest_version.clone().unwrap_or_default(),
+                bid.challenge.clone().unwrap_or_default(),
+                ranked
+                    .map(|r| r.conflicting_bids.join(" "))
+                    .unwrap_or_default(),
+                bid.error.clone().unwrap_or_default(),
+                bid.submitted_at.to_string(),
+            ];
+            let row: Vec<_> = row.iter().map(|field| csv_field(field)).collect();
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+impl Bid {
+    pub fn verified(deal: &VerifiedDeal) -> Self {
+        Self {
+            status: BidStatus::Verified,
+            error: None,
+            buyer: Some(deal.deal_info.buyer.clone()),
+            verified_amount: Some(deal.verified_amount),
+            bank_count: Some(deal.bank_count),
+            guest_version: Some(deal.guest_version.clone()),
+            challenge: deal.challenge.clone(),
+            nullifiers: deal.nullifiers.iter().map(hex::encode).collect(),
+            ..Self::rejected(String::new())
+        }
+    }
+
+    pub fn rejected(error: String) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            status: BidStatus::Rejected,
+            error: Some(error),
+            buyer: None,
+            verified_amount: None,
+            bank_count: None,
+            guest_version: None,
+            challenge: None,
+            nullifiers: Vec::new(),
+            submitted_at: OffsetDateTime::now_utc(),
+        }
+    }
+
+    // A bid that verified but doesn't meet the auction's requirements keeps
+    // what was learned from its proof
+    pub fn with_rejection(mut self, error: String) -> Self {
+        self.status = BidStatus::Rejected;
+        self.error = Some(error);
+        self
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rwz_pof_core::{get_deterministic_signing_key, DealInfo};
+    use time::Duration;
+
+    fn auction() -> Auction {
+        Auction::new(
+            "DEAL1".to_string(),
+            "seller123".to_string(),
+            100,
+            "root".to_string(),
+            OffsetDateTime::now_utc() + Duration::hours(1),
+            vec!["buyer1".to_string(), "buyer2".to_string()],
+            &get_deterministic_signing_key(0),
+        )
+        .unwrap()
+    }
+
+    fn nonce(auction: &Auction, buyer: &str) -> String {
+        auction
+            .challenges
+            .iter()
+            .find(|challenge| challenge.buyer == buyer)
+            .map(|challenge| challenge.nonce.clone())
+            .unwrap()
+    }
+
+    fn verified(auction: &Auction, buyer: &str, amount: u64, nullifiers: &[u8]) -> VerifiedDeal {
+        let mut requirement_hash = [0u8; 32];
+        hex::decode_to_slice(&auction.requirement_hash, &mut requirement_hash).unwrap();
+        VerifiedDeal {
+            deal_info: DealInfo {
+                amount,
+                deal_id: auction.deal_id.clone(),
+                buyer: buyer.to_string(),
+            },
+            verified_amount: amount,
+            bank_count: nullifiers.len() as u32,
+            recipient: None,
+            challenge: Some(nonce(auction, buyer)),
+            nullifiers: nullifiers.iter().map(|byte| [*byte; 32]).collect(),
+            requirement_hash: Some(requirement_hash),
+            journal_digest: [0; 32],
+            previous_journal_digest: None,
+            image_id: [0; 8],
+            guest_version: "v6".to_string(),
+            registry_root: None,
+            created_at: None,
+            signer: None,
+        }
+    }
+
+    #[test]
+    fn test_check_bid() {
+        let mut auction = auction();
+        let bid = verified(&auction, "buyer1", 120, &[1]);
+        assert_eq!(auction.check_bid(&bid), Ok(()));
+
+        let mut low = bid.clone();
+        low.verified_amount = 90;
+        assert!(auction.check_bid(&low).is_err());
+        let mut unrequired = bid.clone();
+        unrequired.requirement_hash = None;
+        assert!(auction.check_bid(&unrequired).is_err());
+
+        // Another invited bidder's nonce, or none at all
+        let mut foreign = verified(&auction, "buyer2", 120, &[2]);
+        foreign.challenge = bid.challenge.clone();
+        assert!(auction.check_bid(&foreign).is_err());
+        let mut unanswered = bid.clone();
+        unanswered.challenge = None;
+        assert!(auction.check_bid(&unanswered).is_err());
+
+        // A challenge is answered once, rejected bids don't use it up
+        auction
+            .bids
+            .push(Bid::verified(&bid).with_rejection("Rejected".to_string()));
+        assert_eq!(auction.check_bid(&bid), Ok(()));
+        auction.bids.push(Bid::verified(&bid));
+        let replay = auction.check_bid(&bid).unwrap_err();
+        assert!(replay.contains("already answered"), "{}", replay);
+    }
+
+    #[test]
+    fn test_bids_above_the_minimum_rank_by_amount() {
+        let mut auction = auction();
+        let now = OffsetDateTime::now_utc();
+        for (buyer, amount, seconds) in [("buyer1", 120, 1), ("buyer2", 180, 2)] {
+            let bid = verified(&auction, buyer, amount, &[amount as u8]);
+            assert_eq!(auction.check_bid(&bid), Ok(()));
+            let mut bid = Bid::verified(&bid);
+            bid.submitted_at = now + Duration::seconds(seconds);
+            auction.bids.push(bid);
+        }
+
+        // The later, higher bid ranks first
+        let ranking = auction.ranking();
+        assert_eq!(ranking[0].buyer, "buyer2");
+        assert_eq!(ranking[0].verified_amount, 180);
+        assert_eq!(ranking[1].verified_amount, 120);
+    }
+
+    #[test]
+    fn test_bidder_view() {
+        let mut auction = auction();
+        auction
+            .bids
+            .push(Bid::verified(&verified(&auction, "buyer1", 120, &[1])));
+        auction
+            .bids
+            .push(Bid::verified(&verified(&auction, "buyer2", 150, &[2])));
+        auction
+            .bids
+            .push(Bid::rejected("Invalid receipt".to_string()));
+
+        let view = auction.bidder_view(Some("buyer1"));
+        assert_eq!(view.bids.len(), 1);
+        assert_eq!(view.bids[0].buyer.as_deref(), Some("buyer1"));
+        assert_eq!(view.challenges.len(), 1);
+        assert_eq!(view.challenges[0].nonce, nonce(&auction, "buyer1"));
+
+        let view = auction.bidder_view(None);
+        assert!(view.bids.is_empty() && view.challenges.is_empty());
+        assert_eq!(view.requirement_hash, auction.requirement_hash);
+    }
+
+    #[test]
+    fn test_ranking() {
+        let mut auction = auction();
+        let now = OffsetDateTime::now_utc();
+        for (buyer, amount, nullifiers, seconds) in [
+            ("buyer1", 150, &[1, 2][..], 2),
+            ("buyer2", 150, &[2][..], 1),
+            ("buyer3", 200, &[3][..], 3),
+        ] {
+            let mut bid = Bid::verified(&verified(&auction, "buyer1", amount, nullifiers));
+            bid.buyer = Some(buyer.to_string());
+            bid.submitted_at = now + Duration::seconds(seconds);
+            auction.bids.push(bid);
+        }
+        auction
+            .bids
+            .push(Bid::rejected("Invalid receipt".to_string()));
+
+        // Highest first, the earlier bid first on a tie
+        let ranking = auction.ranking();
+        let buyers: Vec<_> = ranking.iter().map(|bid| bid.buyer.as_str()).collect();
+        assert_eq!(buyers, ["buyer3", "buyer2", "buyer1"]);
+        assert_eq!(
+            ranking.iter().map(|bid| bid.rank).collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+
+        // buyer1 and buyer2 are both backed by the bank behind nullifier 2
+        let shared = hex::encode([2u8; 32]);
+        assert_eq!(
+            ranking[1].conflicting_bids,
+            vec![auction.bids[0].id.clone()]
+        );
+        assert_eq!(ranking[1].shared_nullifiers, vec![shared.clone()]);
+        assert_eq!(
+            ranking[2].conflicting_bids,
+            vec![auction.bids[1].id.clone()]
+        );
+        assert_eq!(ranking[2].shared_nullifiers, vec![shared]);
+        assert!(ranking[0].conflicting_bids.is_empty());
+    }
+
+    #[test]
+    fn test_csv_escaping() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+
+        let mut auction = auction();
+        auction
+            .bids
+            .push(Bid::rejected("Bad \"bundle\", truncated".to_string()));
+        let csv = auction.to_csv();
+        let row = csv.lines().nth(1).unwrap();
+        assert!(row.contains(",rejected,,"), "{}", row);
+        assert!(
+            row.contains(",\"Bad \"\"bundle\"\", truncated\","),
+            "{}",
+            row
+        );
+    }
+}
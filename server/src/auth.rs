@@ -0,0 +1,416 @@
+use crate::jwt::JwtValidator;
+use crate::storage::Storage;
+use crate::tls::ClientIdentity;
+use rand_core::{OsRng, RngCore};
+use risc0_zkvm::sha::{Impl, Sha256};
+use rwz_pof_core::registry_pubkeys;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use time::serde::iso8601;
+use time::OffsetDateTime;
+use warp::{Filter, Rejection};
+
+// Prefix of issued keys, so they are recognisable in configs and logs
+const KEY_PREFIX: &str = "rwz_";
+
+// What an API key may do
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "role", rename_all = "snake_case")]
+pub enum Role {
+    LendingBank { bank_index: u64 }, // Acts only as this registry bank
+    BuyersBank { buyer: String },    // Acts only for this deal buyer
+    SellersBank { seller: String },  // Acts only for this deal seller
+    Admin,                           // Everything, including managing keys
+}
+
+// The role a route requires
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Authenticated, // Any valid key
+    LendingBank,
+    BuyersBank,
+    SellersBank,
+    Admin,
+}
+
+impl Role {
+    pub fn allows(&self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (Role::Admin, _)
+                | (_, Access::Authenticated)
+                | (Role::LendingBank { .. }, Access::LendingBank)
+                | (Role::BuyersBank { .. }, Access::BuyersBank)
+                | (Role::SellersBank { .. }, Access::SellersBank)
+        )
+    }
+
+    // Whether the caller may act as the registry bank with this index
+    pub fn is_bank(&self, bank_index: u64) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::LendingBank { bank_index: own } => *own == bank_index,
+            _ => false,
+        }
+    }
+
+    // Whether the caller may act for this deal buyer
+    pub fn is_buyer(&self, buyer: &str) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::BuyersBank { buyer: own } => own == buyer,
+            _ => false,
+        }
+    }
+
+    // Whether the caller may act for this deal seller
+    pub fn is_seller(&self, seller: &str) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::SellersBank { seller: own } => own == seller,
+            _ => false,
+        }
+    }
+
+    // Whether the caller may act as the registry bank with this public key
+    pub fn is_bank_key(&self, pubkey: &[u8]) -> bool {
+        registry_pubkeys()
+            .iter()
+            .position(|key| key == pubkey)
+            .is_some_and(|index| self.is_bank(index as u64))
+    }
+}
+
+// Who made a request, from an API key or a bearer JWT
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub subject: String, // API key ID, or the token's issuer and subject
+    pub role: Role,
+}
+
+impl Principal {
+    // The registry bank the caller acts as. A lending bank always acts as its
+    // own; only admins pick one, with `requested`.
+    pub fn bank_index(&self, requested: Option<u64>) -> Result<u64, String> {
+        match (&self.role, requested) {
+            (Role::LendingBank { bank_index }, None) => Ok(*bank_index),
+            (Role::LendingBank { bank_index }, Some(requested)) if requested == *bank_index => {
+                Ok(requested)
+            }
+            (Role::Admin, Some(requested)) => Ok(requested),
+            (Role::Admin, None) => Err("Admins must give a bank_index".to_string()),
+            (_, Some(requested)) => Err(format!("This principal can't act as bank {}", requested)),
+            (_, None) => Err("This principal isn't a lending bank".to_string()),
+        }
+    }
+}
+
+// Checks bearer credentials: JWTs against the identity provider's JWKS when
+// one is configured, anything else as an API key
+pub struct Authenticator {
+    storage: Arc<Mutex<Storage>>,
+    jwt: Option<JwtValidator>,
+}
+
+impl Authenticator {
+    pub fn new(storage: Arc<Mutex<Storage>>, jwt: Option<JwtValidator>) -> Self {
+        Self { storage, jwt }
+    }
+
+    async fn authenticate(&self, credential: &str) -> Result<Principal, AuthError> {
+        if let Some(jwt) = self.jwt.as_ref().filter(|_| is_jwt(credential)) {
+            return jwt.validate(credential).await;
+        }
+
+        self.storage
+            .lock()
+            .unwrap()
+            .find_api_key(&hash_key(credential))
+            .map(|api_key| Principal {
+                subject: format!("key:{}", api_key.id),
+                role: api_key.role.clone(),
+            })
+            .ok_or(AuthError::InvalidKey)
+    }
+}
+
+// Compact JWS: three base64url parts. Issued API keys never contain dots.
+fn is_jwt(credential: &str) -> bool {
+    credential.split('.').count() == 3
+}
+
+// An issued key. Only its hash is kept, the key itself is shown once.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKey {
+    pub id: String,
+    #[serde(skip)]
+    pub key_hash: String, // Hex SHA-256 of the key
+    #[serde(flatten)]
+    pub role: Role,
+    pub label: Option<String>,
+    #[serde(with = "iso8601")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "iso8601::option")]
+    pub revoked_at: Option<OffsetDateTime>,
+}
+
+impl ApiKey {
+    // A new random key for the role, returned alongside its record
+    pub fn issue(role: Role, label: Option<String>) -> (Self, String) {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        let key = format!("{}{}", KEY_PREFIX, hex::encode(secret));
+        (Self::from_key(&key, role, label), key)
+    }
+
+    // A record for a key chosen by the operator, e.g. the bootstrap admin key
+    pub fn from_key(key: &str, role: Role, label: Option<String>) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            key_hash: hash_key(key),
+            role,
+            label,
+            created_at: OffsetDateTime::now_utc(),
+            revoked_at: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none()
+    }
+}
+
+pub fn hash_key(key: &str) -> String {
+    hex::encode(Impl::hash_bytes(key.as_bytes()).as_bytes())
+}
+
+// Why a request was refused, turned into a 401 or 403 by `handle_rejection`
+#[derive(Debug)]
+pub enum AuthError {
+    MissingKey,
+    InvalidKey,
+    InvalidToken(String),
+    Forbidden(Access),
+}
+
+impl warp::reject::Reject for AuthError {}
+
+// Requires an active API key or a valid JWT with the given access in
+// `Authorization: Bearer`, or else a client certificate mapped to a principal,
+// extracting the caller
+pub fn authorize(
+    auth: Arc<Authenticator>,
+    access: Access,
+) -> impl Filter<Extract = (Principal,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::ext::optional::<ClientIdentity>())
+        .and_then(
+            move |header: Option<String>, client: Option<ClientIdentity>| {
+                let auth = auth.clone();
+                async move {
+                    let principal = match (header, client) {
+                        (Some(header), _) => {
+                            let credential = header
+                                .strip_prefix("Bearer ")
+                                .ok_or_else(|| warp::reject::custom(AuthError::MissingKey))?;
+                            auth.authenticate(credential.trim())
+                                .await
+                                .map_err(warp::reject::custom)?
+                        }
+                        (None, Some(ClientIdentity(principal))) => principal,
+                        (None, None) => return Err(warp::reject::custom(AuthError::MissingKey)),
+                    };
+
+                    if principal.role.allows(access) {
+                        Ok(principal)
+                    } else {
+                        Err(warp::reject::custom(AuthError::Forbidden(access)))
+                    }
+                }
+            },
+        )
+}
+
+// Same as `authorize`, for handlers that don't need the caller
+pub fn require(
+    auth: Arc<Authenticator>,
+    access: Access,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    authorize(auth, access).map(|_| ()).untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deal::Deal;
+    use crate::handlers::{handle_cancel_deal, handle_list_deal_commitments, handle_rejection};
+    use crate::storage::StoredCommitment;
+    use rwz_pof_core::{create_signed_message, get_deterministic_signing_key};
+
+    fn buyer(buyer: &str) -> Role {
+        Role::BuyersBank {
+            buyer: buyer.to_string(),
+        }
+    }
+
+    fn principal(role: Role) -> Principal {
+        Principal {
+            subject: "test".to_string(),
+            role,
+        }
+    }
+
+    #[test]
+    fn test_role_allows() {
+        let lender = Role::LendingBank { bank_index: 1 };
+        let seller = Role::SellersBank {
+            seller: "seller123".to_string(),
+        };
+
+        assert!(lender.allows(Access::LendingBank));
+        assert!(!lender.allows(Access::BuyersBank));
+        assert!(buyer("buyer123").allows(Access::BuyersBank));
+        assert!(!buyer("buyer123").allows(Access::SellersBank));
+        assert!(seller.allows(Access::SellersBank));
+        assert!(!seller.allows(Access::Admin));
+        for role in [lender, buyer("buyer123"), seller.clone(), Role::Admin] {
+            assert!(role.allows(Access::Authenticated));
+        }
+        assert!(Role::Admin.allows(Access::Admin));
+
+        assert!(buyer("buyer123").is_buyer("buyer123"));
+        assert!(!buyer("buyer123").is_buyer("buyer456"));
+        assert!(!seller.is_buyer("seller123"));
+        assert!(seller.is_seller("seller123"));
+        assert!(Role::Admin.is_buyer("buyer456") && Role::Admin.is_seller("seller456"));
+    }
+
+    #[test]
+    fn test_principal_bank_index() {
+        let lender = principal(Role::LendingBank { bank_index: 1 });
+        assert_eq!(lender.bank_index(None), Ok(1));
+        assert_eq!(lender.bank_index(Some(1)), Ok(1));
+        assert!(lender.bank_index(Some(2)).is_err());
+
+        let admin = principal(Role::Admin);
+        assert_eq!(admin.bank_index(Some(2)), Ok(2));
+        assert!(admin.bank_index(None).is_err());
+
+        assert!(principal(buyer("buyer123")).bank_index(None).is_err());
+        assert!(principal(buyer("buyer123")).bank_index(Some(0)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_deal_ownership() {
+        let mut storage = Storage::new();
+        storage.add_deal(Deal::new(
+            "DEAL1".to_string(),
+            "buyer123".to_string(),
+            "seller123".to_string(),
+            100,
+            "USD".to_string(),
+            None,
+        ));
+        for (key, role) in [
+            ("own", buyer("buyer123")),
+            ("other", buyer("buyer456")),
+            ("lender", Role::LendingBank { bank_index: 0 }),
+        ] {
+            storage.add_api_key(ApiKey::from_key(key, role, None));
+        }
+        let storage = Arc::new(Mutex::new(storage));
+        let auth = Arc::new(Authenticator::new(storage.clone(), None));
+
+        let routes = warp::delete()
+            .and(warp::path!("deals" / String))
+            .and(authorize(auth, Access::BuyersBank))
+            .and(warp::any().map(move || storage.clone()))
+            .and_then(handle_cancel_deal)
+            .recover(handle_rejection);
+        let cancel = |key: Option<&str>| {
+            let request = warp::test::request().method("DELETE").path("/deals/DEAL1");
+            match key {
+                Some(key) => request.header("authorization", format!("Bearer {}", key)),
+                None => request,
+            }
+        };
+
+        assert_eq!(cancel(None).reply(&routes).await.status(), 401);
+        assert_eq!(cancel(Some("unknown")).reply(&routes).await.status(), 401);
+        assert_eq!(cancel(Some("lender")).reply(&routes).await.status(), 403);
+        assert_eq!(cancel(Some("other")).reply(&routes).await.status(), 403);
+        assert_eq!(cancel(Some("own")).reply(&routes).await.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_commitment_visibility() {
+        let mut storage = Storage::new();
+        storage.add_deal(Deal::new(
+            "DEAL1".to_string(),
+            "buyer123".to_string(),
+            "seller123".to_string(),
+            100,
+            "USD".to_string(),
+            None,
+        ));
+        for bank_index in [0, 1] {
+            let signed = create_signed_message(
+                &get_deterministic_signing_key(bank_index),
+                40,
+                "DEAL1".to_string(),
+                "buyer123".to_string(),
+            )
+            .unwrap();
+            storage.add_commitment(StoredCommitment::new(
+                format!("c{}", bank_index),
+                "DEAL1".to_string(),
+                signed,
+                None,
+            ));
+        }
+        for (key, role) in [
+            ("buyer", buyer("buyer123")),
+            (
+                "seller",
+                Role::SellersBank {
+                    seller: "seller123".to_string(),
+                },
+            ),
+            ("lender", Role::LendingBank { bank_index: 1 }),
+        ] {
+            storage.add_api_key(ApiKey::from_key(key, role, None));
+        }
+        let storage = Arc::new(Mutex::new(storage));
+        let auth = Arc::new(Authenticator::new(storage.clone(), None));
+
+        let routes = warp::get()
+            .and(warp::path!("deals" / String / "commitments"))
+            .and(authorize(auth, Access::Authenticated))
+            .and(warp::any().map(move || storage.clone()))
+            .and_then(handle_list_deal_commitments)
+            .recover(handle_rejection);
+        let list = |key: &str| {
+            warp::test::request()
+                .path("/deals/DEAL1/commitments")
+                .header("authorization", format!("Bearer {}", key))
+                .reply(&routes)
+        };
+        let ids = |body: &[u8]| -> Vec<String> {
+            let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+            body["commitments"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|c| c["commitment_id"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let response = list("buyer").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(ids(response.body()), ["c0", "c1"]);
+        let response = list("lender").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(ids(response.body()), ["c1"]);
+        assert_eq!(list("seller").await.status(), 403);
+    }
+}
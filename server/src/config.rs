@@ -0,0 +1,413 @@
+use crate::tls::{ClientAuth, TlsConfig};
+use crate::webhooks::WebhookConfig;
+use rwz_pof_core::registry_pubkeys;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Read when SERVER_CONFIG doesn't name another file, if it exists
+const DEFAULT_CONFIG_PATH: &str = "server.toml";
+
+// The only storage backend so far, kept for the life of the process
+const MEMORY_STORAGE: &str = "memory://";
+
+// Server settings from a TOML file, with environment variable overrides.
+// Everything has a default, so an empty file is a valid configuration.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: SocketAddr,
+    pub cors_origins: Vec<String>, // Empty allows any origin
+    pub storage: String,           // DSN
+    pub policy_dir: PathBuf,       // Seller's Bank acceptance policies
+    pub registry: Option<PathBuf>, // Lending banks the deployment expects
+    pub log_format: LogFormat,
+    pub tls: Option<TlsConfig>,
+    pub worker: WorkerConfig,
+    pub prover: ProverMode,
+    pub demo: DemoConfig,
+    pub webhooks: WebhookConfig,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Full,
+    Compact,
+    Pretty,
+}
+
+// Asynchronous proof jobs
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkerConfig {
+    pub concurrency: usize, // Jobs proving at once, the rest wait as pending
+    pub job_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProverMode {
+    Local, // Real proofs
+    Dev,   // RISC0_DEV_MODE: fake receipts, for development only
+}
+
+// The demo deal used by the frontend and the README examples, which is also
+// the deal of commitments that name none
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DemoConfig {
+    pub enabled: bool,
+    pub deal_id: String,
+    pub buyer: String,
+    pub seller: String,
+    pub target_amount: u64,
+    pub currency: String,
+}
+
+// Lending bank registry file: the SEC1 public keys, in registry order
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegistryFile {
+    banks: Vec<RegistryBank>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegistryBank {
+    name: String,
+    pubkey: String, // Hex
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: ([127, 0, 0, 1], 3030).into(),
+            cors_origins: Vec::new(),
+            storage: MEMORY_STORAGE.to_string(),
+            policy_dir: "policies".into(),
+            registry: None,
+            log_format: LogFormat::Full,
+            tls: None,
+            worker: WorkerConfig::default(),
+            prover: ProverMode::Local,
+            demo: DemoConfig::default(),
+            webhooks: WebhookConfig::default(),
+        }
+    }
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 1,
+            job_timeout_secs: 30 * 60,
+        }
+    }
+}
+
+impl Default for DemoConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            deal_id: "DEAL123".to_string(),
+            buyer: "buyer123".to_string(),
+            seller: "seller123".to_string(),
+            target_amount: 60,
+            currency: "USD".to_string(),
+        }
+    }
+}
+
+impl WorkerConfig {
+    pub fn job_timeout(&self) -> Duration {
+        Duration::from_secs(self.job_timeout_secs)
+    }
+}
+
+impl Config {
+    // The file named by SERVER_CONFIG, or server.toml if there is one, then
+    // the environment overrides, validated
+    pub fn load() -> Result<Self, String> {
+        let mut config = match std::env::var("SERVER_CONFIG") {
+            Ok(path) => Self::from_file(Path::new(&path))?,
+            Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            Err(_) => Self::default(),
+        };
+        config.apply_env(|name| std::env::var(name).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        toml::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Overrides from LISTEN_ADDR, CORS_ORIGINS (comma-separated), STORAGE_DSN,
+    // POLICY_DIR, REGISTRY_PATH, LOG_FORMAT, WORKER_CONCURRENCY,
+    // JOB_TIMEOUT_SECS, PROVER_MODE, WEBHOOK_MAX_ATTEMPTS, and TLS_CERT,
+    // TLS_KEY, TLS_CLIENT_CA and TLS_CLIENT_AUTH
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+        fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String>
+        where
+            T::Err: std::fmt::Display,
+        {
+            value
+                .trim()
+                .parse()
+                .map_err(|e| format!("{}={:?}: {}", name, value, e))
+        }
+        // Enums take the same values as in the file
+        fn parse_enum<T: for<'de> Deserialize<'de>>(name: &str, value: &str) -> Result<T, String> {
+            T::deserialize(
+                serde::de::value::StrDeserializer::<serde::de::value::Error>::new(value.trim()),
+            )
+            .map_err(|e| format!("{}={:?}: {}", name, value, e))
+        }
+
+        if let Some(value) = var("LISTEN_ADDR") {
+            self.listen = parse("LISTEN_ADDR", &value)?;
+        }
+        if let Some(value) = var("CORS_ORIGINS") {
+            self.cors_origins = value
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        if let Some(value) = var("STORAGE_DSN") {
+            self.storage = value;
+        }
+        if let Some(value) = var("POLICY_DIR") {
+            self.policy_dir = value.into();
+        }
+        if let Some(value) = var("REGISTRY_PATH") {
+            self.registry = Some(value.into());
+        }
+        if let Some(value) = var("LOG_FORMAT") {
+            self.log_format = parse_enum("LOG_FORMAT", &value)?;
+        }
+        if let Some(value) = var("WORKER_CONCURRENCY") {
+            self.worker.concurrency = parse("WORKER_CONCURRENCY", &value)?;
+        }
+        if let Some(value) = var("JOB_TIMEOUT_SECS") {
+            self.worker.job_timeout_secs = parse("JOB_TIMEOUT_SECS", &value)?;
+        }
+        if let Some(value) = var("PROVER_MODE") {
+            self.prover = parse_enum("PROVER_MODE", &value)?;
+        }
+        if let Some(value) = var("WEBHOOK_MAX_ATTEMPTS") {
+            self.webhooks.max_attempts = parse("WEBHOOK_MAX_ATTEMPTS", &value)?;
+        }
+
+        if let Some(cert) = var("TLS_CERT") {
+            let key = var("TLS_KEY").ok_or("TLS_KEY is required with TLS_CERT")?;
+            match &mut self.tls {
+                Some(tls) => {
+                    tls.cert = cert.into();
+                    tls.key = key.into();
+                }
+                None => {
+                    self.tls = Some(TlsConfig {
+                        cert: cert.into(),
+                        key: key.into(),
+                        client_ca: None,
+                        client_auth: ClientAuth::Optional,
+                    })
+                }
+            }
+        }
+        if let Some(tls) = &mut self.tls {
+            if let Some(value) = var("TLS_CLIENT_CA") {
+                tls.client_ca = Some(value.into());
+            }
+            if let Some(value) = var("TLS_CLIENT_AUTH") {
+                tls.client_auth = parse_enum("TLS_CLIENT_AUTH", &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.storage != MEMORY_STORAGE {
+            return Err(format!(
+                "storage: unsupported DSN {:?}, only {} is available",
+                self.storage, MEMORY_STORAGE
+            ));
+        }
+        for origin in &self.cors_origins {
+            if origin != "*" && !is_origin(origin) {
+                return Err(format!(
+                    "cors_origins: {:?} isn't an origin like https://bank.example",
+                    origin
+                ));
+            }
+        }
+        if self.worker.concurrency == 0 {
+            return Err("worker.concurrency must be at least 1".to_string());
+        }
+        if self.worker.job_timeout_secs == 0 {
+            return Err("worker.job_timeout_secs must be at least 1".to_string());
+        }
+        if self.webhooks.max_attempts == 0 {
+            return Err("webhooks.max_attempts must be at least 1".to_string());
+        }
+        if self.webhooks.timeout_secs == 0 {
+            return Err("webhooks.timeout_secs must be at least 1".to_string());
+        }
+        if let Some(tls) = &self.tls {
+            for path in [Some(&tls.cert), Some(&tls.key), tls.client_ca.as_ref()]
+                .into_iter()
+                .flatten()
+            {
+                if !path.is_file() {
+                    return Err(format!("tls: {} doesn't exist", path.display()));
+                }
+            }
+            if tls.client_auth == ClientAuth::Required && tls.client_ca.is_none() {
+                return Err("tls: client_auth = \"required\" needs client_ca".to_string());
+            }
+        }
+        if let Some(path) = &self.registry {
+            check_registry(path)?;
+        }
+        Ok(())
+    }
+}
+
+// Scheme and host, with an optional port and nothing after
+fn is_origin(origin: &str) -> bool {
+    let Some((scheme, host)) = origin.split_once("://") else {
+        return false;
+    };
+    matches!(scheme, "http" | "https") && !host.is_empty() && !host.contains(['/', '?', '#'])
+}
+
+// The guests accept only the lending banks they were built with, so a
+// deployment expecting other banks can't prove their commitments
+fn check_registry(path: &Path) -> Result<(), String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("registry: failed to read {}: {}", path.display(), e))?;
+    let registry: RegistryFile = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&contents).map_err(|e| e.to_string()),
+        _ => toml::from_str(&contents).map_err(|e| e.to_string()),
+    }
+    .map_err(|e| format!("registry: {}: {}", path.display(), e))?;
+
+    let built_in = registry_pubkeys();
+    if registry.banks.len() != built_in.len() {
+        return Err(format!(
+            "registry: {} lists {} banks, the guests were built with {}",
+            path.display(),
+            registry.banks.len(),
+            built_in.len()
+        ));
+    }
+    for (index, (bank, pubkey)) in registry.banks.iter().zip(&built_in).enumerate() {
+        if !bank.pubkey.eq_ignore_ascii_case(&hex::encode(pubkey)) {
+            return Err(format!(
+                "registry: {} key doesn't match bank {} of the guests' registry, {}",
+                bank.name,
+                index,
+                hex::encode(pubkey)
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn with_env(config: &mut Config, vars: &[(&str, &str)]) -> Result<(), String> {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        config.apply_env(|name| vars.get(name).map(|value| value.to_string()))
+    }
+
+    #[test]
+    fn test_file_and_env_overrides() {
+        let mut config: Config = toml::from_str(
+            r#"
+            listen = "0.0.0.0:8443"
+            cors_origins = ["https://lb1.example"]
+
+            [worker]
+            concurrency = 4
+
+            [demo]
+            enabled = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.listen.port(), 8443);
+        assert_eq!(config.worker.job_timeout_secs, 30 * 60);
+        assert!(!config.demo.enabled);
+
+        with_env(
+            &mut config,
+            &[
+                ("CORS_ORIGINS", "https://a.example, https://b.example"),
+                ("JOB_TIMEOUT_SECS", "600"),
+                ("PROVER_MODE", "dev"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.cors_origins.len(), 2);
+        assert_eq!(config.worker.concurrency, 4);
+        assert_eq!(config.worker.job_timeout_secs, 600);
+        assert_eq!(config.prover, ProverMode::Dev);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_rejects_invalid_settings() {
+        assert!(toml::from_str::<Config>("port = 3030").is_err());
+        assert!(with_env(&mut Config::default(), &[("WORKER_CONCURRENCY", "many")]).is_err());
+        assert!(with_env(&mut Config::default(), &[("LOG_FORMAT", "xml")]).is_err());
+
+        let invalid = [
+            "storage = \"postgres://localhost/rwz\"",
+            "cors_origins = [\"bank.example\"]",
+            "worker = { concurrency = 0 }",
+            "tls = { cert = \"missing.pem\", key = \"missing.key\" }",
+        ];
+        for toml in invalid {
+            let config: Config = toml::from_str(toml).unwrap();
+            assert!(config.validate().is_err(), "{}", toml);
+        }
+    }
+
+    #[test]
+    fn test_checks_registry() {
+        let dir = std::env::temp_dir().join(format!("rwz-registry-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let banks: Vec<_> = registry_pubkeys()
+            .iter()
+            .enumerate()
+            .map(|(index, key)| {
+                format!(
+                    "{{ name = \"LB{}\", pubkey = \"{}\" }}",
+                    index + 1,
+                    hex::encode(key)
+                )
+            })
+            .collect();
+
+        let path = dir.join("registry.toml");
+        std::fs::write(&path, format!("banks = [{}]", banks.join(", "))).unwrap();
+        check_registry(&path).unwrap();
+
+        std::fs::write(&path, format!("banks = [{}]", banks[0])).unwrap();
+        assert!(check_registry(&path).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
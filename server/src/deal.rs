@@ -0,0 +1,313 @@
+use rwz_pof_core::VerifiedDeal;
+use serde::{Deserialize, Serialize};
+use time::serde::iso8601;
+use time::OffsetDateTime;
+
+// Lifecycle of a deal:
+// open -> financing -> proven -> submitted -> closed, or cancelled before closing
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DealState {
+    Open,      // Created, no commitments yet
+    Financing, // Lending banks are committing
+    Proven,    // At least one proof generated
+    Submitted, // Proof handed to the seller's bank
+    Closed,
+    Cancelled,
+}
+
+impl DealState {
+    pub fn can_transition_to(self, next: DealState) -> bool {
+        use DealState::*;
+        matches!(
+            (self, next),
+            (Open, Financing)
+                | (Financing, Proven)
+                | (Proven, Submitted)
+                | (Submitted, Closed)
+                | (Open | Financing | Proven | Submitted, Cancelled)
+        )
+    }
+
+    // Commitments can be submitted, amended and withdrawn until a proof exists
+    pub fn accepts_commitments(self) -> bool {
+        matches!(self, DealState::Open | DealState::Financing)
+    }
+
+    // Proofs can be generated once financing started, and again at other
+    // levels until one is submitted
+    pub fn accepts_proofs(self) -> bool {
+        matches!(self, DealState::Financing | DealState::Proven)
+    }
+
+    // Deal terms can only change before a proof commits to them
+    pub fn accepts_updates(self) -> bool {
+        matches!(self, DealState::Open | DealState::Financing)
+    }
+
+    // Bids can be raised once a proof exists, also after one was handed over
+    pub fn accepts_rounds(self) -> bool {
+        matches!(self, DealState::Proven | DealState::Submitted)
+    }
+}
+
+// A recorded state change
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DealTransition {
+    pub from: DealState,
+    pub to: DealState,
+    pub reason: Option<String>,
+    #[serde(with = "iso8601")]
+    pub at: OffsetDateTime,
+}
+
+// One bid in the deal's chain. Every round after the first is a revision
+// proof committing the journal digest of the round before.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BidRound {
+    pub round: u32,
+    pub proof_id: Option<String>, // None for uploaded bundles
+    pub verified_amount: u64,
+    pub journal_digest: String,                  // Hex
+    pub previous_journal_digest: Option<String>, // Hex, None for the first round
+    #[serde(with = "iso8601")]
+    pub submitted_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Deal {
+    pub id: String,
+    pub buyer: String,
+    pub seller: String,
+    pub target_amount: u64,
+    pub currency: String,
+    #[serde(with = "iso8601::option")]
+    pub deadline: Option<OffsetDateTime>,
+    pub state: DealState,
+    pub transitions: Vec<DealTransition>,
+    pub rounds: Vec<BidRound>,
+    #[serde(with = "iso8601")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "iso8601")]
+    pub updated_at: OffsetDateTime,
+}
+
+impl Deal {
+    pub fn new(
+        id: String,
+        buyer: String,
+        seller: String,
+        target_amount: u64,
+        currency: String,
+        deadline: Option<OffsetDateTime>,
+    ) -> Self {
+        let now = OffsetDateTime::now_utc();
+        Self {
+            id,
+            buyer,
+            seller,
+            target_amount,
+            currency,
+            deadline,
+            state: DealState::Open,
+            transitions: Vec::new(),
+            rounds: Vec::new(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    // Moves the deal to `to` and records the change, if the state machine allows it
+    pub fn transition(&mut self, to: DealState, reason: Option<String>) -> Result<(), String> {
+        if !self.state.can_transition_to(to) {
+            return Err(format!(
+                "Deal {} can't go from {:?} to {:?}",
+                self.id, self.state, to
+            ));
+        }
+
+        let now = OffsetDateTime::now_utc();
+        self.transitions.push(DealTransition {
+            from: self.state,
+            to,
+            reason,
+            at: now,
+        });
+        self.state = to;
+        self.updated_at = now;
+        Ok(())
+    }
+
+    pub fn is_past_deadline(&self, now: OffsetDateTime) -> bool {
+        self.deadline.is_some_and(|deadline| now >= deadline)
+    }
+
+    // Why commitments can't be changed right now, if they can't
+    pub fn check_commitments_allowed(&self) -> Result<(), String> {
+        if !self.state.accepts_commitments() {
+            return Err(format!(
+                "Deal {} is {:?} and no longer accepts commitment changes",
+                self.id, self.state
+            ));
+        }
+        if self.is_past_deadline(OffsetDateTime::now_utc()) {
+            return Err(format!("Deal {} is past its deadline", self.id));
+        }
+        Ok(())
+    }
+
+    // Why a proof can't be generated right now, if it can't
+    pub fn check_proofs_allowed(&self) -> Result<(), String> {
+        if !self.state.accepts_proofs() {
+            return Err(format!(
+                "Deal {} is {:?}, proofs need it to be financing or proven",
+                self.id, self.state
+            ));
+        }
+        if self.is_past_deadline(OffsetDateTime::now_utc()) {
+            return Err(format!("Deal {} is past its deadline", self.id));
+        }
+        Ok(())
+    }
+
+    // Adds a verified proof as the next bid round. After the first round it
+    // must be a revision of the latest one, raising the amount.
+    pub fn add_round(
+        &mut self,
+        verified: &VerifiedDeal,
+        proof_id: Option<String>,
+    ) -> Result<BidRound, String> {
+        if !self.state.accepts_rounds() {
+            return Err(format!(
+                "Deal {} is {:?}, bid rounds need it to be proven or submitted",
+                self.id, self.state
+            ));
+        }
+        if verified.deal_info.deal_id != self.id || verified.deal_info.buyer != self.buyer {
+            return Err(format!(
+                "Proof is for deal {} and buyer {}, not {} and {}",
+                verified.deal_info.deal_id, verified.deal_info.buyer, self.id, self.buyer
+            ));
+        }
+
+        let previous_journal_digest = verified.previous_journal_digest.map(hex::encode);
+        if let Some(last) = self.rounds.last() {
+            if previous_journal_digest.as_ref() != Some(&last.journal_digest) {
+                return Err(format!(
+                    "Proof doesn't revise round {} ({})",
+                    last.round, last.journal_digest
+                ));
+            }
+            if verified.verified_amount <= last.verified_amount {
+                return Err(format!(
+                    "Revised amount {} doesn't exceed {} from round {}",
+                    verified.verified_amount, last.verified_amount, last.round
+                ));
+            }
+        }
+
+        let now = OffsetDateTime::now_utc();
+        let round = BidRound {
+            round: self.rounds.len() as u32 + 1,
+            proof_id,
+            verified_amount: verified.verified_amount,
+            journal_digest: hex::encode(verified.journal_digest),
+            previous_journal_digest,
+            submitted_at: now,
+        };
+        self.rounds.push(round.clone());
+        self.updated_at = now;
+        Ok(round)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rwz_pof_core::DealInfo;
+
+    fn deal() -> Deal {
+        Deal::new(
+            "DEAL1".to_string(),
+            "buyer123".to_string(),
+            "seller123".to_string(),
+            100,
+            "USD".to_string(),
+            None,
+        )
+    }
+
+    fn verified(amount: u64, digest: u8, previous: Option<u8>) -> VerifiedDeal {
+        VerifiedDeal {
+            deal_info: DealInfo {
+                amount,
+                deal_id: "DEAL1".to_string(),
+                buyer: "buyer123".to_string(),
+            },
+            verified_amount: amount,
+            bank_count: 1,
+            recipient: None,
+            challenge: None,
+            nullifiers: Vec::new(),
+            requirement_hash: None,
+            journal_digest: [digest; 32],
+            previous_journal_digest: previous.map(|previous| [previous; 32]),
+            image_id: [0; 8],
+            guest_version: "v6".to_string(),
+            registry_root: None,
+            created_at: None,
+            signer: None,
+        }
+    }
+
+    #[test]
+    fn test_illegal_transitions() {
+        let mut deal = deal();
+        assert!(deal.transition(DealState::Proven, None).is_err());
+        assert!(deal.transition(DealState::Closed, None).is_err());
+        assert_eq!(deal.state, DealState::Open);
+        assert!(deal.transitions.is_empty());
+
+        deal.transition(DealState::Financing, None).unwrap();
+        deal.transition(DealState::Cancelled, Some("Withdrawn".to_string()))
+            .unwrap();
+        for state in [
+            DealState::Open,
+            DealState::Financing,
+            DealState::Proven,
+            DealState::Closed,
+            DealState::Cancelled,
+        ] {
+            assert!(deal.transition(state, None).is_err(), "{:?}", state);
+        }
+        assert_eq!(deal.transitions.len(), 2);
+        assert_eq!(deal.transitions[1].from, DealState::Financing);
+        assert_eq!(deal.transitions[1].reason.as_deref(), Some("Withdrawn"));
+
+        // Closed deals can't be cancelled any more
+        assert!(!DealState::Closed.can_transition_to(DealState::Cancelled));
+    }
+
+    #[test]
+    fn test_rounds_must_revise_and_raise() {
+        let mut deal = deal();
+        assert!(deal.add_round(&verified(100, 1, None), None).is_err());
+
+        deal.transition(DealState::Financing, None).unwrap();
+        deal.transition(DealState::Proven, None).unwrap();
+        let first = deal.add_round(&verified(100, 1, None), None).unwrap();
+        assert_eq!(first.round, 1);
+
+        // Not linked to round 1, then not raising it
+        assert!(deal.add_round(&verified(120, 2, None), None).is_err());
+        assert!(deal.add_round(&verified(100, 2, Some(1)), None).is_err());
+
+        let mut other_buyer = verified(120, 2, Some(1));
+        other_buyer.deal_info.buyer = "buyer456".to_string();
+        assert!(deal.add_round(&other_buyer, None).is_err());
+
+        let second = deal.add_round(&verified(120, 2, Some(1)), None).unwrap();
+        assert_eq!(second.round, 2);
+        assert_eq!(second.previous_journal_digest, Some(first.journal_digest));
+    }
+}
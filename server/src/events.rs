@@ -0,0 +1,179 @@
+use rwz_pof_core::PreflightReport;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use time::serde::iso8601;
+use time::OffsetDateTime;
+use tokio::sync::broadcast;
+
+// Live events buffered per subscriber before it starts missing some
+const CHANNEL_CAPACITY: usize = 64;
+
+// What happened to an asynchronous proof job. Progress is reported per
+// proving step, not per segment: without risc0's `prove` feature, proofs are
+// made by the external `r0vm` (or Bonsai), which reports nothing until a
+// receipt is done.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JobEventKind {
+    Queued,
+    PreflightPassed {
+        preflight: PreflightReport,
+    },
+    ProvingStarted {
+        steps: usize, // Commitments, then the aggregation
+    },
+    // A commitment sub-receipt, from the cache or proven, or the aggregation
+    StepProven {
+        step: usize,
+        steps: usize,
+        stage: &'static str, // "commitment" or "aggregation"
+        cached: bool,
+    },
+    Completed {
+        proof_id: String,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobEvent {
+    pub id: usize, // Position in the job's history
+    #[serde(flatten)]
+    pub kind: JobEventKind,
+    #[serde(with = "iso8601")]
+    pub at: OffsetDateTime,
+}
+
+impl JobEventKind {
+    // Completed and failed jobs send nothing more
+    pub fn is_final(&self) -> bool {
+        matches!(self, Self::Completed { .. } | Self::Failed { .. })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::PreflightPassed { .. } => "preflight_passed",
+            Self::ProvingStarted { .. } => "proving_started",
+            Self::StepProven { .. } => "step_proven",
+            Self::Completed { .. } => "completed",
+            Self::Failed { .. } => "failed",
+        }
+    }
+}
+
+struct JobLog {
+    history: Vec<JobEvent>,
+    sender: broadcast::Sender<JobEvent>,
+}
+
+// Event history of every proof job, with live subscriptions
+#[derive(Default)]
+pub struct JobEvents {
+    jobs: Mutex<HashMap<String, JobLog>>,
+}
+
+impl JobEvents {
+    pub fn publish(&self, job_id: &str, kind: JobEventKind) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let log = jobs.entry(job_id.to_string()).or_insert_with(|| JobLog {
+            history: Vec::new(),
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+        });
+        let event = JobEvent {
+            id: log.history.len(),
+            kind,
+            at: OffsetDateTime::now_utc(),
+        };
+        log.history.push(event.clone());
+        // No receivers is fine, the history keeps the event
+        let _ = log.sender.send(event);
+    }
+
+    // The job's events so far and a receiver for the ones after them, or
+    // None for an unknown job
+    pub fn subscribe(
+        &self,
+        job_id: &str,
+    ) -> Option<(Vec<JobEvent>, broadcast::Receiver<JobEvent>)> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.get(job_id)
+            .map(|log| (log.history.clone(), log.sender.subscribe()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_replays_history_then_live_events() {
+        let events = JobEvents::default();
+        assert!(events.subscribe("job").is_none());
+
+        events.publish("job", JobEventKind::Queued);
+        events.publish("job", JobEventKind::ProvingStarted { steps: 2 });
+        let (history, mut live) = events.subscribe("job").unwrap();
+        assert_eq!(history.len(), 2);
+        assert!(matches!(
+            history[1].kind,
+            JobEventKind::ProvingStarted { steps: 2 }
+        ));
+
+        events.publish(
+            "job",
+            JobEventKind::Failed {
+                error: "Timed out".to_string(),
+            },
+        );
+        let event = live.recv().await.unwrap();
+        assert_eq!(event.id, 2);
+        assert!(event.kind.is_final());
+    }
+
+    #[tokio::test]
+    async fn test_streams_server_sent_events() {
+        use crate::auth::{Principal, Role};
+        use crate::handlers::handle_proof_job_events;
+        use crate::storage::Storage;
+        use crate::webhooks::Webhooks;
+        use crate::worker::ProofWorker;
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+        use warp::Filter;
+
+        let worker = Arc::new(ProofWorker::new(
+            1,
+            Duration::from_secs(60),
+            Arc::new(Webhooks::new(Default::default())),
+        ));
+        worker.events.publish("job", JobEventKind::Queued);
+        worker.events.publish(
+            "job",
+            JobEventKind::Completed {
+                proof_id: "proof".to_string(),
+            },
+        );
+
+        let storage = Arc::new(Mutex::new(Storage::new()));
+        let routes = warp::path::param().and_then(move |job_id| {
+            let admin = Principal {
+                subject: "test".to_string(),
+                role: Role::Admin,
+            };
+            let (storage, worker) = (storage.clone(), worker.clone());
+            async move { handle_proof_job_events(job_id, admin, storage, worker).await }
+        });
+        let response = warp::test::request().path("/job").reply(&routes).await;
+        let body = String::from_utf8_lossy(response.body()).to_string();
+        assert!(body.contains("event:queued"), "{}", body);
+        assert!(body.contains("event:completed"), "{}", body);
+        assert!(body.contains(r#""proof_id":"proof""#), "{}", body);
+
+        let response = warp::test::request().path("/other").reply(&routes).await;
+        assert_eq!(response.status(), 404);
+    }
+}
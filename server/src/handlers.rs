@@ -1,13 +1,33 @@
-use crate::storage::Storage;
-use crate::worker::ProofWorker;
+use crate::auction::{Auction, Bid, BidStatus, RankedBid};
+use crate::auth::{ApiKey, AuthError, Principal, Role};
+use crate::deal::{BidRound, Deal, DealState};
+use crate::events::{JobEvent, JobEventKind};
+use crate::storage::{CommitmentStatus, Storage, StoredCommitment, StoredProof};
+use crate::upload::{ProofArtifact, UploadParams};
+use crate::webhooks::{DeadLetter, WebhookEventType, WebhookSubscription, Webhooks};
+use crate::worker::{commitment_hashes, commitment_receipts, ProofWorker};
+use futures_util::StreamExt;
+use k256::ecdsa::SigningKey;
+use risc0_zkvm::Receipt;
 use rwz_pof_core::{
-    create_signed_message, generate_proof, get_deterministic_signing_key, DealInfo, SignedMessage,
+    aggregate_proof, commitment_hash, create_bundle, create_signed_message,
+    create_signed_withdrawal, get_deterministic_signing_key, journal_digest, local_policy,
+    preflight, receipt_digest, registry_root, select_commitments, validate_commitments,
+    verify_bundle, verify_receipt, withdrawal_is_valid, DealInfo, PolicyVerdict, PreflightReport,
+    ProofBundle, ProofOptions, ReceiptKind, SelectionStrategy, SignedMessage, SignedRequirement,
+    SignedWithdrawal, ValidationIssue, VerifiedDeal, BUNDLE_EXTENSION,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::convert::Infallible;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 use time::serde::iso8601;
-use warp::{reply::json, Reply};
+use tokio::sync::broadcast;
+use warp::http::{header, StatusCode};
+use warp::multipart::FormData;
+use warp::reply::{json, Response};
+use warp::Reply;
 
 use crate::storage::ProofJobStatus;
 use time::OffsetDateTime;
@@ -15,65 +35,281 @@ use time::OffsetDateTime;
 // Request/Response types
 #[derive(Debug, Deserialize)]
 pub struct CommitmentRequest {
-    bank_index: u64,
+    #[serde(default)]
+    bank_index: Option<u64>, // Only for admins, lending banks commit as themselves
     amount: u64,
     #[serde(default = "default_deal_id")]
     deal_id: String,
-    #[serde(default = "default_buyer")]
-    buyer: String,
+    #[serde(default, with = "iso8601::option")]
+    expires_at: Option<OffsetDateTime>,
+}
+
+// Withdraws a commitment with either a withdrawal signed by the lending bank,
+// or one signed here with the demo key of the caller's bank (`bank_index`
+// for admins)
+#[derive(Debug, Deserialize)]
+pub struct WithdrawCommitmentRequest {
+    #[serde(default)]
+    withdrawal: Option<SignedWithdrawal>,
+    #[serde(default)]
+    bank_index: Option<u64>,
+}
+
+// Deal of commitments that name none, the configured demo deal
+static DEFAULT_DEAL_ID: OnceLock<String> = OnceLock::new();
+
+pub fn set_default_deal_id(deal_id: String) {
+    let _ = DEFAULT_DEAL_ID.set(deal_id);
 }
 
 fn default_deal_id() -> String {
-    "DEAL123".to_string()
+    DEFAULT_DEAL_ID.get().cloned().unwrap_or_default()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateDealRequest {
+    #[serde(default)]
+    deal_id: Option<String>, // Generated if not given
+    buyer: String,
+    seller: String,
+    target_amount: u64,
+    currency: String,
+    #[serde(default, with = "iso8601::option")]
+    deadline: Option<OffsetDateTime>,
+}
+
+// Deal terms to change, fields left out are kept
+#[derive(Debug, Deserialize)]
+pub struct UpdateDealRequest {
+    #[serde(default)]
+    seller: Option<String>,
+    #[serde(default)]
+    target_amount: Option<u64>,
+    #[serde(default)]
+    currency: Option<String>,
+    #[serde(default, with = "iso8601::option")]
+    deadline: Option<OffsetDateTime>,
+}
+
+// Moves a deal to submitted, closed or cancelled. Financing and proven are
+// reached through commitments and proofs.
+#[derive(Debug, Deserialize)]
+pub struct DealTransitionRequest {
+    state: DealState,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DealsResponse {
+    deals: Vec<Deal>,
 }
 
-fn default_buyer() -> String {
-    "buyer123".to_string()
+// Exactly one of: a proof stored on this server or an uploaded bundle
+#[derive(Debug, Deserialize)]
+pub struct SubmitRoundRequest {
+    #[serde(default)]
+    proof_id: Option<String>,
+    #[serde(default)]
+    bundle: Option<ProofBundle>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DealRoundsResponse {
+    deal_id: String,
+    rounds: Vec<BidRound>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ProofRequest {
-    required_amount: u64,
+    #[serde(default)]
+    required_amount: Option<u64>, // Taken from the requirement when one is given
+    #[serde(default)]
+    requirement: Option<SignedRequirement>, // Seller-signed, e.g. an auction's
+    #[serde(default)]
+    revises: Option<String>, // Proof ID of the deal's earlier proof this one raises
     deal_id: String,
+    #[serde(default)]
+    recipient: Option<String>,
+    #[serde(default)]
+    challenge: Option<String>,
+    #[serde(default)]
+    commitment_ids: Option<Vec<String>>,
+    #[serde(default)]
+    selection: SelectionStrategy,
 }
 
+// Exactly one of: a proof stored on this server (by proof ID, or the latest
+// for a deal), an uploaded bundle or an uploaded receipt
 #[derive(Debug, Deserialize)]
 pub struct VerifyRequest {
-    deal_id: String,
+    #[serde(default)]
+    deal_id: Option<String>,
+    #[serde(default)]
+    proof_id: Option<String>,
+    #[serde(default)]
+    bundle: Option<ProofBundle>,
+    #[serde(default)]
+    receipt: Option<Receipt>,
+    #[serde(default)]
+    policy_id: Option<String>,
+    #[serde(default)]
+    bid_amount: Option<u64>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct CommitmentResponse {
+    commitment_id: String,
     signed_message: SignedMessage,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    superseded: Vec<String>, // Earlier commitments of the same bank replaced by this one
+}
+
+// A commitment as listed for its deal
+#[derive(Debug, Serialize)]
+pub struct CommitmentSummary {
+    commitment_id: String,
+    status: CommitmentStatus,
+    amount: u64,
+    pubkey: String, // Hex, SEC1
+    #[serde(with = "iso8601::option")]
+    expires_at: Option<OffsetDateTime>,
+    #[serde(with = "iso8601")]
+    created_at: OffsetDateTime,
+    #[serde(with = "iso8601")]
+    updated_at: OffsetDateTime,
+}
+
+impl From<&StoredCommitment> for CommitmentSummary {
+    fn from(commitment: &StoredCommitment) -> Self {
+        Self {
+            commitment_id: commitment.id.clone(),
+            status: commitment.status,
+            amount: commitment.signed.message.amount,
+            pubkey: hex::encode(&commitment.signed.pubkey),
+            expires_at: commitment.expires_at,
+            created_at: commitment.created_at,
+            updated_at: commitment.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DealCommitmentsResponse {
+    deal_id: String,
+    commitments: Vec<CommitmentSummary>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct ProofResponse {
     success: bool,
+    proof_id: String,
     verified_amount: u64,
     deal_info: DealInfo,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    commitments: Vec<SelectedCommitment>,
+}
+
+// A stored proof as listed for its deal
+#[derive(Debug, Serialize)]
+pub struct ProofSummary {
+    proof_id: String,
+    job_id: Option<String>,
+    required_amount: u64,
+    commitment_hashes: Vec<String>,
+    receipt_kind: ReceiptKind,
+    recipient: Option<String>,
+    #[serde(with = "iso8601")]
+    created_at: OffsetDateTime,
+}
+
+impl From<&StoredProof> for ProofSummary {
+    fn from(proof: &StoredProof) -> Self {
+        Self {
+            proof_id: proof.id.clone(),
+            job_id: proof.job_id.clone(),
+            required_amount: proof.required_amount,
+            commitment_hashes: proof.commitment_hashes.clone(),
+            receipt_kind: proof.options.receipt_kind,
+            recipient: proof.options.recipient.clone(),
+            created_at: proof.created_at,
+        }
+    }
+}
+
+// A page of a deal's proofs, oldest first
+#[derive(Debug, Deserialize)]
+pub struct ProofHistoryQuery {
+    #[serde(default)]
+    offset: usize,
+    #[serde(default = "default_page_size")]
+    limit: usize, // At most MAX_PAGE_SIZE
+}
+
+const MAX_PAGE_SIZE: usize = 100;
+
+fn default_page_size() -> usize {
+    20
+}
+
+#[derive(Debug, Serialize)]
+pub struct DealProofsResponse {
+    deal_id: String,
+    total: usize, // Proofs of the deal, on every page
+    offset: usize,
+    proofs: Vec<ProofSummary>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct VerifyResponse {
     verified: bool,
     deal_info: Option<DealInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    guest_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    verdict: Option<PolicyVerdict>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CreateProofJobRequest {
     deal_id: String,
-    required_amount: u64,
+    #[serde(default)]
+    required_amount: Option<u64>,
+    #[serde(default)]
+    requirement: Option<SignedRequirement>,
+    #[serde(default)]
+    revises: Option<String>,
+    #[serde(default)]
+    receipt_kind: ReceiptKind,
+    #[serde(default)]
+    recipient: Option<String>,
+    #[serde(default)]
+    challenge: Option<String>,
+    #[serde(default)]
+    commitment_ids: Option<Vec<String>>,
+    #[serde(default)]
+    selection: SelectionStrategy,
 }
 
 #[derive(Debug, Serialize)]
 pub struct CreateProofJobResponse {
     job_id: String,
+    preflight: PreflightReport,
+    commitments: Vec<SelectedCommitment>,
+}
+
+// A commitment chosen for a proof, shown to the buyer's bank only
+#[derive(Debug, Serialize)]
+pub struct SelectedCommitment {
+    commitment_id: String,
+    amount: u64,
 }
 
 #[derive(Debug, Serialize)]
 pub struct GetProofJobResponse {
     status: ProofJobStatus,
+    receipt_kind: ReceiptKind,
+    commitment_ids: Vec<String>,
     #[serde(with = "iso8601")]
     created_at: OffsetDateTime,
     #[serde(with = "iso8601")]
@@ -81,6 +317,8 @@ pub struct GetProofJobResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     proof: Option<ProofResponse>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    receipt_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     error: Option<String>,
 }
 
@@ -90,202 +328,2248 @@ struct ErrorResponse {
     error: String,
 }
 
+#[derive(Debug, Serialize)]
+struct ValidationErrorResponse {
+    error: String,
+    issues: Vec<String>,
+}
+
+impl ValidationErrorResponse {
+    fn new(context: &str, issues: &[ValidationIssue]) -> Self {
+        Self {
+            error: format!("{}: {} validation issue(s)", context, issues.len()),
+            issues: issues.iter().map(|issue| issue.to_string()).collect(),
+        }
+    }
+}
+
 // Handlers
 pub async fn handle_commitment(
+    caller: Principal,
     req: CommitmentRequest,
     storage: Arc<Mutex<Storage>>,
+    webhooks: Arc<Webhooks>,
 ) -> Result<impl Reply, Infallible> {
-    println!("Handling commitment request: {:?}", req);
-
-    let signing_key = get_deterministic_signing_key(req.bank_index);
+    println!(
+        "Handling commitment request from {}: {:?}",
+        caller.subject, req
+    );
 
-    match create_signed_message(&signing_key, req.amount, req.deal_id.clone(), req.buyer) {
-        Ok(signed_message) => {
-            // Lock storage for modification
-            let mut storage = storage.lock().unwrap();
-            storage.add_commitment(req.deal_id.clone(), signed_message.clone());
+    let bank_index = match caller.bank_index(req.bank_index) {
+        Ok(bank_index) => bank_index,
+        Err(error) => return Ok(error_reply(StatusCode::FORBIDDEN, error)),
+    };
 
-            // Debug: verify storage after adding
-            if let Some(commitments) = storage.get_commitments(&req.deal_id) {
-                println!(
-                    "Stored commitments for deal {}: {}",
-                    req.deal_id,
-                    commitments.len()
-                );
-            }
+    // Lock storage for modification
+    let mut storage = storage.lock().unwrap();
 
-            Ok(json(&CommitmentResponse { signed_message }))
+    let buyer = match storage.get_deal(&req.deal_id) {
+        Some(deal) => match deal.check_commitments_allowed() {
+            Ok(()) => deal.buyer.clone(),
+            Err(error) => return Ok(error_reply(StatusCode::CONFLICT, error)),
+        },
+        None => {
+            return Ok(error_reply(
+                StatusCode::NOT_FOUND,
+                format!("Deal {} not found", req.deal_id),
+            ))
         }
+    };
+
+    let signing_key = get_deterministic_signing_key(bank_index);
+
+    let signed_message =
+        match create_signed_message(&signing_key, req.amount, req.deal_id.clone(), buyer) {
+            Ok(signed_message) => signed_message,
+            Err(e) => {
+                println!("Error creating commitment: {}", e);
+                return Ok(error_reply(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to create commitment: {}", e),
+                ));
+            }
+        };
+    let commitment_id = match commitment_hash(&signed_message) {
+        Ok(commitment_id) => commitment_id,
         Err(e) => {
-            println!("Error creating commitment: {}", e);
-            Ok(json(&ErrorResponse {
-                error: format!("Failed to create commitment: {}", e),
-            }))
+            return Ok(error_reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to hash commitment: {}", e),
+            ))
         }
+    };
+
+    if storage
+        .get_commitment(&req.deal_id, &commitment_id)
+        .is_some()
+    {
+        println!("Commitment {} already submitted", commitment_id);
+        return Ok(error_reply(
+            StatusCode::CONFLICT,
+            format!("Commitment {} already submitted", commitment_id),
+        ));
+    }
+
+    // Reject the commitment if it conflicts with the other banks' active
+    // commitments. The bank's own active commitment is superseded by it.
+    let mut commitments: Vec<_> = storage
+        .get_active_commitments(&req.deal_id)
+        .into_iter()
+        .filter(|other| other.pubkey != signed_message.pubkey)
+        .collect();
+    commitments.push(signed_message.clone());
+
+    let issues = validate_commitments(&commitments, None);
+    if !issues.is_empty() {
+        println!("Rejecting commitment: {:?}", issues);
+        return Ok(validation_reply("Invalid commitment", &issues));
     }
+
+    let superseded = storage.add_commitment(StoredCommitment::new(
+        commitment_id.clone(),
+        req.deal_id.clone(),
+        signed_message.clone(),
+        req.expires_at,
+    ));
+    println!(
+        "Stored commitment {} for deal {}, superseding {:?}",
+        commitment_id, req.deal_id, superseded
+    );
+    webhooks.notify_bank(
+        WebhookEventType::CommitmentCreated,
+        Some(&req.deal_id),
+        &signed_message.pubkey,
+        json!({
+            "commitment_id": commitment_id,
+            "pubkey": hex::encode(&signed_message.pubkey),
+            "amount": req.amount,
+            "superseded": superseded,
+        }),
+    );
+
+    Ok(json(&CommitmentResponse {
+        commitment_id,
+        signed_message,
+        superseded,
+    })
+    .into_response())
 }
 
-pub async fn handle_proof(
-    req: ProofRequest,
+// The deal's buyer sees every commitment, lending banks only their own
+pub async fn handle_list_deal_commitments(
+    deal_id: String,
+    caller: Principal,
     storage: Arc<Mutex<Storage>>,
 ) -> Result<impl Reply, Infallible> {
-    println!("Handling proof request for deal {}", req.deal_id);
-
-    // First lock storage for reading commitments
-    let storage_guard = storage.lock().unwrap();
-
-    let commitments = match storage_guard.get_commitments(&req.deal_id) {
-        Some(commits) => commits.clone(), // Clone the commits while we have the lock
+    let mut storage = storage.lock().unwrap();
+    let sees_all = match storage.get_deal(&deal_id) {
+        Some(deal) if caller.role.is_buyer(&deal.buyer) => true,
+        Some(_) if matches!(caller.role, Role::LendingBank { .. }) => false,
+        Some(deal) => return Ok(not_party(&deal.buyer)),
         None => {
-            println!("No commitments found for deal {}", req.deal_id);
-            return Ok(json(&ErrorResponse {
-                error: format!("No commitments found for deal {}", req.deal_id),
-            }));
+            return Ok(error_reply(
+                StatusCode::NOT_FOUND,
+                format!("Deal {} not found", deal_id),
+            ))
         }
     };
+    let commitments = storage
+        .get_commitments(&deal_id)
+        .iter()
+        .filter(|commitment| sees_all || caller.role.is_bank_key(&commitment.signed.pubkey))
+        .map(CommitmentSummary::from)
+        .collect();
 
-    // Drop the lock so we don't hold it during proof generation
-    drop(storage_guard);
+    Ok(json(&DealCommitmentsResponse {
+        deal_id,
+        commitments,
+    })
+    .into_response())
+}
 
+pub async fn handle_withdraw_commitment(
+    deal_id: String,
+    commitment_id: String,
+    caller: Principal,
+    req: WithdrawCommitmentRequest,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<impl Reply, Infallible> {
     println!(
-        "Found {} commitments for deal {}",
-        commitments.len(),
-        req.deal_id
+        "Withdrawing commitment {} for deal {} as {}",
+        commitment_id, deal_id, caller.subject
     );
 
-    if commitments.len() < 2 {
-        println!("Not enough commitments (need 2, got {})", commitments.len());
-        return Ok(json(&ErrorResponse {
-            error: format!(
-                "Not enough commitments for proof generation. Need 2, got {}",
-                commitments.len()
+    let withdrawal = match (req.withdrawal, req.bank_index) {
+        (Some(withdrawal), None) if caller.role.is_bank_key(&withdrawal.pubkey) => withdrawal,
+        (Some(_), None) => {
+            return Ok(error_reply(
+                StatusCode::FORBIDDEN,
+                "The withdrawal is signed by another bank".to_string(),
+            ))
+        }
+        (None, requested) => {
+            let bank_index = match caller.bank_index(requested) {
+                Ok(bank_index) => bank_index,
+                Err(error) => return Ok(error_reply(StatusCode::FORBIDDEN, error)),
+            };
+            let signing_key = get_deterministic_signing_key(bank_index);
+            match create_signed_withdrawal(&signing_key, commitment_id.clone()) {
+                Ok(withdrawal) => withdrawal,
+                Err(e) => {
+                    return Ok(error_reply(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to sign withdrawal: {}", e),
+                    ))
+                }
+            }
+        }
+        (Some(_), Some(_)) => {
+            return Ok(error_reply(
+                StatusCode::BAD_REQUEST,
+                "Provide either a withdrawal or bank_index, not both".to_string(),
+            ))
+        }
+    };
+
+    let mut storage = storage.lock().unwrap();
+
+    match storage
+        .get_deal(&deal_id)
+        .map(Deal::check_commitments_allowed)
+    {
+        Some(Ok(())) => {}
+        Some(Err(error)) => return Ok(error_reply(StatusCode::CONFLICT, error)),
+        None => {
+            return Ok(error_reply(
+                StatusCode::NOT_FOUND,
+                format!("Deal {} not found", deal_id),
+            ))
+        }
+    }
+
+    let Some(commitment) = storage.get_commitment(&deal_id, &commitment_id) else {
+        return Ok(error_reply(
+            StatusCode::NOT_FOUND,
+            format!(
+                "Commitment {} not found for deal {}",
+                commitment_id, deal_id
+            ),
+        ));
+    };
+    if !withdrawal_is_valid(&withdrawal, &commitment.signed) {
+        println!("Invalid withdrawal for commitment {}", commitment_id);
+        return Ok(error_reply(
+            StatusCode::FORBIDDEN,
+            "Withdrawal isn't signed by the committing bank".to_string(),
+        ));
+    }
+    if !storage.withdraw_commitment(&deal_id, &commitment_id) {
+        return Ok(error_reply(
+            StatusCode::CONFLICT,
+            format!("Commitment {} is {:?}", commitment_id, commitment.status),
+        ));
+    }
+
+    match storage.get_commitment(&deal_id, &commitment_id) {
+        Some(commitment) => Ok(json(&CommitmentSummary::from(&commitment)).into_response()),
+        None => Ok(error_reply(
+            StatusCode::NOT_FOUND,
+            format!(
+                "Commitment {} not found for deal {}",
+                commitment_id, deal_id
             ),
-        }));
+        )),
+    }
+}
+
+pub async fn handle_proof(
+    caller: Principal,
+    req: ProofRequest,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<Response, Infallible> {
+    println!("Handling proof request for deal {}", req.deal_id);
+
+    if let Some(reply) = buyer_refusal(&storage.lock().unwrap(), &req.deal_id, &caller) {
+        return Ok(reply);
     }
 
-    let lb1_signed = commitments[0].clone();
-    let lb2_signed = commitments[1].clone();
+    let required_amount =
+        match resolve_required_amount(&req.deal_id, req.required_amount, &req.requirement) {
+            Ok(amount) => amount,
+            Err(error) => return Ok(error_reply(StatusCode::BAD_REQUEST, error)),
+        };
+    let previous_journal = match previous_journal(&storage, &req.deal_id, req.revises.as_deref()) {
+        Ok(journal) => journal,
+        Err(error) => return Ok(error_reply(StatusCode::NOT_FOUND, error)),
+    };
+
+    let selected = match choose_commitments(
+        &storage,
+        &req.deal_id,
+        req.commitment_ids.as_deref(),
+        &req.selection,
+        required_amount,
+    ) {
+        Ok(selected) => selected,
+        Err(reply) => return Ok(*reply),
+    };
+    let (chosen, selected): (Vec<_>, Vec<_>) = selected.into_iter().unzip();
 
     println!(
-        "Generating proof with amounts: {} and {}",
-        lb1_signed.message.amount, lb2_signed.message.amount
+        "Generating proof from {} commitments for deal {}",
+        selected.len(),
+        req.deal_id
     );
 
-    match generate_proof(lb1_signed, lb2_signed, req.required_amount) {
-        Ok((receipt, deal_info, verified_amount)) => {
+    let options = ProofOptions {
+        recipient: req.recipient,
+        challenge: req.challenge,
+        requirement: req.requirement,
+        previous_journal,
+        ..Default::default()
+    };
+    // Proving is CPU-bound, so it runs off the async worker threads
+    let proving = {
+        let storage = storage.clone();
+        let options = options.clone();
+        tokio::task::spawn_blocking(move || {
+            commitment_hashes(&selected).and_then(|hashes| {
+                let receipts = commitment_receipts(&selected, &storage, |_| {})?;
+                let proof = aggregate_proof(&receipts, required_amount, &options)?;
+                Ok((hashes, proof))
+            })
+        })
+    };
+    let result = match proving.await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(e) => Err(format!("Proving task failed: {}", e)),
+    };
+
+    match result {
+        Ok((hashes, (receipt, deal_info, verified_amount))) => {
             println!(
                 "Proof generated successfully. Verified amount: {}",
                 verified_amount
             );
+            let proof =
+                StoredProof::new(req.deal_id, None, required_amount, hashes, options, receipt);
+            let proof_id = proof.id.clone();
+
             // Get a new mutable lock for storing the proof
             let mut storage = storage.lock().unwrap();
-            storage.add_proof(req.deal_id, receipt);
+            storage.add_proof(proof);
 
             Ok(json(&ProofResponse {
                 success: true,
+                proof_id,
                 verified_amount,
                 deal_info,
-            }))
+                commitments: chosen,
+            })
+            .into_response())
         }
         Err(e) => {
             println!("Error generating proof: {}", e);
-            Ok(json(&ErrorResponse {
-                error: format!("Failed to generate proof: {}", e),
-            }))
+            Ok(error_reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to generate proof: {}", e),
+            ))
         }
     }
 }
 
-pub async fn handle_verify(
-    req: VerifyRequest,
+pub async fn handle_create_deal(
+    caller: Principal,
+    req: CreateDealRequest,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<impl Reply, Infallible> {
+    println!("Creating deal: {:?}", req);
+
+    if !caller.role.is_buyer(&req.buyer) {
+        return Ok(not_party(&req.buyer));
+    }
+
+    let deal_id = req
+        .deal_id
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let mut storage = storage.lock().unwrap();
+    if storage.get_deal(&deal_id).is_some() {
+        return Ok(error_reply(
+            StatusCode::CONFLICT,
+            format!("Deal {} already exists", deal_id),
+        ));
+    }
+
+    let deal = Deal::new(
+        deal_id,
+        req.buyer,
+        req.seller,
+        req.target_amount,
+        req.currency,
+        req.deadline,
+    );
+    storage.add_deal(deal.clone());
+
+    Ok(warp::reply::with_status(json(&deal), StatusCode::CREATED).into_response())
+}
+
+// Deals the caller is a party to, and every open deal's terms for lending banks
+pub async fn handle_list_deals(
+    caller: Principal,
     storage: Arc<Mutex<Storage>>,
 ) -> Result<impl Reply, Infallible> {
-    println!("Handling verify request for deal {}", req.deal_id);
+    let storage = storage.lock().unwrap();
+    let deals = storage
+        .get_deals()
+        .into_iter()
+        .filter_map(|deal| deal_view(deal, &caller))
+        .collect();
+
+    Ok(json(&DealsResponse { deals }))
+}
 
-    // Lock storage for reading proof
+pub async fn handle_get_deal(
+    deal_id: String,
+    caller: Principal,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<impl Reply, Infallible> {
     let storage = storage.lock().unwrap();
-    match storage.get_proof(&req.deal_id) {
-        Some(receipt) => {
-            println!("Found proof for deal {}", req.deal_id);
-            match receipt.verify(rwz_pof_core::RWZ_POF_GUEST_ID) {
-                Ok(()) => {
-                    println!("Proof verified successfully");
-                    match receipt.journal.decode::<(DealInfo, u64)>() {
-                        Ok((deal_info, verified_amount)) => {
-                            println!("Decoded deal info - verified amount: {}", verified_amount);
-                            Ok(json(&VerifyResponse {
-                                verified: true,
-                                deal_info: Some(deal_info),
-                            }))
-                        }
-                        Err(e) => {
-                            println!("Error decoding proof data: {}", e);
-                            Ok(json(&ErrorResponse {
-                                error: format!("Failed to decode proof data: {}", e),
-                            }))
-                        }
-                    }
-                }
-                Err(e) => {
-                    println!("Error verifying proof: {}", e);
-                    Ok(json(&ErrorResponse {
-                        error: format!("Proof verification failed: {}", e),
-                    }))
-                }
-            }
+    match storage.get_deal(&deal_id) {
+        Some(deal) => match deal_view(deal, &caller) {
+            Some(deal) => Ok(json(&deal).into_response()),
+            None => Ok(not_party(&deal.buyer)),
+        },
+        None => Ok(error_reply(
+            StatusCode::NOT_FOUND,
+            format!("Deal {} not found", deal_id),
+        )),
+    }
+}
+
+// The deal as the caller may see it: in full for its buyer and seller, without
+// the bid rounds for lending banks, and not at all for anyone else
+fn deal_view(deal: &Deal, caller: &Principal) -> Option<Deal> {
+    if caller.role.is_buyer(&deal.buyer) || caller.role.is_seller(&deal.seller) {
+        Some(deal.clone())
+    } else if matches!(caller.role, Role::LendingBank { .. }) {
+        Some(Deal {
+            rounds: Vec::new(),
+            ..deal.clone()
+        })
+    } else {
+        None
+    }
+}
+
+// Adds a proof as the deal's next bid round, a revision of the latest round
+pub async fn handle_submit_round(
+    deal_id: String,
+    caller: Principal,
+    req: SubmitRoundRequest,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<impl Reply, Infallible> {
+    println!("Submitting bid round for deal {}", deal_id);
+
+    let mut storage = storage.lock().unwrap();
+    if let Some(reply) = buyer_refusal(&storage, &deal_id, &caller) {
+        return Ok(reply);
+    }
+
+    let verified = match (req.proof_id.as_deref(), req.bundle) {
+        (Some(proof_id), None) => {
+            let Some(proof) = storage.get_proof(proof_id).filter(|p| p.deal_id == deal_id) else {
+                return Ok(error_reply(
+                    StatusCode::NOT_FOUND,
+                    format!("Proof {} not found for deal {}", proof_id, deal_id),
+                ));
+            };
+            verify_receipt(&proof.receipt, &local_policy()).map(|(version, journal)| {
+                VerifiedDeal::new(
+                    &version,
+                    journal,
+                    journal_digest(&proof.receipt),
+                    Some(proof.created_at.unix_timestamp() as u64),
+                    None,
+                )
+            })
         }
-        None => {
-            println!("No proof found for deal {}", req.deal_id);
-            Ok(json(&ErrorResponse {
-                error: format!("No proof found for deal {}", req.deal_id),
-            }))
+        (None, Some(bundle)) => verify_bundle(&bundle, &local_policy()),
+        _ => {
+            return Ok(error_reply(
+                StatusCode::BAD_REQUEST,
+                "Provide exactly one of proof_id or bundle".to_string(),
+            ))
+        }
+    };
+    let verified = match verified {
+        Ok(verified) => verified,
+        Err(e) => {
+            println!("Bid round proof failed verification: {}", e);
+            return Ok(error_reply(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Proof verification failed: {}", e),
+            ));
+        }
+    };
+
+    match storage.add_bid_round(&deal_id, &verified, req.proof_id) {
+        Ok(round) => {
+            println!(
+                "Deal {} round {}: {}",
+                deal_id, round.round, round.verified_amount
+            );
+            Ok(warp::reply::with_status(json(&round), StatusCode::CREATED).into_response())
+        }
+        Err(error) => {
+            println!("Rejecting bid round for deal {}: {}", deal_id, error);
+            Ok(error_reply(StatusCode::UNPROCESSABLE_ENTITY, error))
         }
     }
 }
 
-pub async fn handle_create_proof_job(
-    req: CreateProofJobRequest,
+pub async fn handle_list_rounds(
+    deal_id: String,
+    caller: Principal,
     storage: Arc<Mutex<Storage>>,
-    proof_worker: Arc<ProofWorker>,
 ) -> Result<impl Reply, Infallible> {
-    println!("Creating proof job for deal {}", req.deal_id);
+    match storage.lock().unwrap().get_deal(&deal_id) {
+        Some(deal)
+            if !caller.role.is_buyer(&deal.buyer) && !caller.role.is_seller(&deal.seller) =>
+        {
+            Ok(not_party(&deal.seller))
+        }
+        Some(deal) => Ok(json(&DealRoundsResponse {
+            rounds: deal.rounds.clone(),
+            deal_id,
+        })
+        .into_response()),
+        None => Ok(error_reply(
+            StatusCode::NOT_FOUND,
+            format!("Deal {} not found", deal_id),
+        )),
+    }
+}
 
-    let job = {
-        let mut storage = storage.lock().unwrap();
-        storage.create_proof_job(req.deal_id.clone(), req.required_amount)
-    };
+pub async fn handle_update_deal(
+    deal_id: String,
+    caller: Principal,
+    req: UpdateDealRequest,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<impl Reply, Infallible> {
+    println!("Updating deal {}: {:?}", deal_id, req);
 
-    // Spawn background task to generate proof
-    let storage_clone = Arc::clone(&storage);
-    let job_id = job.id.clone();
+    let mut storage = storage.lock().unwrap();
+    if let Some(reply) = buyer_refusal(&storage, &deal_id, &caller) {
+        return Ok(reply);
+    }
+    if let Some(deal) = storage.get_deal(&deal_id) {
+        if !deal.state.accepts_updates() {
+            return Ok(error_reply(
+                StatusCode::CONFLICT,
+                format!(
+                    "Deal {} is {:?} and can no longer change",
+                    deal_id, deal.state
+                ),
+            ));
+        }
+    }
 
-    tokio::spawn(async move {
-        proof_worker.process_job(job_id, storage_clone).await;
+    let deal = storage.update_deal(&deal_id, |deal| {
+        if let Some(seller) = &req.seller {
+            deal.seller = seller.clone();
+        }
+        if let Some(target_amount) = req.target_amount {
+            deal.target_amount = target_amount;
+        }
+        if let Some(currency) = &req.currency {
+            deal.currency = currency.clone();
+        }
+        if req.deadline.is_some() {
+            deal.deadline = req.deadline;
+        }
     });
 
-    Ok(json(&CreateProofJobResponse { job_id: job.id }))
+    match deal {
+        Some(deal) => Ok(json(&deal).into_response()),
+        None => Ok(error_reply(
+            StatusCode::NOT_FOUND,
+            format!("Deal {} not found", deal_id),
+        )),
+    }
 }
 
-pub async fn handle_get_proof_job(
-    job_id: String,
+pub async fn handle_transition_deal(
+    deal_id: String,
+    caller: Principal,
+    req: DealTransitionRequest,
     storage: Arc<Mutex<Storage>>,
 ) -> Result<impl Reply, Infallible> {
-    let storage = storage.lock().unwrap();
+    if !matches!(
+        req.state,
+        DealState::Submitted | DealState::Closed | DealState::Cancelled
+    ) {
+        return Ok(error_reply(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Deals become {:?} through commitments and proofs",
+                req.state
+            ),
+        ));
+    }
 
-    match storage.get_proof_job_with_receipt(&job_id) {
-        Some((job, _receipt)) => Ok(json(&GetProofJobResponse {
-            status: job.status.clone(),
-            created_at: job.created_at,
-            updated_at: job.updated_at,
-            proof: job.proof.map(|(deal_info, verified_amount)| ProofResponse {
-                success: true,
-                verified_amount,
-                deal_info,
-            }),
-            error: job.error.clone(),
-        })),
-        None => Ok(json(&ErrorResponse {
-            error: format!("Proof job not found: {}", job_id),
-        })),
+    transition_deal(&deal_id, &caller, req.state, req.reason, &storage)
+}
+
+// DELETE cancels the deal, its history is kept
+pub async fn handle_cancel_deal(
+    deal_id: String,
+    caller: Principal,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<impl Reply, Infallible> {
+    transition_deal(&deal_id, &caller, DealState::Cancelled, None, &storage)
+}
+
+fn transition_deal(
+    deal_id: &str,
+    caller: &Principal,
+    to: DealState,
+    reason: Option<String>,
+    storage: &Arc<Mutex<Storage>>,
+) -> Result<Response, Infallible> {
+    println!("Moving deal {} to {:?}", deal_id, to);
+
+    let mut storage = storage.lock().unwrap();
+    if let Some(reply) = buyer_refusal(&storage, deal_id, caller) {
+        return Ok(reply);
+    }
+
+    match storage.transition_deal(deal_id, to, reason) {
+        Ok(deal) => Ok(json(&deal).into_response()),
+        Err(error) => Ok(error_reply(StatusCode::CONFLICT, error)),
+    }
+}
+
+// The amount to prove: at least the seller's, if it signed a requirement for
+// this deal, and the seller's minimum unless the buyer bids more
+fn resolve_required_amount(
+    deal_id: &str,
+    required_amount: Option<u64>,
+    requirement: &Option<SignedRequirement>,
+) -> Result<u64, String> {
+    let Some(signed) = requirement else {
+        return required_amount
+            .ok_or_else(|| "Either required_amount or requirement is needed".to_string());
+    };
+
+    signed
+        .verify()
+        .map_err(|e| format!("Invalid requirement: {}", e))?;
+    if signed.requirement.deal_id != deal_id {
+        return Err(format!(
+            "Requirement is for deal {}, not {}",
+            signed.requirement.deal_id, deal_id
+        ));
+    }
+    match required_amount {
+        Some(amount) if amount < signed.requirement.amount => Err(format!(
+            "Required amount {} is below the seller's requirement of {}",
+            amount, signed.requirement.amount
+        )),
+        Some(amount) => Ok(amount),
+        None => Ok(signed.requirement.amount),
+    }
+}
+
+// Journal of the deal's stored proof a revision raises
+fn previous_journal(
+    storage: &Arc<Mutex<Storage>>,
+    deal_id: &str,
+    revises: Option<&str>,
+) -> Result<Option<Vec<u8>>, String> {
+    let Some(proof_id) = revises else {
+        return Ok(None);
+    };
+    match storage.lock().unwrap().get_proof(proof_id) {
+        Some(proof) if proof.deal_id == deal_id => Ok(Some(proof.receipt.journal.bytes.clone())),
+        _ => Err(format!("Proof {} not found for deal {}", proof_id, deal_id)),
+    }
+}
+
+// Commitments for a proof: the ones the buyer's bank named, or an automatic
+// selection covering the required amount. Either way they must pass validation.
+fn choose_commitments(
+    storage: &Arc<Mutex<Storage>>,
+    deal_id: &str,
+    commitment_ids: Option<&[String]>,
+    selection: &SelectionStrategy,
+    required_amount: u64,
+) -> Result<Vec<(SelectedCommitment, SignedMessage)>, Box<Response>> {
+    let mut storage = storage.lock().unwrap();
+
+    match storage.get_deal(deal_id).map(Deal::check_proofs_allowed) {
+        Some(Ok(())) => {}
+        Some(Err(error)) => return Err(Box::new(error_reply(StatusCode::CONFLICT, error))),
+        None => {
+            return Err(Box::new(error_reply(
+                StatusCode::NOT_FOUND,
+                format!("Deal {} not found", deal_id),
+            )))
+        }
+    }
+
+    let selected = match commitment_ids {
+        Some(ids) => match storage.find_commitments(deal_id, ids) {
+            Some(selected) => ids.iter().cloned().zip(selected).collect(),
+            None => {
+                println!("Inactive commitment IDs for deal {}: {:?}", deal_id, ids);
+                return Err(Box::new(error_reply(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!("Unknown or inactive commitment IDs for deal {}", deal_id),
+                )));
+            }
+        },
+        None => {
+            let commitments = storage.get_active_commitments(deal_id);
+            let indices =
+                select_commitments(&commitments, required_amount, selection).map_err(|issue| {
+                    println!("No commitment selection for deal {}: {}", deal_id, issue);
+                    Box::new(validation_reply("No commitment selection", &[issue]))
+                })?;
+
+            let mut selected = Vec::with_capacity(indices.len());
+            for index in indices {
+                let id = commitment_hash(&commitments[index]).map_err(|e| {
+                    Box::new(error_reply(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to hash commitment: {}", e),
+                    ))
+                })?;
+                selected.push((id, commitments[index].clone()));
+            }
+            selected
+        }
+    };
+
+    let messages: Vec<_> = selected.iter().map(|(_, signed)| signed.clone()).collect();
+    let issues = validate_commitments(&messages, Some(required_amount));
+    if !issues.is_empty() {
+        println!("Commitments failed validation: {:?}", issues);
+        return Err(Box::new(validation_reply("Invalid commitments", &issues)));
+    }
+
+    Ok(selected
+        .into_iter()
+        .map(|(commitment_id, signed)| {
+            (
+                SelectedCommitment {
+                    commitment_id,
+                    amount: signed.message.amount,
+                },
+                signed,
+            )
+        })
+        .collect())
+}
+
+// Stored proofs can only be verified by the deal's seller, uploads by any
+// seller's bank
+pub async fn handle_verify(
+    caller: Principal,
+    req: VerifyRequest,
+    storage: Arc<Mutex<Storage>>,
+    webhooks: Arc<Webhooks>,
+) -> Result<impl Reply, Infallible> {
+    let params = UploadParams {
+        policy_id: req.policy_id,
+        bid_amount: req.bid_amount,
+    };
+
+    let artifact = match (req.deal_id, req.proof_id, req.bundle, req.receipt) {
+        (None, None, Some(bundle), None) => ProofArtifact::Bundle(bundle),
+        (None, None, None, Some(receipt)) => ProofArtifact::Receipt(receipt),
+        (Some(deal_id), None, None, None) => {
+            println!("Handling verify request for deal {}", deal_id);
+            let storage = storage.lock().unwrap();
+            if let Some(reply) = seller_refusal(&storage, &deal_id, &caller) {
+                return Ok(reply);
+            }
+            match storage.get_latest_proof(&deal_id) {
+                Some(proof) => ProofArtifact::Receipt(proof.receipt.clone()),
+                None => {
+                    println!("No proof found for deal {}", deal_id);
+                    return Ok(error_reply(
+                        StatusCode::NOT_FOUND,
+                        format!("No proof found for deal {}", deal_id),
+                    ));
+                }
+            }
+        }
+        (None, Some(proof_id), None, None) => {
+            println!("Handling verify request for proof {}", proof_id);
+            let storage = storage.lock().unwrap();
+            match storage.get_proof(&proof_id) {
+                Some(proof) => {
+                    if let Some(reply) = seller_refusal(&storage, &proof.deal_id, &caller) {
+                        return Ok(reply);
+                    }
+                    ProofArtifact::Receipt(proof.receipt.clone())
+                }
+                None => {
+                    println!("Proof not found: {}", proof_id);
+                    return Ok(error_reply(
+                        StatusCode::NOT_FOUND,
+                        format!("Proof not found: {}", proof_id),
+                    ));
+                }
+            }
+        }
+        _ => {
+            return Ok(error_reply(
+                StatusCode::BAD_REQUEST,
+                "Provide exactly one of deal_id, proof_id, bundle or receipt".to_string(),
+            ))
+        }
+    };
+
+    Ok(verify_artifact(artifact, params, &storage, &webhooks))
+}
+
+// Binary upload: a .pof bundle or a bincode receipt, parameters in the query string
+pub async fn handle_verify_upload(
+    caller: Principal,
+    params: UploadParams,
+    body: bytes::Bytes,
+    storage: Arc<Mutex<Storage>>,
+    webhooks: Arc<Webhooks>,
+) -> Result<impl Reply, Infallible> {
+    println!(
+        "Handling verify request from {} for an uploaded proof ({} bytes)",
+        caller.subject,
+        body.len()
+    );
+
+    match ProofArtifact::from_bytes(&body) {
+        Ok(artifact) => Ok(verify_artifact(artifact, params, &storage, &webhooks)),
+        Err(error) => {
+            println!("Rejecting upload: {}", error);
+            Ok(error_reply(StatusCode::BAD_REQUEST, error))
+        }
+    }
+}
+
+pub async fn handle_verify_form(
+    caller: Principal,
+    form: FormData,
+    storage: Arc<Mutex<Storage>>,
+    webhooks: Arc<Webhooks>,
+) -> Result<impl Reply, Infallible> {
+    println!(
+        "Handling verify request from {} for a proof uploaded as a form",
+        caller.subject
+    );
+
+    match ProofArtifact::from_form(form).await {
+        Ok((artifact, params)) => Ok(verify_artifact(artifact, params, &storage, &webhooks)),
+        Err(error) => {
+            println!("Rejecting upload: {}", error);
+            Ok(error_reply(StatusCode::BAD_REQUEST, error))
+        }
+    }
+}
+
+// Verifies a proof against the accepted guest versions and registry, and
+// evaluates the requested acceptance policy. Needs no stored state for the deal.
+// The result goes to webhook subscribers, of the deal if the proof verified.
+fn verify_artifact(
+    artifact: ProofArtifact,
+    params: UploadParams,
+    storage: &Arc<Mutex<Storage>>,
+    webhooks: &Arc<Webhooks>,
+) -> Response {
+    let policy = match &params.policy_id {
+        Some(policy_id) => match storage.lock().unwrap().get_policy(policy_id) {
+            Some(policy) => Some(policy.clone()),
+            None => {
+                println!("Unknown policy {}", policy_id);
+                return error_reply(
+                    StatusCode::NOT_FOUND,
+                    format!("Unknown policy {}", policy_id),
+                );
+            }
+        },
+        None => None,
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    let verified = match artifact {
+        ProofArtifact::Bundle(bundle) => verify_bundle(&bundle, &local_policy()),
+        // Tries every accepted guest version, so receipts from before an upgrade still verify
+        ProofArtifact::Receipt(receipt) => {
+            verify_receipt(&receipt, &local_policy()).map(|(version, journal)| {
+                VerifiedDeal::new(&version, journal, journal_digest(&receipt), None, None)
+            })
+        }
+    };
+
+    match verified {
+        Ok(verified) => {
+            println!(
+                "Proof verified successfully with guest {} - deal {}, verified amount: {}",
+                verified.guest_version, verified.deal_info.deal_id, verified.verified_amount
+            );
+
+            let verdict = policy.map(|policy| policy.evaluate(&verified, params.bid_amount, now));
+            if let Some(verdict) = &verdict {
+                println!(
+                    "Policy {} {}",
+                    verdict.policy_id,
+                    if verdict.accepted {
+                        "accepted"
+                    } else {
+                        "rejected"
+                    }
+                );
+            }
+            webhooks.notify(
+                WebhookEventType::VerificationCompleted,
+                Some(&verified.deal_info.deal_id),
+                json!({
+                    "verified": true,
+                    "buyer": verified.deal_info.buyer,
+                    "verified_amount": verified.verified_amount,
+                    "guest_version": verified.guest_version,
+                    "policy_id": verdict.as_ref().map(|verdict| &verdict.policy_id),
+                    "accepted": verdict.as_ref().map(|verdict| verdict.accepted),
+                }),
+            );
+
+            json(&VerifyResponse {
+                verified: true,
+                deal_info: Some(verified.deal_info),
+                guest_version: Some(verified.guest_version),
+                verdict,
+            })
+            .into_response()
+        }
+        Err(e) => {
+            println!("Error verifying proof: {}", e);
+            let error = format!("Proof verification failed: {}", e);
+            webhooks.notify(
+                WebhookEventType::VerificationCompleted,
+                None,
+                json!({ "verified": false, "error": error }),
+            );
+            error_reply(StatusCode::UNPROCESSABLE_ENTITY, error)
+        }
+    }
+}
+
+pub async fn handle_create_proof_job(
+    caller: Principal,
+    req: CreateProofJobRequest,
+    storage: Arc<Mutex<Storage>>,
+    proof_worker: Arc<ProofWorker>,
+) -> Result<Response, Infallible> {
+    println!("Creating proof job for deal {}", req.deal_id);
+
+    if let Some(reply) = buyer_refusal(&storage.lock().unwrap(), &req.deal_id, &caller) {
+        return Ok(reply);
+    }
+
+    let required_amount =
+        match resolve_required_amount(&req.deal_id, req.required_amount, &req.requirement) {
+            Ok(amount) => amount,
+            Err(error) => return Ok(error_reply(StatusCode::BAD_REQUEST, error)),
+        };
+    let previous_journal = match previous_journal(&storage, &req.deal_id, req.revises.as_deref()) {
+        Ok(journal) => journal,
+        Err(error) => return Ok(error_reply(StatusCode::NOT_FOUND, error)),
+    };
+
+    let selected = match choose_commitments(
+        &storage,
+        &req.deal_id,
+        req.commitment_ids.as_deref(),
+        &req.selection,
+        required_amount,
+    ) {
+        Ok(selected) => selected,
+        Err(reply) => return Ok(*reply),
+    };
+    let (chosen, selected): (Vec<_>, Vec<_>) = selected.into_iter().unzip();
+
+    let options = ProofOptions {
+        receipt_kind: req.receipt_kind,
+        recipient: req.recipient,
+        challenge: req.challenge,
+        requirement: req.requirement,
+        previous_journal,
+    };
+
+    // Execute without proving first so bad jobs are rejected up front. The
+    // executor is CPU-bound too, so it also runs off the async worker threads.
+    let preflight = {
+        let options = options.clone();
+        tokio::task::spawn_blocking(move || preflight(&selected, required_amount, &options))
+    };
+    let report = match preflight.await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(e) => Err(format!("Preflight task failed: {}", e)),
+    };
+    let report = match report {
+        Ok(report) if report.passed => report,
+        Ok(report) => {
+            let reason = report.failure.unwrap_or_default();
+            println!("Preflight failed: {}", reason);
+            return Ok(error_reply(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Preflight failed: {}", reason),
+            ));
+        }
+        Err(e) => {
+            println!("Error running preflight: {}", e);
+            return Ok(error_reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to run preflight: {}", e),
+            ));
+        }
+    };
+
+    println!(
+        "Preflight passed: {} cycles in {} segments, ~{}s to prove",
+        report.cycles, report.segments, report.estimated_proving_secs
+    );
+
+    let job = {
+        let mut storage = storage.lock().unwrap();
+        storage.create_proof_job(
+            req.deal_id.clone(),
+            required_amount,
+            options,
+            chosen.iter().map(|c| c.commitment_id.clone()).collect(),
+        )
+    };
+
+    proof_worker.events.publish(&job.id, JobEventKind::Queued);
+    proof_worker.events.publish(
+        &job.id,
+        JobEventKind::PreflightPassed {
+            preflight: report.clone(),
+        },
+    );
+
+    // Spawn background task to generate proof
+    let storage_clone = Arc::clone(&storage);
+    let job_id = job.id.clone();
+
+    tokio::spawn(async move {
+        proof_worker.process_job(job_id, storage_clone).await;
+    });
+
+    Ok(json(&CreateProofJobResponse {
+        job_id: job.id,
+        preflight: report,
+        commitments: chosen,
+    })
+    .into_response())
+}
+
+pub async fn handle_get_proof_job(
+    job_id: String,
+    caller: Principal,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<impl Reply, Infallible> {
+    let storage = storage.lock().unwrap();
+
+    match storage.get_proof_job(&job_id) {
+        Some(job) => {
+            if let Some(reply) = buyer_refusal(&storage, &job.deal_id, &caller) {
+                return Ok(reply);
+            }
+            Ok(json(&GetProofJobResponse {
+                status: job.status.clone(),
+                receipt_kind: job.options.receipt_kind,
+                commitment_ids: job.commitment_ids.clone(),
+                created_at: job.created_at,
+                updated_at: job.updated_at,
+                proof: job.proof.clone().zip(job.proof_id.clone()).map(
+                    |((deal_info, verified_amount), proof_id)| ProofResponse {
+                        success: true,
+                        proof_id,
+                        verified_amount,
+                        deal_info,
+                        commitments: Vec::new(),
+                    },
+                ),
+                receipt_size: job.receipt_size,
+                error: job.error.clone(),
+            })
+            .into_response())
+        }
+        None => Ok(error_reply(
+            StatusCode::NOT_FOUND,
+            format!("Proof job not found: {}", job_id),
+        )),
+    }
+}
+
+// Streams a proof job's events as Server-Sent Events: those so far, then live
+// ones until the job completes or fails
+pub async fn handle_proof_job_events(
+    job_id: String,
+    caller: Principal,
+    storage: Arc<Mutex<Storage>>,
+    proof_worker: Arc<ProofWorker>,
+) -> Result<Response, Infallible> {
+    {
+        let storage = storage.lock().unwrap();
+        if let Some(job) = storage.get_proof_job(&job_id) {
+            if let Some(reply) = buyer_refusal(&storage, &job.deal_id, &caller) {
+                return Ok(reply);
+            }
+        }
+    }
+    let Some((history, receiver)) = proof_worker.events.subscribe(&job_id) else {
+        return Ok(error_reply(
+            StatusCode::NOT_FOUND,
+            format!("Job {} not found", job_id),
+        ));
+    };
+    println!("Streaming events of job {}", job_id);
+
+    let finished = history.iter().any(|event| event.kind.is_final());
+    let live = futures_util::stream::unfold(
+        (receiver, finished),
+        |(mut receiver, finished)| async move {
+            if finished {
+                return None;
+            }
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => {
+                        let finished = event.kind.is_final();
+                        return Some((event, (receiver, finished)));
+                    }
+                    // Missed events stay in the history, a reconnect replays them
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        },
+    );
+    let events = futures_util::stream::iter(history)
+        .chain(live)
+        .map(|event| Ok::<_, Infallible>(sse_event(&event)));
+
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)).into_response())
+}
+
+fn sse_event(event: &JobEvent) -> warp::sse::Event {
+    warp::sse::Event::default()
+        .id(event.id.to_string())
+        .event(event.kind.name())
+        .json_data(event)
+        .unwrap_or_else(|e| warp::sse::Event::default().comment(e.to_string()))
+}
+
+pub async fn handle_list_deal_proofs(
+    deal_id: String,
+    caller: Principal,
+    query: ProofHistoryQuery,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<impl Reply, Infallible> {
+    if !(1..=MAX_PAGE_SIZE).contains(&query.limit) {
+        return Ok(error_reply(
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {}", MAX_PAGE_SIZE),
+        ));
+    }
+
+    let storage = storage.lock().unwrap();
+    if let Some(reply) = buyer_refusal(&storage, &deal_id, &caller) {
+        return Ok(reply);
+    }
+    let proofs = storage.get_deal_proofs(&deal_id);
+    let total = proofs.len();
+    let proofs = proofs
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit)
+        .map(ProofSummary::from)
+        .collect();
+
+    Ok(json(&DealProofsResponse {
+        deal_id,
+        total,
+        offset: query.offset,
+        proofs,
+    })
+    .into_response())
+}
+
+// Proof artifacts available for download
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArtifactKind {
+    Receipt,
+    Bundle,
+}
+
+impl std::str::FromStr for ArtifactKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "receipt" => Ok(Self::Receipt),
+            "bundle" => Ok(Self::Bundle),
+            _ => Err(()),
+        }
+    }
+}
+
+// JSON if the client asks for it, otherwise the binary format (bincode receipt or .pof bundle)
+pub async fn handle_get_proof_artifact(
+    proof_id: String,
+    kind: ArtifactKind,
+    caller: Principal,
+    accept: Option<String>,
+    if_none_match: Option<String>,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<Response, Infallible> {
+    let proof = {
+        let storage = storage.lock().unwrap();
+        let Some(proof) = storage.get_proof(&proof_id) else {
+            return Ok(error_reply(
+                StatusCode::NOT_FOUND,
+                format!("Proof not found: {}", proof_id),
+            ));
+        };
+        if let Some(reply) = buyer_refusal(&storage, &proof.deal_id, &caller) {
+            return Ok(reply);
+        }
+        proof.clone()
+    };
+    let receipt = proof.receipt;
+
+    let digest = match receipt_digest(&receipt) {
+        Ok(digest) => digest,
+        Err(e) => {
+            return Ok(error_reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to hash receipt: {}", e),
+            ))
+        }
+    };
+
+    let as_json = accept.is_some_and(|accept| accept.contains("application/json"));
+    let etag = format!(
+        "\"{}-{}\"",
+        digest,
+        match (kind, as_json) {
+            (ArtifactKind::Receipt, false) => "receipt",
+            (ArtifactKind::Receipt, true) => "receipt-json",
+            (ArtifactKind::Bundle, false) => "bundle",
+            (ArtifactKind::Bundle, true) => "bundle-json",
+        }
+    );
+
+    if if_none_match.is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag)) {
+        return Ok(warp::http::Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header(header::ETAG, etag)
+            .body(Default::default())
+            .unwrap());
+    }
+
+    let body = match kind {
+        ArtifactKind::Receipt if as_json => serde_json::to_vec(&receipt).map_err(|e| e.to_string()),
+        ArtifactKind::Receipt => bincode::serialize(&receipt).map_err(|e| e.to_string()),
+        ArtifactKind::Bundle => create_bundle(receipt)
+            .map(|mut bundle| {
+                // Stamped with the proof's creation time so the same proof always
+                // downloads as the same bytes
+                bundle.created_at = proof.created_at.unix_timestamp() as u64;
+                bundle
+            })
+            .map_err(|e| e.to_string())
+            .and_then(|bundle| {
+                if as_json {
+                    serde_json::to_vec(&bundle).map_err(|e| e.to_string())
+                } else {
+                    bundle.to_bytes().map_err(|e| e.to_string())
+                }
+            }),
+    };
+
+    match body {
+        Ok(body) => {
+            let mut response = warp::http::Response::builder()
+                .header(header::ETAG, etag)
+                .header(
+                    header::CONTENT_TYPE,
+                    if as_json {
+                        "application/json"
+                    } else {
+                        "application/octet-stream"
+                    },
+                );
+            if kind == ArtifactKind::Bundle && !as_json {
+                response = response.header(
+                    header::CONTENT_DISPOSITION,
+                    format!(
+                        "attachment; filename=\"{}.{}\"",
+                        proof.deal_id, BUNDLE_EXTENSION
+                    ),
+                );
+            }
+            Ok(response.body(body.into()).unwrap())
+        }
+        Err(e) => {
+            println!("Error building {:?} for proof {}: {}", kind, proof_id, e);
+            Ok(error_reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to build {:?}: {}", kind, e),
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateAuctionRequest {
+    deal_id: String,
+    seller: String,
+    min_amount: u64,
+    #[serde(default)]
+    registry_root: Option<String>, // Defaults to this server's registry
+    #[serde(with = "iso8601")]
+    deadline: OffsetDateTime,
+    bidders: Vec<String>, // One challenge nonce is issued per bidder
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubmitBidRequest {
+    bundle: ProofBundle,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuctionRankingResponse {
+    auction_id: String,
+    bids: Vec<RankedBid>,
+}
+
+// Everything the seller learned from the auction, for its records
+#[derive(Debug, Serialize)]
+pub struct AuctionExport {
+    auction: Auction,
+    ranking: Vec<RankedBid>,
+}
+
+pub async fn handle_create_auction(
+    caller: Principal,
+    req: CreateAuctionRequest,
+    storage: Arc<Mutex<Storage>>,
+    seller_key: Arc<SigningKey>,
+) -> Result<impl Reply, Infallible> {
+    println!("Creating auction: {:?}", req);
+
+    if !caller.role.is_seller(&req.seller) {
+        return Ok(not_party(&req.seller));
+    }
+
+    if req.bidders.is_empty() {
+        return Ok(error_reply(
+            StatusCode::BAD_REQUEST,
+            "An auction needs at least one bidder".to_string(),
+        ));
+    }
+
+    let auction = match Auction::new(
+        req.deal_id,
+        req.seller,
+        req.min_amount,
+        req.registry_root.unwrap_or_else(registry_root),
+        req.deadline,
+        req.bidders,
+        &seller_key,
+    ) {
+        Ok(auction) => auction,
+        Err(e) => {
+            println!("Error signing auction requirement: {}", e);
+            return Ok(error_reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to sign the financing requirement: {}", e),
+            ));
+        }
+    };
+    storage.lock().unwrap().add_auction(auction.clone());
+
+    Ok(warp::reply::with_status(json(&auction), StatusCode::CREATED).into_response())
+}
+
+// The seller's bank sees every bid and challenge, bidders only their own
+pub async fn handle_get_auction(
+    auction_id: String,
+    caller: Principal,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<impl Reply, Infallible> {
+    match storage.lock().unwrap().get_auction(&auction_id) {
+        Some(auction) if caller.role.is_seller(&auction.seller) => {
+            Ok(json(auction).into_response())
+        }
+        Some(auction) => {
+            let buyer = match &caller.role {
+                Role::BuyersBank { buyer } => Some(buyer.as_str()),
+                _ => None,
+            };
+            Ok(json(&auction.bidder_view(buyer)).into_response())
+        }
+        None => Ok(auction_not_found(&auction_id)),
+    }
+}
+
+pub async fn handle_submit_bid(
+    auction_id: String,
+    caller: Principal,
+    req: SubmitBidRequest,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<impl Reply, Infallible> {
+    Ok(submit_bid(&auction_id, &caller, req.bundle, &storage))
+}
+
+// Binary .pof bundle upload
+pub async fn handle_submit_bid_upload(
+    auction_id: String,
+    caller: Principal,
+    body: bytes::Bytes,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<impl Reply, Infallible> {
+    match ProofBundle::from_bytes(&body) {
+        Ok(bundle) => Ok(submit_bid(&auction_id, &caller, bundle, &storage)),
+        Err(e) => Ok(error_reply(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid proof bundle: {}", e),
+        )),
+    }
+}
+
+// Verifies a bid against the auction's registry and requirements, recording
+// it whatever the outcome unless its proof is for another buyer
+fn submit_bid(
+    auction_id: &str,
+    caller: &Principal,
+    bundle: ProofBundle,
+    storage: &Arc<Mutex<Storage>>,
+) -> Response {
+    let mut storage = storage.lock().unwrap();
+    let Some(auction) = storage.get_auction(auction_id) else {
+        return auction_not_found(auction_id);
+    };
+    if !auction.is_open(OffsetDateTime::now_utc()) {
+        return error_reply(
+            StatusCode::CONFLICT,
+            format!("Auction {} closed at {}", auction_id, auction.deadline),
+        );
+    }
+
+    let mut policy = local_policy();
+    policy.accepted_registry_roots = vec![auction.registry_root.clone()];
+
+    let bid = match verify_bundle(&bundle, &policy) {
+        Ok(verified) if !caller.role.is_buyer(&verified.deal_info.buyer) => {
+            return not_party(&verified.deal_info.buyer)
+        }
+        Ok(verified) => match auction.check_bid(&verified) {
+            Ok(()) => Bid::verified(&verified),
+            Err(error) => Bid::verified(&verified).with_rejection(error),
+        },
+        Err(e) => Bid::rejected(e.to_string()),
+    };
+    println!(
+        "Bid {} on auction {}: {:?} {}",
+        bid.id,
+        auction_id,
+        bid.status,
+        bid.error.as_deref().unwrap_or_default()
+    );
+
+    storage.update_auction(auction_id, |auction| auction.bids.push(bid.clone()));
+
+    let status = match bid.status {
+        BidStatus::Verified => StatusCode::CREATED,
+        BidStatus::Rejected => StatusCode::UNPROCESSABLE_ENTITY,
+    };
+    warp::reply::with_status(json(&bid), status).into_response()
+}
+
+pub async fn handle_auction_ranking(
+    auction_id: String,
+    caller: Principal,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<impl Reply, Infallible> {
+    match storage.lock().unwrap().get_auction(&auction_id) {
+        Some(auction) if !caller.role.is_seller(&auction.seller) => Ok(not_party(&auction.seller)),
+        Some(auction) => Ok(json(&AuctionRankingResponse {
+            auction_id,
+            bids: auction.ranking(),
+        })
+        .into_response()),
+        None => Ok(auction_not_found(&auction_id)),
+    }
+}
+
+// JSON by default, CSV with `Accept: text/csv`
+pub async fn handle_export_auction(
+    auction_id: String,
+    caller: Principal,
+    accept: Option<String>,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<impl Reply, Infallible> {
+    let Some(auction) = storage.lock().unwrap().get_auction(&auction_id).cloned() else {
+        return Ok(auction_not_found(&auction_id));
+    };
+    if !caller.role.is_seller(&auction.seller) {
+        return Ok(not_party(&auction.seller));
+    }
+
+    let wants_csv = accept.is_some_and(|accept| accept.contains("text/csv"));
+    let (body, content_type, extension) = if wants_csv {
+        (auction.to_csv(), "text/csv", "csv")
+    } else {
+        let export = AuctionExport {
+            ranking: auction.ranking(),
+            auction,
+        };
+        match serde_json::to_string_pretty(&export) {
+            Ok(body) => (body, "application/json", "json"),
+            Err(e) => {
+                return Ok(error_reply(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to export auction: {}", e),
+                ))
+            }
+        }
+    };
+
+    let response = warp::http::Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"auction-{}.{}\"",
+                auction_id, extension
+            ),
+        )
+        .body(body.into());
+    match response {
+        Ok(response) => Ok(response),
+        Err(e) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to export auction: {}", e),
+        )),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IssueKeyRequest {
+    #[serde(flatten)]
+    role: Role,
+    #[serde(default)]
+    label: Option<String>,
+}
+
+// The key itself is only ever returned here
+#[derive(Debug, Serialize)]
+pub struct IssueKeyResponse {
+    #[serde(flatten)]
+    record: ApiKey,
+    api_key: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeysResponse {
+    keys: Vec<ApiKey>,
+}
+
+pub async fn handle_issue_key(
+    req: IssueKeyRequest,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<impl Reply, Infallible> {
+    let (record, api_key) = ApiKey::issue(req.role, req.label);
+    println!("Issued API key {} for {:?}", record.id, record.role);
+    storage.lock().unwrap().add_api_key(record.clone());
+
+    Ok(warp::reply::with_status(
+        json(&IssueKeyResponse { record, api_key }),
+        StatusCode::CREATED,
+    ))
+}
+
+pub async fn handle_list_keys(storage: Arc<Mutex<Storage>>) -> Result<impl Reply, Infallible> {
+    let storage = storage.lock().unwrap();
+    let keys = storage.get_api_keys().into_iter().cloned().collect();
+    Ok(json(&ApiKeysResponse { keys }))
+}
+
+pub async fn handle_revoke_key(
+    key_id: String,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<impl Reply, Infallible> {
+    match storage.lock().unwrap().revoke_api_key(&key_id) {
+        Some(record) => {
+            println!("Revoked API key {}", key_id);
+            Ok(json(&record).into_response())
+        }
+        None => Ok(error_reply(
+            StatusCode::NOT_FOUND,
+            format!("API key {} not found", key_id),
+        )),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhookRequest {
+    url: String,
+    #[serde(default)]
+    deal_id: Option<String>, // Every deal if left out, for admins only
+    #[serde(default)]
+    events: Vec<WebhookEventType>, // Every event if empty
+}
+
+// The secret itself is only ever returned here
+#[derive(Debug, Serialize)]
+pub struct CreateWebhookResponse {
+    #[serde(flatten)]
+    subscription: WebhookSubscription,
+    secret: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebhooksResponse {
+    webhooks: Vec<WebhookSubscription>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeadLettersResponse {
+    dead_letters: Vec<DeadLetter>,
+}
+
+pub async fn handle_create_webhook(
+    caller: Principal,
+    req: CreateWebhookRequest,
+    storage: Arc<Mutex<Storage>>,
+    webhooks: Arc<Webhooks>,
+) -> Result<impl Reply, Infallible> {
+    if let Err(error) = webhooks.check_target(&req.url).await {
+        return Ok(error_reply(StatusCode::BAD_REQUEST, error));
+    }
+    match &req.deal_id {
+        Some(deal_id) => match storage.lock().unwrap().get_deal(deal_id) {
+            None => {
+                return Ok(error_reply(
+                    StatusCode::NOT_FOUND,
+                    format!("Deal {} not found", deal_id),
+                ))
+            }
+            // Lending banks may finance any deal and hear about their own commitments
+            // to it, the others only follow their own deals
+            Some(deal)
+                if !matches!(caller.role, Role::LendingBank { .. })
+                    && !caller.role.is_buyer(&deal.buyer)
+                    && !caller.role.is_seller(&deal.seller) =>
+            {
+                return Ok(error_reply(
+                    StatusCode::FORBIDDEN,
+                    format!("This principal isn't a party to deal {}", deal_id),
+                ))
+            }
+            Some(_) => {}
+        },
+        None if caller.role != Role::Admin => {
+            return Ok(error_reply(
+                StatusCode::FORBIDDEN,
+                "Only admins can subscribe to every deal".to_string(),
+            ))
+        }
+        None => {}
+    }
+
+    let subscription = WebhookSubscription::new(
+        req.url,
+        req.deal_id,
+        req.events,
+        caller.subject,
+        caller.role,
+    );
+    println!(
+        "Webhook {} to {} for {:?}",
+        subscription.id, subscription.url, subscription.deal_id
+    );
+    webhooks.subscribe(subscription.clone());
+
+    Ok(warp::reply::with_status(
+        json(&CreateWebhookResponse {
+            secret: subscription.secret.clone(),
+            subscription,
+        }),
+        StatusCode::CREATED,
+    )
+    .into_response())
+}
+
+// Admins see every subscription, others their own
+pub async fn handle_list_webhooks(
+    caller: Principal,
+    webhooks: Arc<Webhooks>,
+) -> Result<impl Reply, Infallible> {
+    let webhooks = webhooks
+        .get_subscriptions()
+        .into_iter()
+        .filter(|subscription| caller.role == Role::Admin || subscription.owner == caller.subject)
+        .collect();
+    Ok(json(&WebhooksResponse { webhooks }))
+}
+
+pub async fn handle_delete_webhook(
+    webhook_id: String,
+    caller: Principal,
+    webhooks: Arc<Webhooks>,
+) -> Result<impl Reply, Infallible> {
+    match webhooks.get_subscription(&webhook_id) {
+        Some(subscription)
+            if caller.role == Role::Admin || subscription.owner == caller.subject =>
+        {
+            webhooks.unsubscribe(&webhook_id);
+            println!("Deleted webhook {}", webhook_id);
+            Ok(json(&subscription).into_response())
+        }
+        _ => Ok(error_reply(
+            StatusCode::NOT_FOUND,
+            format!("Webhook {} not found", webhook_id),
+        )),
+    }
+}
+
+pub async fn handle_list_dead_letters(webhooks: Arc<Webhooks>) -> Result<impl Reply, Infallible> {
+    Ok(json(&DeadLettersResponse {
+        dead_letters: webhooks.get_dead_letters(),
+    }))
+}
+
+// Turns authentication failures into JSON errors, leaving other rejections
+// to warp
+pub async fn handle_rejection(rejection: warp::Rejection) -> Result<Response, warp::Rejection> {
+    match rejection.find::<AuthError>() {
+        Some(AuthError::MissingKey) => Ok(error_reply(
+            StatusCode::UNAUTHORIZED,
+            "Missing API key, send it as \"Authorization: Bearer <key>\"".to_string(),
+        )),
+        Some(AuthError::InvalidKey) => Ok(error_reply(
+            StatusCode::UNAUTHORIZED,
+            "Unknown or revoked API key".to_string(),
+        )),
+        Some(AuthError::InvalidToken(reason)) => Ok(error_reply(
+            StatusCode::UNAUTHORIZED,
+            format!("Invalid bearer token: {}", reason),
+        )),
+        Some(AuthError::Forbidden(access)) => Ok(error_reply(
+            StatusCode::FORBIDDEN,
+            format!("This route needs a {:?} key", access),
+        )),
+        None => Err(rejection),
+    }
+}
+
+// The refusal, if any, when the caller doesn't act for the deal's buyer
+fn buyer_refusal(storage: &Storage, deal_id: &str, caller: &Principal) -> Option<Response> {
+    match storage.get_deal(deal_id) {
+        Some(deal) if caller.role.is_buyer(&deal.buyer) => None,
+        Some(deal) => Some(not_party(&deal.buyer)),
+        None => Some(error_reply(
+            StatusCode::NOT_FOUND,
+            format!("Deal {} not found", deal_id),
+        )),
+    }
+}
+
+// The refusal, if any, when the caller doesn't act for the deal's seller
+fn seller_refusal(storage: &Storage, deal_id: &str, caller: &Principal) -> Option<Response> {
+    match storage.get_deal(deal_id) {
+        Some(deal) if caller.role.is_seller(&deal.seller) => None,
+        Some(deal) => Some(not_party(&deal.seller)),
+        None => Some(error_reply(
+            StatusCode::NOT_FOUND,
+            format!("Deal {} not found", deal_id),
+        )),
+    }
+}
+
+fn not_party(party: &str) -> Response {
+    error_reply(
+        StatusCode::FORBIDDEN,
+        format!("This principal can't act for {}", party),
+    )
+}
+
+fn auction_not_found(auction_id: &str) -> Response {
+    error_reply(
+        StatusCode::NOT_FOUND,
+        format!("Auction {} not found", auction_id),
+    )
+}
+
+fn error_reply(status: StatusCode, error: String) -> Response {
+    warp::reply::with_status(json(&ErrorResponse { error }), status).into_response()
+}
+
+fn validation_reply(context: &str, issues: &[ValidationIssue]) -> Response {
+    warp::reply::with_status(
+        json(&ValidationErrorResponse::new(context, issues)),
+        StatusCode::UNPROCESSABLE_ENTITY,
+    )
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{authorize, Access, Authenticator};
+    use crate::webhooks::WebhookConfig;
+    use risc0_zkvm::{FakeReceipt, InnerReceipt, ReceiptClaim};
+    use rwz_pof_core::{
+        AcceptancePolicy, PofJournal, BUNDLE_MAGIC, RWZ_POF_COMMITMENT_ID, RWZ_POF_GUEST_ID,
+    };
+    use std::time::Duration;
+    use warp::Filter;
+
+    // Fake receipts only verify in dev mode, as on a server run with
+    // PROVER_MODE=dev
+    fn dev_mode() {
+        std::env::set_var("RISC0_DEV_MODE", "1");
+    }
+
+    // A receipt of the aggregation guest proving `amount` for DEAL1, without
+    // running the prover
+    fn fake_receipt(amount: u64) -> Receipt {
+        let journal = PofJournal {
+            deal_info: DealInfo {
+                amount,
+                deal_id: "DEAL1".to_string(),
+                buyer: "buyer123".to_string(),
+            },
+            verified_amount: amount,
+            commitment_image_id: RWZ_POF_COMMITMENT_ID,
+            bank_count: 2,
+            recipient: None,
+            challenge: None,
+            nullifiers: vec![[1; 32], [2; 32]],
+            requirement_hash: None,
+            previous_journal_digest: None,
+        };
+        let words = risc0_zkvm::serde::to_vec(&journal).unwrap();
+        let journal: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+
+        let claim = ReceiptClaim::ok(RWZ_POF_GUEST_ID, journal.clone());
+        Receipt::new(InnerReceipt::Fake(FakeReceipt::new(claim)), journal)
+    }
+
+    // DEAL1 between buyer123 and seller123, with an API key for each party
+    // and an outsider of each kind
+    fn storage() -> Arc<Mutex<Storage>> {
+        let mut storage = Storage::new();
+        storage.add_deal(Deal::new(
+            "DEAL1".to_string(),
+            "buyer123".to_string(),
+            "seller123".to_string(),
+            100,
+            "USD".to_string(),
+            None,
+        ));
+        for (key, role) in [
+            (
+                "buyer",
+                Role::BuyersBank {
+                    buyer: "buyer123".to_string(),
+                },
+            ),
+            (
+                "other_buyer",
+                Role::BuyersBank {
+                    buyer: "buyer456".to_string(),
+                },
+            ),
+            (
+                "seller",
+                Role::SellersBank {
+                    seller: "seller123".to_string(),
+                },
+            ),
+            ("lender", Role::LendingBank { bank_index: 0 }),
+        ] {
+            storage.add_api_key(ApiKey::from_key(key, role, None));
+        }
+        Arc::new(Mutex::new(storage))
+    }
+
+    fn body(response: &warp::http::Response<bytes::Bytes>) -> serde_json::Value {
+        serde_json::from_slice(response.body()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_verify_upload() {
+        dev_mode();
+        let storage = storage();
+        storage.lock().unwrap().add_policy(AcceptancePolicy {
+            id: "full-bid".to_string(),
+            min_amount_percent: Some(100),
+            registry_root: Some(registry_root()),
+            ..Default::default()
+        });
+        let auth = Arc::new(Authenticator::new(storage.clone(), None));
+        let webhooks = Arc::new(Webhooks::new(WebhookConfig::default()));
+
+        let routes = warp::post()
+            .and(warp::path!("sb" / "verify"))
+            .and(authorize(auth, Access::SellersBank))
+            .and(warp::query())
+            .and(warp::body::bytes())
+            .and(warp::any().map(move || storage.clone()))
+            .and(warp::any().map(move || webhooks.clone()))
+            .and_then(handle_verify_upload)
+            .recover(handle_rejection);
+        let upload = |key: &str, query: &str, body: Vec<u8>| {
+            warp::test::request()
+                .method("POST")
+                .path(&format!("/sb/verify{}", query))
+                .header("authorization", format!("Bearer {}", key))
+                .header("content-type", "application/octet-stream")
+                .body(body)
+                .reply(&routes)
+        };
+        let bundle = create_bundle(fake_receipt(60)).unwrap();
+
+        // Any seller's bank, no stored state needed
+        let response = upload("seller", "", bundle.to_bytes().unwrap()).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(body(&response)["verified"], true);
+        assert!(body(&response).get("verdict").is_none());
+        let receipt = bincode::serialize(&fake_receipt(60)).unwrap();
+        assert_eq!(upload("seller", "", receipt).await.status(), 200);
+        assert_eq!(
+            upload("buyer", "", bundle.to_bytes().unwrap())
+                .await
+                .status(),
+            403
+        );
+
+        // The selected policy's verdict, which may reject a verified proof
+        let response = upload(
+            "seller",
+            "?policy_id=full-bid&bid_amount=60",
+            bundle.to_bytes().unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(body(&response)["verdict"]["accepted"], true);
+        let response = upload(
+            "seller",
+            "?policy_id=full-bid&bid_amount=80",
+            bundle.to_bytes().unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(body(&response)["verdict"]["accepted"], false);
+        assert_eq!(body(&response)["verdict"]["rules"][0]["passed"], false);
+        let response = upload("seller", "?policy_id=unknown", bundle.to_bytes().unwrap()).await;
+        assert_eq!(response.status(), 404);
+
+        // Rejected bundles
+        assert_eq!(
+            upload("seller", "", b"RPOF garbage".to_vec())
+                .await
+                .status(),
+            400
+        );
+        let mut inflated = bundle.clone();
+        inflated.verified_amount = 600;
+        assert_eq!(
+            upload("seller", "", inflated.to_bytes().unwrap())
+                .await
+                .status(),
+            422
+        );
+        let mut unknown_guest = bundle;
+        unknown_guest.image_id = [9; 8];
+        assert_eq!(
+            upload("seller", "", unknown_guest.to_bytes().unwrap())
+                .await
+                .status(),
+            422
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proof_artifact_download() {
+        let storage = storage();
+        let proof = StoredProof::new(
+            "DEAL1".to_string(),
+            None,
+            60,
+            Vec::new(),
+            ProofOptions::default(),
+            fake_receipt(60),
+        );
+        let proof_id = proof.id.clone();
+        storage.lock().unwrap().add_proof(proof);
+        let auth = Arc::new(Authenticator::new(storage.clone(), None));
+
+        let routes = warp::get()
+            .and(warp::path!("proofs" / String / ArtifactKind))
+            .and(authorize(auth, Access::BuyersBank))
+            .and(warp::header::optional("accept"))
+            .and(warp::header::optional("if-none-match"))
+            .and(warp::any().map(move || storage.clone()))
+            .and_then(handle_get_proof_artifact)
+            .recover(handle_rejection);
+        let download = |key: &str, path: String| {
+            warp::test::request()
+                .path(&path)
+                .header("authorization", format!("Bearer {}", key))
+        };
+        let receipt_path = format!("/proofs/{}/receipt", proof_id);
+
+        let response = download("buyer", receipt_path.clone()).reply(&routes).await;
+        assert_eq!(response.status(), 200);
+        let receipt: Receipt = bincode::deserialize(response.body()).unwrap();
+        assert_eq!(receipt.journal.bytes, fake_receipt(60).journal.bytes);
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        // Unchanged since the last download
+        let response = download("buyer", receipt_path.clone())
+            .header("if-none-match", format!("\"other\", {}", etag))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 304);
+        assert!(response.body().is_empty());
+        assert_eq!(response.headers()[header::ETAG], etag.as_str());
+
+        // Each representation has its own tag
+        let response = download("buyer", receipt_path.clone())
+            .header("accept", "application/json")
+            .header("if-none-match", etag.as_str())
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert_ne!(response.headers()[header::ETAG], etag.as_str());
+        assert!(serde_json::from_slice::<Receipt>(response.body()).is_ok());
+
+        let response = download("buyer", format!("/proofs/{}/bundle", proof_id))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(response.body().starts_with(BUNDLE_MAGIC));
+
+        for (key, path, status) in [
+            ("other_buyer", receipt_path.clone(), 403),
+            ("seller", receipt_path, 403),
+            ("buyer", "/proofs/unknown/receipt".to_string(), 404),
+            ("buyer", format!("/proofs/{}/journal", proof_id), 404),
+        ] {
+            let response = download(key, path.clone()).reply(&routes).await;
+            assert_eq!(response.status(), status, "{} {}", key, path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proof_history_paging() {
+        let storage = storage();
+        let mut proof_ids = Vec::new();
+        for amount in [60, 70, 80] {
+            let proof = StoredProof::new(
+                "DEAL1".to_string(),
+                None,
+                amount,
+                Vec::new(),
+                ProofOptions::default(),
+                fake_receipt(amount),
+            );
+            proof_ids.push(proof.id.clone());
+            storage.lock().unwrap().add_proof(proof);
+        }
+        let auth = Arc::new(Authenticator::new(storage.clone(), None));
+
+        let routes = warp::get()
+            .and(warp::path!("deals" / String / "proofs"))
+            .and(authorize(auth, Access::BuyersBank))
+            .and(warp::query())
+            .and(warp::any().map(move || storage.clone()))
+            .and_then(handle_list_deal_proofs)
+            .recover(handle_rejection);
+        let list = |key: &str, path: &str| {
+            warp::test::request()
+                .path(path)
+                .header("authorization", format!("Bearer {}", key))
+                .reply(&routes)
+        };
+        let page = |body: &serde_json::Value| -> Vec<String> {
+            body["proofs"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|proof| proof["proof_id"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let response = list("buyer", "/deals/DEAL1/proofs").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(page(&body(&response)), proof_ids);
+
+        let response = list("buyer", "/deals/DEAL1/proofs?limit=2").await;
+        assert_eq!(body(&response)["total"], 3);
+        assert_eq!(page(&body(&response)), proof_ids[..2]);
+        let response = list("buyer", "/deals/DEAL1/proofs?offset=2&limit=2").await;
+        assert_eq!(body(&response)["offset"], 2);
+        assert_eq!(page(&body(&response)), proof_ids[2..]);
+        let response = list("buyer", "/deals/DEAL1/proofs?offset=5").await;
+        assert_eq!(response.status(), 200);
+        assert!(page(&body(&response)).is_empty());
+
+        for (key, path, status) in [
+            ("buyer", "/deals/DEAL1/proofs?limit=0", 400),
+            ("buyer", "/deals/DEAL1/proofs?limit=101", 400),
+            ("buyer", "/deals/DEAL1/proofs?offset=-1", 400),
+            ("other_buyer", "/deals/DEAL1/proofs", 403),
+            ("buyer", "/deals/DEAL2/proofs", 404),
+        ] {
+            assert_eq!(list(key, path).await.status(), status, "{} {}", key, path);
+        }
+    }
+
+    // DEAL1 with a commitment of 50 from LB1 and one of 30 from LB2, stored
+    // under their commitment hashes as the commitment handler does
+    fn storage_with_commitments() -> (Arc<Mutex<Storage>>, Vec<String>) {
+        let storage = storage();
+        let mut ids = Vec::new();
+        for (bank_index, amount) in [(0, 50), (1, 30)] {
+            let signed = create_signed_message(
+                &get_deterministic_signing_key(bank_index),
+                amount,
+                "DEAL1".to_string(),
+                "buyer123".to_string(),
+            )
+            .unwrap();
+            let id = commitment_hash(&signed).unwrap();
+            storage
+                .lock()
+                .unwrap()
+                .add_commitment(StoredCommitment::new(
+                    id.clone(),
+                    "DEAL1".to_string(),
+                    signed,
+                    None,
+                ));
+            ids.push(id);
+        }
+        (storage, ids)
+    }
+
+    #[test]
+    fn test_choose_commitments() {
+        let (storage, ids) = storage_with_commitments();
+        let chosen = |commitment_ids: Option<&[String]>, selection, required_amount| {
+            choose_commitments(
+                &storage,
+                "DEAL1",
+                commitment_ids,
+                &selection,
+                required_amount,
+            )
+            .map(|selected| {
+                selected
+                    .into_iter()
+                    .map(|(chosen, _)| (chosen.commitment_id, chosen.amount))
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        // The smallest set covering the amount, or one commitment per bank
+        assert_eq!(
+            chosen(None, SelectionStrategy::Fewest, 40).unwrap(),
+            [(ids[0].clone(), 50)]
+        );
+        assert_eq!(
+            chosen(None, SelectionStrategy::MostBanks, 40).unwrap(),
+            [(ids[0].clone(), 50), (ids[1].clone(), 30)]
+        );
+
+        // The buyer's bank's own choice, in its order
+        let named = [ids[1].clone(), ids[0].clone()];
+        assert_eq!(
+            chosen(Some(&named), SelectionStrategy::Fewest, 80).unwrap(),
+            [(ids[1].clone(), 30), (ids[0].clone(), 50)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proof_job_commitment_selection_errors() {
+        let (storage, ids) = storage_with_commitments();
+        let auth = Arc::new(Authenticator::new(storage.clone(), None));
+        let webhooks = Arc::new(Webhooks::new(WebhookConfig::default()));
+        let worker = Arc::new(ProofWorker::new(1, Duration::from_secs(60), webhooks));
+
+        let routes = warp::post()
+            .and(warp::path!("proofs" / "async"))
+            .and(authorize(auth, Access::BuyersBank))
+            .and(warp::body::json())
+            .and(warp::any().map(move || storage.clone()))
+            .and(warp::any().map(move || worker.clone()))
+            .and_then(handle_create_proof_job)
+            .recover(handle_rejection);
+        let create = |key: &str, request: serde_json::Value| {
+            warp::test::request()
+                .method("POST")
+                .path("/proofs/async")
+                .header("authorization", format!("Bearer {}", key))
+                .json(&request)
+                .reply(&routes)
+        };
+
+        // Rejected before anything is executed or queued
+        for (key, request, status) in [
+            ("other_buyer", json!({ "deal_id": "DEAL1" }), 403),
+            ("buyer", json!({ "deal_id": "DEAL2" }), 404),
+            (
+                "buyer",
+                json!({ "deal_id": "DEAL1", "required_amount": 60, "commitment_ids": ["unknown"] }),
+                422,
+            ),
+            ("buyer", json!({ "deal_id": "DEAL1" }), 400),
+            // Named commitments that don't cover the amount
+            (
+                "buyer",
+                json!({ "deal_id": "DEAL1", "required_amount": 60, "commitment_ids": [ids[1]] }),
+                422,
+            ),
+            // No selection covers it
+            (
+                "buyer",
+                json!({ "deal_id": "DEAL1", "required_amount": 500, "selection": { "strategy": "most_banks" } }),
+                422,
+            ),
+            (
+                "buyer",
+                json!({ "deal_id": "DEAL1", "required_amount": 60, "selection": { "strategy": "cheapest" } }),
+                400,
+            ),
+        ] {
+            let response = create(key, request.clone()).await;
+            assert_eq!(response.status(), status, "{} {}", key, request);
+        }
     }
 }
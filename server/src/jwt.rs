@@ -0,0 +1,315 @@
+use crate::auth::{AuthError, Principal, Role};
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+// An unknown key ID refetches a JWKS URL at most this often, so rotated keys
+// are picked up without letting bad tokens hammer the identity provider
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub enum JwksSource {
+    File(PathBuf),
+    Url(String),
+}
+
+// How bearer JWTs from the banks' identity provider are validated and mapped
+// to roles
+#[derive(Debug, Clone)]
+pub struct JwtConfig {
+    pub jwks: JwksSource,
+    pub issuer: String,
+    pub audience: String,
+    pub role_claim: String, // "lending_bank", "buyers_bank", "sellers_bank" or "admin"
+    pub bank_claim: String, // Registry bank index, for lending banks
+    pub party_claim: String, // Deal buyer or seller, for buyers' and sellers' banks
+}
+
+impl JwtConfig {
+    // From JWKS_FILE or JWKS_URL, JWT_ISSUER and JWT_AUDIENCE, and optionally
+    // JWT_ROLE_CLAIM, JWT_BANK_CLAIM and JWT_PARTY_CLAIM. None if no JWKS is
+    // configured.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let jwks = match (std::env::var("JWKS_FILE"), std::env::var("JWKS_URL")) {
+            (Ok(path), Err(_)) => JwksSource::File(path.into()),
+            (Err(_), Ok(url)) => JwksSource::Url(url),
+            (Err(_), Err(_)) => return Ok(None),
+            (Ok(_), Ok(_)) => return Err("Set only one of JWKS_FILE and JWKS_URL".to_string()),
+        };
+        let required = |name: &str| {
+            std::env::var(name).map_err(|_| format!("{} is required with a JWKS", name))
+        };
+
+        Ok(Some(Self {
+            jwks,
+            issuer: required("JWT_ISSUER")?,
+            audience: required("JWT_AUDIENCE")?,
+            role_claim: std::env::var("JWT_ROLE_CLAIM").unwrap_or_else(|_| "rwz_role".to_string()),
+            bank_claim: std::env::var("JWT_BANK_CLAIM")
+                .unwrap_or_else(|_| "rwz_bank_index".to_string()),
+            party_claim: std::env::var("JWT_PARTY_CLAIM")
+                .unwrap_or_else(|_| "rwz_party".to_string()),
+        }))
+    }
+}
+
+pub struct JwtValidator {
+    config: JwtConfig,
+    keys: RwLock<JwkSet>,
+    fetched_at: RwLock<Instant>,
+}
+
+impl JwtValidator {
+    pub async fn load(config: JwtConfig) -> Result<Self, String> {
+        let keys = fetch_jwks(&config.jwks).await?;
+        Ok(Self {
+            config,
+            keys: RwLock::new(keys),
+            fetched_at: RwLock::new(Instant::now()),
+        })
+    }
+
+    // Checks the token's signature against the JWKS, its issuer, audience and
+    // expiry, and maps its claims to a principal
+    pub async fn validate(&self, token: &str) -> Result<Principal, AuthError> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| AuthError::InvalidToken(e.to_string()))?;
+
+        let jwk = match self.find_key(header.kid.as_deref()) {
+            Some(jwk) => jwk,
+            None => {
+                self.refresh().await;
+                self.find_key(header.kid.as_deref()).ok_or_else(|| {
+                    AuthError::InvalidToken(format!("Unknown key ID {:?}", header.kid))
+                })?
+            }
+        };
+
+        if !key_algorithms(&jwk).contains(&header.alg) {
+            return Err(AuthError::InvalidToken(format!(
+                "Algorithm {:?} doesn't match the key",
+                header.alg
+            )));
+        }
+        let key =
+            DecodingKey::from_jwk(&jwk).map_err(|e| AuthError::InvalidToken(e.to_string()))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.config.issuer]);
+        validation.set_audience(&[&self.config.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let claims = jsonwebtoken::decode::<Value>(token, &key, &validation)
+            .map_err(|e| AuthError::InvalidToken(e.to_string()))?
+            .claims;
+        self.principal(&claims)
+    }
+
+    fn principal(&self, claims: &Value) -> Result<Principal, AuthError> {
+        let subject = claims["sub"].as_str().unwrap_or_default().to_string();
+        let party = || {
+            claims[&self.config.party_claim]
+                .as_str()
+                .map(String::from)
+                .ok_or_else(|| {
+                    AuthError::InvalidToken(format!(
+                        "Token without a {} claim",
+                        self.config.party_claim
+                    ))
+                })
+        };
+        let role = match claims[&self.config.role_claim].as_str() {
+            Some("lending_bank") => {
+                let bank_index = claims[&self.config.bank_claim].as_u64().ok_or_else(|| {
+                    AuthError::InvalidToken(format!(
+                        "Lending bank token without a numeric {} claim",
+                        self.config.bank_claim
+                    ))
+                })?;
+                Role::LendingBank { bank_index }
+            }
+            Some("buyers_bank") => Role::BuyersBank { buyer: party()? },
+            Some("sellers_bank") => Role::SellersBank { seller: party()? },
+            Some("admin") => Role::Admin,
+            other => {
+                return Err(AuthError::InvalidToken(format!(
+                    "Unknown {} claim {:?}",
+                    self.config.role_claim, other
+                )))
+            }
+        };
+
+        Ok(Principal {
+            subject: format!("{}#{}", self.config.issuer, subject),
+            role,
+        })
+    }
+
+    // The key with this ID, or the only key if the token names none
+    fn find_key(&self, kid: Option<&str>) -> Option<Jwk> {
+        let keys = self.keys.read().unwrap();
+        match kid {
+            Some(kid) => keys.find(kid).cloned(),
+            None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+            None => None,
+        }
+    }
+
+    // Refetches a JWKS URL, at most once per `JWKS_REFRESH_INTERVAL`
+    async fn refresh(&self) {
+        if !matches!(self.config.jwks, JwksSource::Url(_))
+            || self.fetched_at.read().unwrap().elapsed() < JWKS_REFRESH_INTERVAL
+        {
+            return;
+        }
+        *self.fetched_at.write().unwrap() = Instant::now();
+
+        match fetch_jwks(&self.config.jwks).await {
+            Ok(keys) => *self.keys.write().unwrap() = keys,
+            Err(e) => println!("Failed to refresh JWKS: {}", e),
+        }
+    }
+}
+
+async fn fetch_jwks(source: &JwksSource) -> Result<JwkSet, String> {
+    match source {
+        JwksSource::File(path) => {
+            let json = std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read JWKS {}: {}", path.display(), e))?;
+            serde_json::from_str(&json)
+                .map_err(|e| format!("Invalid JWKS {}: {}", path.display(), e))
+        }
+        JwksSource::Url(url) => reqwest::get(url)
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("Failed to fetch JWKS {}: {}", url, e))?
+            .json()
+            .await
+            .map_err(|e| format!("Invalid JWKS {}: {}", url, e)),
+    }
+}
+
+// Algorithms a key can verify, so a token can't pick one meant for another
+// kind of key
+fn key_algorithms(jwk: &Jwk) -> &'static [Algorithm] {
+    use Algorithm::*;
+    match jwk.algorithm {
+        AlgorithmParameters::RSA(_) => &[RS256, RS384, RS512, PS256, PS384, PS512],
+        AlgorithmParameters::EllipticCurve(_) => &[ES256, ES384],
+        AlgorithmParameters::OctetKeyPair(_) => &[EdDSA],
+        AlgorithmParameters::OctetKey(_) => &[HS256, HS384, HS512],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    const PRIVATE_KEY: &[u8] = include_bytes!("../tests/fixtures/jwt/private.pem");
+
+    async fn validator() -> JwtValidator {
+        JwtValidator::load(JwtConfig {
+            jwks: JwksSource::File(
+                concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/jwt/jwks.json").into(),
+            ),
+            issuer: "https://idp.example".to_string(),
+            audience: "rwz-pof".to_string(),
+            role_claim: "rwz_role".to_string(),
+            bank_claim: "rwz_bank_index".to_string(),
+            party_claim: "rwz_party".to_string(),
+        })
+        .await
+        .unwrap()
+    }
+
+    fn token(claims: Value, kid: &str) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_string());
+        let key = EncodingKey::from_rsa_pem(PRIVATE_KEY).unwrap();
+        jsonwebtoken::encode(&header, &claims, &key).unwrap()
+    }
+
+    fn claims(role: &str) -> Value {
+        json!({
+            "sub": "alice",
+            "iss": "https://idp.example",
+            "aud": "rwz-pof",
+            "exp": jsonwebtoken::get_current_timestamp() + 300,
+            "rwz_role": role,
+            "rwz_bank_index": 2,
+            "rwz_party": "seller123",
+        })
+    }
+
+    #[tokio::test]
+    async fn test_maps_claims_to_principal() {
+        let validator = validator().await;
+
+        let principal = validator
+            .validate(&token(claims("lending_bank"), "test-key"))
+            .await
+            .unwrap();
+        assert_eq!(principal.role, Role::LendingBank { bank_index: 2 });
+        assert_eq!(principal.subject, "https://idp.example#alice");
+
+        let principal = validator
+            .validate(&token(claims("sellers_bank"), "test-key"))
+            .await
+            .unwrap();
+        assert_eq!(
+            principal.role,
+            Role::SellersBank {
+                seller: "seller123".to_string()
+            }
+        );
+
+        let mut no_party = claims("buyers_bank");
+        no_party.as_object_mut().unwrap().remove("rwz_party");
+        assert!(validator
+            .validate(&token(no_party, "test-key"))
+            .await
+            .is_err());
+        assert!(validator
+            .validate(&token(claims("auditor"), "test-key"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_invalid_tokens() {
+        let validator = validator().await;
+
+        let mut wrong_audience = claims("admin");
+        wrong_audience["aud"] = json!("other");
+        let mut wrong_issuer = claims("admin");
+        wrong_issuer["iss"] = json!("https://evil.example");
+        let mut expired = claims("admin");
+        expired["exp"] = json!(jsonwebtoken::get_current_timestamp() - 3600);
+
+        for token in [
+            token(wrong_audience, "test-key"),
+            token(wrong_issuer, "test-key"),
+            token(expired, "test-key"),
+            token(claims("admin"), "rotated-key"),
+        ] {
+            assert!(matches!(
+                validator.validate(&token).await,
+                Err(AuthError::InvalidToken(_))
+            ));
+        }
+
+        // Signed with a shared secret instead of the RSA key
+        let forged = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims("admin"),
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        assert!(validator.validate(&forged).await.is_err());
+    }
+}
@@ -1,23 +1,60 @@
+mod auction;
+mod auth;
+mod config;
+mod deal;
+mod events;
 mod handlers;
+mod jwt;
 mod storage;
+mod tls;
+mod upload;
+mod webhooks;
 pub mod worker;
 
+use auth::{authorize, require, Access, ApiKey, Authenticator, Role};
+use config::{Config, LogFormat, ProverMode};
+use deal::Deal;
 use handlers::{
-    handle_commitment, handle_create_proof_job, handle_get_proof_job, handle_proof, handle_verify,
+    handle_auction_ranking, handle_cancel_deal, handle_commitment, handle_create_auction,
+    handle_create_deal, handle_create_proof_job, handle_create_webhook, handle_delete_webhook,
+    handle_export_auction, handle_get_auction, handle_get_deal, handle_get_proof_artifact,
+    handle_get_proof_job, handle_issue_key, handle_list_dead_letters, handle_list_deal_commitments,
+    handle_list_deal_proofs, handle_list_deals, handle_list_keys, handle_list_rounds,
+    handle_list_webhooks, handle_proof, handle_proof_job_events, handle_rejection,
+    handle_revoke_key, handle_submit_bid, handle_submit_bid_upload, handle_submit_round,
+    handle_transition_deal, handle_update_deal, handle_verify, handle_verify_form,
+    handle_verify_upload, handle_withdraw_commitment,
 };
+use jwt::{JwtConfig, JwtValidator};
+use k256::ecdsa::{SigningKey, VerifyingKey};
+use rand_core::OsRng;
+use rwz_pof_core::AcceptancePolicy;
 use std::convert::Infallible;
+use std::io::Write;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use storage::Storage;
 use warp::cors::Cors;
 use warp::Filter;
+use webhooks::Webhooks;
 use worker::ProofWorker;
 
-fn cors() -> Cors {
-    warp::cors()
-        .allow_any_origin()
-        .allow_headers(vec!["Content-Type"])
-        .allow_methods(vec!["GET", "POST", "OPTIONS"])
-        .build()
+// Any origin unless the configuration lists some
+fn cors(origins: &[String]) -> Cors {
+    let cors = if origins.is_empty() || origins.iter().any(|origin| origin == "*") {
+        warp::cors().allow_any_origin()
+    } else {
+        warp::cors().allow_origins(origins.iter().map(String::as_str))
+    };
+    cors.allow_headers(vec![
+        "Authorization",
+        "Content-Type",
+        "Accept",
+        "If-None-Match",
+    ])
+    .expose_headers(vec!["ETag", "Content-Disposition"])
+    .allow_methods(vec!["GET", "POST", "PATCH", "DELETE", "OPTIONS"])
+    .build()
 }
 
 // Helper functions for warp filters
@@ -33,25 +70,199 @@ fn with_proof_worker(
     warp::any().map(move || worker.clone())
 }
 
+fn with_webhooks(
+    webhooks: Arc<Webhooks>,
+) -> impl Filter<Extract = (Arc<Webhooks>,), Error = Infallible> + Clone {
+    warp::any().map(move || webhooks.clone())
+}
+
+fn with_seller_key(
+    key: Arc<SigningKey>,
+) -> impl Filter<Extract = (Arc<SigningKey>,), Error = Infallible> + Clone {
+    warp::any().map(move || key.clone())
+}
+
+// The seller's bank key signing auction requirements, from SELLER_KEY (hex).
+// Only dev and demo servers may sign with a random key for this run, as
+// requirements signed with it no longer verify after a restart.
+fn load_seller_key(config: &Config) -> SigningKey {
+    let Ok(hex_key) = std::env::var("SELLER_KEY") else {
+        if config.prover != ProverMode::Dev && !config.demo.enabled {
+            eprintln!("SELLER_KEY must be set unless PROVER_MODE=dev or the demo is enabled");
+            std::process::exit(1);
+        }
+        eprintln!(
+            "WARNING: SELLER_KEY is not set, signing auction requirements with a random key \
+             that is lost on restart"
+        );
+        return SigningKey::random(&mut OsRng);
+    };
+    hex::decode(hex_key.trim())
+        .ok()
+        .and_then(|bytes| SigningKey::from_slice(&bytes).ok())
+        .expect("SELLER_KEY must be a hex-encoded secp256k1 secret key")
+}
+
+// The bootstrap admin key, from ADMIN_API_KEY or else the file at
+// ADMIN_API_KEY_FILE (admin-api-key by default), which is created owner-only
+// with a random key on first start. Other keys are issued with it through
+// /admin/keys. Only the key's ID is logged.
+fn admin_key() -> ApiKey {
+    let key = match std::env::var("ADMIN_API_KEY") {
+        Ok(key) => key,
+        Err(_) => {
+            let path = std::env::var("ADMIN_API_KEY_FILE")
+                .unwrap_or_else(|_| DEFAULT_ADMIN_KEY_FILE.to_string());
+            admin_key_file(Path::new(&path)).unwrap_or_else(|e| {
+                eprintln!("Can't load the admin API key from {}: {}", path, e);
+                std::process::exit(1);
+            })
+        }
+    };
+    let admin = ApiKey::from_key(&key, Role::Admin, Some("bootstrap".to_string()));
+    println!("Bootstrap admin API key: {}", admin.id);
+    admin
+}
+
+const DEFAULT_ADMIN_KEY_FILE: &str = "admin-api-key";
+
+// Reads the admin key from the file, or writes a new one to it readable only
+// by the server's user
+fn admin_key_file(path: &Path) -> std::io::Result<String> {
+    if path.exists() {
+        return Ok(std::fs::read_to_string(path)?.trim().to_string());
+    }
+    let (_, key) = ApiKey::issue(Role::Admin, None);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    writeln!(options.open(path)?, "{}", key)?;
+    println!("Wrote a new admin API key to {}", path.display());
+    Ok(key)
+}
+
+// Loads every TOML and JSON acceptance policy in the directory, skipping invalid files
+fn load_policies(dir: &Path) -> Vec<AcceptancePolicy> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            println!("No policies loaded from {}: {}", dir.display(), e);
+            return Vec::new();
+        }
+    };
+
+    let mut policies = Vec::new();
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        if !matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("toml" | "json")
+        ) {
+            continue;
+        }
+        match AcceptancePolicy::load(&path) {
+            Ok(policy) => {
+                println!("Loaded policy {} from {}", policy.id, path.display());
+                policies.push(policy);
+            }
+            Err(e) => println!("Skipping policy {}: {}", path.display(), e),
+        }
+    }
+    policies
+}
+
 #[tokio::main]
 async fn main() {
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("Invalid configuration: {}", e);
+        std::process::exit(1);
+    });
+
     // Initialize basic logging
-    tracing_subscriber::fmt::init();
+    let logging = tracing_subscriber::fmt();
+    match config.log_format {
+        LogFormat::Full => logging.init(),
+        LogFormat::Compact => logging.compact().init(),
+        LogFormat::Pretty => logging.pretty().init(),
+    }
     println!("Starting RWZ-POF server...");
 
+    if config.prover == ProverMode::Dev {
+        println!("Dev mode: proofs are fake and won't verify elsewhere");
+        std::env::set_var("RISC0_DEV_MODE", "1");
+    }
+
     // Initialize storage with thread-safe wrapper
-    let storage = Arc::new(Mutex::new(Storage::new()));
+    let mut storage = Storage::new();
+
+    // Seller's Bank acceptance policies, selected by ID on /sb/verify
+    for policy in load_policies(&config.policy_dir) {
+        storage.add_policy(policy);
+    }
+
+    storage.add_api_key(admin_key());
+
+    // Demo deal used by the frontend and the README examples
+    if config.demo.enabled {
+        let demo = &config.demo;
+        storage.add_deal(Deal::new(
+            demo.deal_id.clone(),
+            demo.buyer.clone(),
+            demo.seller.clone(),
+            demo.target_amount,
+            demo.currency.clone(),
+            None,
+        ));
+        handlers::set_default_deal_id(demo.deal_id.clone());
+    }
+
+    let storage = Arc::new(Mutex::new(storage));
+
+    // Bearer JWTs from the banks' identity provider, besides API keys
+    let jwt = match JwtConfig::from_env().expect("Invalid JWT configuration") {
+        Some(config) => {
+            println!(
+                "Accepting JWTs from {} for {}",
+                config.issuer, config.audience
+            );
+            Some(
+                JwtValidator::load(config)
+                    .await
+                    .expect("Failed to load the JWKS"),
+            )
+        }
+        None => None,
+    };
+    let auth = Arc::new(Authenticator::new(storage.clone(), jwt));
+
+    let seller_key = Arc::new(load_seller_key(&config));
+    println!(
+        "Seller's bank key: {}",
+        hex::encode(VerifyingKey::from(seller_key.as_ref()).to_sec1_bytes())
+    );
+
+    // Signed notifications of proof, commitment and verification events
+    let webhooks = Arc::new(Webhooks::new(config.webhooks.clone()));
 
     // Initialize proof worker
-    let proof_worker = Arc::new(ProofWorker::new());
+    let proof_worker = Arc::new(ProofWorker::new(
+        config.worker.concurrency,
+        config.worker.job_timeout(),
+        webhooks.clone(),
+    ));
 
     // POST /lb/commitment
     let commitment = warp::post()
         .and(warp::path("lb"))
         .and(warp::path("commitment"))
         .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::LendingBank))
         .and(warp::body::json())
         .and(with_storage(storage.clone()))
+        .and(with_webhooks(webhooks.clone()))
         .and_then(handle_commitment);
 
     // POST /bb/proof
@@ -59,22 +270,50 @@ async fn main() {
         .and(warp::path("bb"))
         .and(warp::path("proof"))
         .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::BuyersBank))
         .and(warp::body::json())
         .and(with_storage(storage.clone()))
         .and_then(handle_proof);
 
-    // POST /sb/verify
-    let verify = warp::post()
+    // POST /sb/verify, with a JSON, binary or multipart body
+    let verify_route = warp::post()
         .and(warp::path("sb"))
         .and(warp::path("verify"))
         .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::SellersBank))
+        .and(warp::body::content_length_limit(upload::MAX_UPLOAD_BYTES));
+
+    let verify_json = verify_route
+        .clone()
         .and(warp::body::json())
         .and(with_storage(storage.clone()))
+        .and(with_webhooks(webhooks.clone()))
         .and_then(handle_verify);
 
+    let verify_upload = verify_route
+        .clone()
+        .and(warp::header::exact_ignore_case(
+            "content-type",
+            "application/octet-stream",
+        ))
+        .and(warp::query())
+        .and(warp::body::bytes())
+        .and(with_storage(storage.clone()))
+        .and(with_webhooks(webhooks.clone()))
+        .and_then(handle_verify_upload);
+
+    let verify_form = verify_route
+        .and(warp::multipart::form().max_length(upload::MAX_UPLOAD_BYTES))
+        .and(with_storage(storage.clone()))
+        .and(with_webhooks(webhooks.clone()))
+        .and_then(handle_verify_form);
+
+    let verify = verify_json.or(verify_upload).or(verify_form);
+
     let create_proof_job = warp::post()
         .and(warp::path("proofs"))
         .and(warp::path("async"))
+        .and(authorize(auth.clone(), Access::BuyersBank))
         .and(warp::body::json())
         .and(with_storage(storage.clone()))
         .and(with_proof_worker(proof_worker.clone()))
@@ -84,18 +323,322 @@ async fn main() {
         .and(warp::path("proofs"))
         .and(warp::path("async"))
         .and(warp::path::param())
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::BuyersBank))
         .and(with_storage(storage.clone()))
         .and_then(handle_get_proof_job);
 
+    // GET /proofs/async/{id}/events, as Server-Sent Events
+    let proof_job_events = warp::get()
+        .and(warp::path("proofs"))
+        .and(warp::path("async"))
+        .and(warp::path::param())
+        .and(warp::path("events"))
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::BuyersBank))
+        .and(with_storage(storage.clone()))
+        .and(with_proof_worker(proof_worker.clone()))
+        .and_then(handle_proof_job_events);
+
+    // POST /deals and GET /deals
+    let create_deal = warp::post()
+        .and(warp::path("deals"))
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::BuyersBank))
+        .and(warp::body::json())
+        .and(with_storage(storage.clone()))
+        .and_then(handle_create_deal);
+
+    let list_deals = warp::get()
+        .and(warp::path("deals"))
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::Authenticated))
+        .and(with_storage(storage.clone()))
+        .and_then(handle_list_deals);
+
+    // GET, PATCH and DELETE /deals/{deal_id}
+    let get_deal = warp::get()
+        .and(warp::path("deals"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::Authenticated))
+        .and(with_storage(storage.clone()))
+        .and_then(handle_get_deal);
+
+    let update_deal = warp::patch()
+        .and(warp::path("deals"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::BuyersBank))
+        .and(warp::body::json())
+        .and(with_storage(storage.clone()))
+        .and_then(handle_update_deal);
+
+    let cancel_deal = warp::delete()
+        .and(warp::path("deals"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::BuyersBank))
+        .and(with_storage(storage.clone()))
+        .and_then(handle_cancel_deal);
+
+    // POST /deals/{deal_id}/transitions
+    let transition_deal = warp::post()
+        .and(warp::path("deals"))
+        .and(warp::path::param())
+        .and(warp::path("transitions"))
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::BuyersBank))
+        .and(warp::body::json())
+        .and(with_storage(storage.clone()))
+        .and_then(handle_transition_deal);
+
+    // POST and GET /deals/{deal_id}/rounds, the deal's chain of bids
+    let submit_round = warp::post()
+        .and(warp::path("deals"))
+        .and(warp::path::param())
+        .and(warp::path("rounds"))
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::BuyersBank))
+        .and(warp::body::json())
+        .and(with_storage(storage.clone()))
+        .and_then(handle_submit_round);
+
+    let list_rounds = warp::get()
+        .and(warp::path("deals"))
+        .and(warp::path::param())
+        .and(warp::path("rounds"))
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::Authenticated))
+        .and(with_storage(storage.clone()))
+        .and_then(handle_list_rounds);
+
+    let deals = create_deal
+        .or(list_deals)
+        .or(get_deal)
+        .or(update_deal)
+        .or(cancel_deal)
+        .or(transition_deal)
+        .or(submit_round)
+        .or(list_rounds);
+
+    // POST /auctions and GET /auctions/{auction_id}, run by the seller's bank
+    let create_auction = warp::post()
+        .and(warp::path("auctions"))
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::SellersBank))
+        .and(warp::body::json())
+        .and(with_storage(storage.clone()))
+        .and(with_seller_key(seller_key.clone()))
+        .and_then(handle_create_auction);
+
+    let get_auction = warp::get()
+        .and(warp::path("auctions"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::Authenticated))
+        .and(with_storage(storage.clone()))
+        .and_then(handle_get_auction);
+
+    // POST /auctions/{auction_id}/bids, a bundle as JSON or a binary .pof
+    let bids_route = warp::post()
+        .and(warp::path("auctions"))
+        .and(warp::path::param())
+        .and(warp::path("bids"))
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::BuyersBank))
+        .and(warp::body::content_length_limit(upload::MAX_UPLOAD_BYTES));
+
+    let submit_bid = bids_route
+        .clone()
+        .and(warp::body::json())
+        .and(with_storage(storage.clone()))
+        .and_then(handle_submit_bid);
+
+    let submit_bid_upload = bids_route
+        .and(warp::header::exact_ignore_case(
+            "content-type",
+            "application/octet-stream",
+        ))
+        .and(warp::body::bytes())
+        .and(with_storage(storage.clone()))
+        .and_then(handle_submit_bid_upload);
+
+    // GET /auctions/{auction_id}/bids, verified bids ranked
+    let auction_ranking = warp::get()
+        .and(warp::path("auctions"))
+        .and(warp::path::param())
+        .and(warp::path("bids"))
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::SellersBank))
+        .and(with_storage(storage.clone()))
+        .and_then(handle_auction_ranking);
+
+    // GET /auctions/{auction_id}/export
+    let export_auction = warp::get()
+        .and(warp::path("auctions"))
+        .and(warp::path::param())
+        .and(warp::path("export"))
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::SellersBank))
+        .and(warp::header::optional("accept"))
+        .and(with_storage(storage.clone()))
+        .and_then(handle_export_auction);
+
+    let auctions = create_auction
+        .or(get_auction)
+        .or(submit_bid)
+        .or(submit_bid_upload)
+        .or(auction_ranking)
+        .or(export_auction);
+
+    // GET /deals/{deal_id}/commitments
+    let list_deal_commitments = warp::get()
+        .and(warp::path("deals"))
+        .and(warp::path::param())
+        .and(warp::path("commitments"))
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::Authenticated))
+        .and(with_storage(storage.clone()))
+        .and_then(handle_list_deal_commitments);
+
+    // POST /deals/{deal_id}/commitments/{commitment_id}/withdraw
+    let withdraw_commitment = warp::post()
+        .and(warp::path("deals"))
+        .and(warp::path::param())
+        .and(warp::path("commitments"))
+        .and(warp::path::param())
+        .and(warp::path("withdraw"))
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::LendingBank))
+        .and(warp::body::json())
+        .and(with_storage(storage.clone()))
+        .and_then(handle_withdraw_commitment);
+
+    // GET /deals/{deal_id}/proofs?offset=&limit=
+    let list_deal_proofs = warp::get()
+        .and(warp::path("deals"))
+        .and(warp::path::param())
+        .and(warp::path("proofs"))
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::BuyersBank))
+        .and(warp::query())
+        .and(with_storage(storage.clone()))
+        .and_then(handle_list_deal_proofs);
+
+    // GET /proofs/{proof_id}/receipt and /proofs/{proof_id}/bundle
+    let get_proof_artifact = warp::get()
+        .and(warp::path("proofs"))
+        .and(warp::path::param())
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::BuyersBank))
+        .and(warp::header::optional("accept"))
+        .and(warp::header::optional("if-none-match"))
+        .and(with_storage(storage.clone()))
+        .and_then(handle_get_proof_artifact);
+
+    // POST and GET /admin/keys, DELETE /admin/keys/{key_id}
+    let issue_key = warp::post()
+        .and(warp::path("admin"))
+        .and(warp::path("keys"))
+        .and(warp::path::end())
+        .and(require(auth.clone(), Access::Admin))
+        .and(warp::body::json())
+        .and(with_storage(storage.clone()))
+        .and_then(handle_issue_key);
+
+    let list_keys = warp::get()
+        .and(warp::path("admin"))
+        .and(warp::path("keys"))
+        .and(warp::path::end())
+        .and(require(auth.clone(), Access::Admin))
+        .and(with_storage(storage.clone()))
+        .and_then(handle_list_keys);
+
+    let revoke_key = warp::delete()
+        .and(warp::path("admin"))
+        .and(warp::path("keys"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(require(auth.clone(), Access::Admin))
+        .and(with_storage(storage.clone()))
+        .and_then(handle_revoke_key);
+
+    // GET /admin/webhooks/dead-letters
+    let dead_letters = warp::get()
+        .and(warp::path("admin"))
+        .and(warp::path("webhooks"))
+        .and(warp::path("dead-letters"))
+        .and(warp::path::end())
+        .and(require(auth.clone(), Access::Admin))
+        .and(with_webhooks(webhooks.clone()))
+        .and_then(handle_list_dead_letters);
+
+    let admin = issue_key.or(list_keys).or(revoke_key).or(dead_letters);
+
+    // POST and GET /webhooks, DELETE /webhooks/{webhook_id}
+    let create_webhook = warp::post()
+        .and(warp::path("webhooks"))
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::Authenticated))
+        .and(warp::body::json())
+        .and(with_storage(storage.clone()))
+        .and(with_webhooks(webhooks.clone()))
+        .and_then(handle_create_webhook);
+
+    let list_webhooks = warp::get()
+        .and(warp::path("webhooks"))
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::Authenticated))
+        .and(with_webhooks(webhooks.clone()))
+        .and_then(handle_list_webhooks);
+
+    let delete_webhook = warp::delete()
+        .and(warp::path("webhooks"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(authorize(auth.clone(), Access::Authenticated))
+        .and(with_webhooks(webhooks.clone()))
+        .and_then(handle_delete_webhook);
+
+    let webhook_routes = create_webhook.or(list_webhooks).or(delete_webhook);
+
     // Combine routes
     let routes = commitment
         .or(proof)
         .or(verify)
         .or(create_proof_job)
         .or(get_proof_job)
-        .with(cors());
+        .or(proof_job_events)
+        .or(get_proof_artifact)
+        .or(list_deal_proofs)
+        .or(deals)
+        .or(auctions)
+        .or(list_deal_commitments)
+        .or(withdraw_commitment)
+        .or(admin)
+        .or(webhook_routes)
+        .recover(handle_rejection)
+        .with(cors(&config.cors_origins));
 
-    // Start the server
-    println!("Server running on http://localhost:3030");
-    warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
+    // Start the server, over TLS if a certificate is configured
+    match &config.tls {
+        Some(tls_config) => {
+            let acceptor = tls_config
+                .acceptor()
+                .expect("Failed to load TLS configuration");
+            if tls_config.client_ca.is_some() {
+                println!("Client certificates: {:?}", tls_config.client_auth);
+            }
+            println!("Server running on https://{}", config.listen);
+            tls::serve(routes, config.listen, acceptor)
+                .await
+                .expect("Server failed");
+        }
+        None => {
+            println!("Server running on http://{}", config.listen);
+            warp::serve(routes).run(config.listen).await;
+        }
+    }
 }
@@ -1,5 +1,8 @@
+use crate::auction::Auction;
+use crate::auth::ApiKey;
+use crate::deal::{BidRound, Deal, DealState};
 use risc0_zkvm::Receipt;
-use rwz_pof_core::{DealInfo, SignedMessage};
+use rwz_pof_core::{AcceptancePolicy, DealInfo, ProofOptions, SignedMessage, VerifiedDeal};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use time::serde::iso8601;
@@ -19,6 +22,8 @@ pub struct ProofGenerationJob {
     pub status: ProofJobStatus,
     pub deal_id: String,
     pub required_amount: u64,
+    pub options: ProofOptions,
+    pub commitment_ids: Vec<String>, // Chosen before the job is queued
     #[serde(with = "iso8601")]
     pub created_at: OffsetDateTime,
     #[serde(with = "iso8601")]
@@ -26,65 +31,414 @@ pub struct ProofGenerationJob {
     // Only store deal_info and verified_amount in the job
     //     pub proof: Option<(Receipt, DealInfo, u64)>,
     pub proof: Option<(DealInfo, u64)>,
+    pub proof_id: Option<String>,    // Set once completed
+    pub receipt_size: Option<usize>, // Serialized receipt size in bytes
     pub error: Option<String>,
 }
 
 impl ProofGenerationJob {
-    pub fn new(deal_id: String, required_amount: u64) -> Self {
+    pub fn new(
+        deal_id: String,
+        required_amount: u64,
+        options: ProofOptions,
+        commitment_ids: Vec<String>,
+    ) -> Self {
         let now = OffsetDateTime::now_utc();
         Self {
             id: uuid::Uuid::new_v4().to_string(),
             status: ProofJobStatus::Pending,
             deal_id,
             required_amount,
+            options,
+            commitment_ids,
             created_at: now,
             updated_at: now,
             proof: None,
+            proof_id: None,
+            receipt_size: None,
             error: None,
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CommitmentStatus {
+    Active,
+    Superseded, // By a newer commitment from the same bank
+    Withdrawn,  // By the bank, with a signed withdrawal
+    Expired,
+}
+
+// A lending bank's commitment to a deal. Only active commitments go into proofs.
+#[derive(Debug, Clone)]
+pub struct StoredCommitment {
+    pub id: String, // Commitment hash
+    pub deal_id: String,
+    pub signed: SignedMessage,
+    pub status: CommitmentStatus,
+    pub expires_at: Option<OffsetDateTime>,
+    pub created_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+impl StoredCommitment {
+    pub fn new(
+        id: String,
+        deal_id: String,
+        signed: SignedMessage,
+        expires_at: Option<OffsetDateTime>,
+    ) -> Self {
+        let now = OffsetDateTime::now_utc();
+        Self {
+            id,
+            deal_id,
+            signed,
+            status: CommitmentStatus::Active,
+            expires_at,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn set_status(&mut self, status: CommitmentStatus) {
+        self.status = status;
+        self.updated_at = OffsetDateTime::now_utc();
+    }
+}
+
+// A generated proof, kept alongside every earlier proof for the same deal
+#[derive(Debug, Clone)]
+pub struct StoredProof {
+    pub id: String,
+    pub deal_id: String,
+    pub job_id: Option<String>, // None for synchronous proofs
+    pub required_amount: u64,
+    pub commitment_hashes: Vec<String>,
+    pub options: ProofOptions,
+    pub receipt: Receipt,
+    pub created_at: OffsetDateTime,
+}
+
+impl StoredProof {
+    pub fn new(
+        deal_id: String,
+        job_id: Option<String>,
+        required_amount: u64,
+        commitment_hashes: Vec<String>,
+        options: ProofOptions,
+        receipt: Receipt,
+    ) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            deal_id,
+            job_id,
+            required_amount,
+            commitment_hashes,
+            options,
+            receipt,
+            created_at: OffsetDateTime::now_utc(),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Storage {
-    commitments: HashMap<String, Vec<SignedMessage>>,
-    proofs: HashMap<String, Receipt>,
-    proof_jobs: HashMap<String, ProofGenerationJob>, // Indexed by job_id
+    deals: HashMap<String, Deal>,
+    commitments: HashMap<String, Vec<StoredCommitment>>, // Per deal, oldest first
+    proofs: HashMap<String, StoredProof>,                // Indexed by proof_id
+    deal_proofs: HashMap<String, Vec<String>>,           // Proof IDs per deal, oldest first
+    proof_jobs: HashMap<String, ProofGenerationJob>,     // Indexed by job_id
+    commitment_receipts: HashMap<String, Receipt>,       // Indexed by commitment hash
+    policies: HashMap<String, AcceptancePolicy>,         // Indexed by policy ID
+    auctions: HashMap<String, Auction>,
+    api_keys: HashMap<String, ApiKey>, // Indexed by key ID
 }
 
 impl Storage {
     pub fn new() -> Self {
         Self {
+            deals: HashMap::new(),
             commitments: HashMap::new(),
             proofs: HashMap::new(),
+            deal_proofs: HashMap::new(),
             proof_jobs: HashMap::new(),
+            commitment_receipts: HashMap::new(),
+            policies: HashMap::new(),
+            auctions: HashMap::new(),
+            api_keys: HashMap::new(),
         }
     }
 
-    pub fn add_commitment(&mut self, deal_id: String, commitment: SignedMessage) {
-        self.commitments
-            .entry(deal_id)
-            .or_insert_with(Vec::new)
-            .push(commitment);
+    pub fn add_deal(&mut self, deal: Deal) {
+        self.deals.insert(deal.id.clone(), deal);
+    }
+
+    pub fn get_deal(&self, deal_id: &str) -> Option<&Deal> {
+        self.deals.get(deal_id)
+    }
+
+    // Every deal, oldest first
+    pub fn get_deals(&self) -> Vec<&Deal> {
+        let mut deals: Vec<_> = self.deals.values().collect();
+        deals.sort_by_key(|deal| deal.created_at);
+        deals
+    }
+
+    pub fn update_deal(
+        &mut self,
+        deal_id: &str,
+        mut updater: impl FnMut(&mut Deal),
+    ) -> Option<Deal> {
+        let deal = self.deals.get_mut(deal_id)?;
+        deal.updated_at = OffsetDateTime::now_utc();
+        updater(deal);
+        Some(deal.clone())
     }
 
-    pub fn get_commitments(&self, deal_id: &str) -> Option<&Vec<SignedMessage>> {
-        self.commitments.get(deal_id)
+    // Adds a verified proof as the deal's next bid round
+    pub fn add_bid_round(
+        &mut self,
+        deal_id: &str,
+        verified: &VerifiedDeal,
+        proof_id: Option<String>,
+    ) -> Result<BidRound, String> {
+        let deal = self
+            .deals
+            .get_mut(deal_id)
+            .ok_or_else(|| format!("Deal {} not found", deal_id))?;
+        deal.add_round(verified, proof_id)
     }
 
-    pub fn add_proof(&mut self, deal_id: String, receipt: Receipt) {
-        self.proofs.insert(deal_id, receipt);
+    // Moves a deal along the state machine, recording the transition
+    pub fn transition_deal(
+        &mut self,
+        deal_id: &str,
+        to: DealState,
+        reason: Option<String>,
+    ) -> Result<Deal, String> {
+        let deal = self
+            .deals
+            .get_mut(deal_id)
+            .ok_or_else(|| format!("Deal {} not found", deal_id))?;
+        deal.transition(to, reason)?;
+        Ok(deal.clone())
     }
 
-    pub fn get_proof(&self, deal_id: &str) -> Option<&Receipt> {
-        self.proofs.get(deal_id)
+    // Automatic transition, only taken if the deal is still in state `from`
+    fn advance_deal(&mut self, deal_id: &str, from: DealState, to: DealState, reason: String) {
+        if let Some(deal) = self.deals.get_mut(deal_id) {
+            if deal.state == from {
+                // Always allowed, `from` -> `to` is part of the state machine
+                let _ = deal.transition(to, Some(reason));
+            }
+        }
+    }
+
+    // Stores a new active commitment, superseding the active commitment of the
+    // same bank for the deal. Returns the IDs of the superseded commitments.
+    pub fn add_commitment(&mut self, commitment: StoredCommitment) -> Vec<String> {
+        let commitments = self
+            .commitments
+            .entry(commitment.deal_id.clone())
+            .or_default();
+
+        let mut superseded = Vec::new();
+        for older in commitments.iter_mut().filter(|older| {
+            older.status == CommitmentStatus::Active
+                && older.signed.pubkey == commitment.signed.pubkey
+        }) {
+            older.set_status(CommitmentStatus::Superseded);
+            superseded.push(older.id.clone());
+        }
+
+        let reason = format!("Commitment {} submitted", commitment.id);
+        let deal_id = commitment.deal_id.clone();
+        commitments.push(commitment);
+
+        // The first commitment starts the deal's financing
+        self.advance_deal(&deal_id, DealState::Open, DealState::Financing, reason);
+        superseded
+    }
+
+    // Every commitment for the deal, whatever its status
+    pub fn get_commitments(&mut self, deal_id: &str) -> Vec<StoredCommitment> {
+        self.expire_commitments(deal_id);
+        self.commitments.get(deal_id).cloned().unwrap_or_default()
+    }
+
+    pub fn get_active_commitments(&mut self, deal_id: &str) -> Vec<SignedMessage> {
+        self.get_commitments(deal_id)
+            .into_iter()
+            .filter(|commitment| commitment.status == CommitmentStatus::Active)
+            .map(|commitment| commitment.signed)
+            .collect()
+    }
+
+    pub fn get_commitment(
+        &mut self,
+        deal_id: &str,
+        commitment_id: &str,
+    ) -> Option<StoredCommitment> {
+        self.get_commitments(deal_id)
+            .into_iter()
+            .find(|commitment| commitment.id == commitment_id)
+    }
+
+    // The deal's active commitments with the given IDs, in that order. None if
+    // any of them isn't stored for the deal or is no longer active.
+    pub fn find_commitments(
+        &mut self,
+        deal_id: &str,
+        commitment_ids: &[String],
+    ) -> Option<Vec<SignedMessage>> {
+        let commitments = self.get_commitments(deal_id);
+        commitment_ids
+            .iter()
+            .map(|id| {
+                commitments
+                    .iter()
+                    .find(|commitment| {
+                        commitment.id == *id && commitment.status == CommitmentStatus::Active
+                    })
+                    .map(|commitment| commitment.signed.clone())
+            })
+            .collect()
     }
+
+    // Marks an active commitment withdrawn, returning false if there is none.
+    // The caller checks the bank's signature.
+    pub fn withdraw_commitment(&mut self, deal_id: &str, commitment_id: &str) -> bool {
+        self.expire_commitments(deal_id);
+        match self.commitments.get_mut(deal_id).and_then(|commitments| {
+            commitments.iter_mut().find(|commitment| {
+                commitment.id == commitment_id && commitment.status == CommitmentStatus::Active
+            })
+        }) {
+            Some(commitment) => {
+                commitment.set_status(CommitmentStatus::Withdrawn);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expire_commitments(&mut self, deal_id: &str) {
+        let now = OffsetDateTime::now_utc();
+        for commitment in self.commitments.get_mut(deal_id).into_iter().flatten() {
+            if commitment.status == CommitmentStatus::Active
+                && commitment
+                    .expires_at
+                    .is_some_and(|expires_at| expires_at <= now)
+            {
+                commitment.set_status(CommitmentStatus::Expired);
+            }
+        }
+    }
+
+    pub fn add_proof(&mut self, proof: StoredProof) {
+        self.deal_proofs
+            .entry(proof.deal_id.clone())
+            .or_default()
+            .push(proof.id.clone());
+
+        // The first proof marks the deal proven
+        let reason = format!("Proof {} generated", proof.id);
+        self.advance_deal(
+            &proof.deal_id,
+            DealState::Financing,
+            DealState::Proven,
+            reason,
+        );
+        self.proofs.insert(proof.id.clone(), proof);
+    }
+
+    pub fn get_proof(&self, proof_id: &str) -> Option<&StoredProof> {
+        self.proofs.get(proof_id)
+    }
+
+    pub fn get_deal_proofs(&self, deal_id: &str) -> Vec<&StoredProof> {
+        self.deal_proofs
+            .get(deal_id)
+            .map(|ids| ids.iter().filter_map(|id| self.proofs.get(id)).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn get_latest_proof(&self, deal_id: &str) -> Option<&StoredProof> {
+        self.deal_proofs
+            .get(deal_id)
+            .and_then(|ids| ids.last())
+            .and_then(|id| self.proofs.get(id))
+    }
+
+    pub fn add_commitment_receipt(&mut self, commitment_hash: String, receipt: Receipt) {
+        self.commitment_receipts.insert(commitment_hash, receipt);
+    }
+
+    pub fn get_commitment_receipt(&self, commitment_hash: &str) -> Option<&Receipt> {
+        self.commitment_receipts.get(commitment_hash)
+    }
+
+    pub fn add_policy(&mut self, policy: AcceptancePolicy) {
+        self.policies.insert(policy.id.clone(), policy);
+    }
+
+    pub fn get_policy(&self, policy_id: &str) -> Option<&AcceptancePolicy> {
+        self.policies.get(policy_id)
+    }
+
+    pub fn add_auction(&mut self, auction: Auction) {
+        self.auctions.insert(auction.id.clone(), auction);
+    }
+
+    pub fn get_auction(&self, auction_id: &str) -> Option<&Auction> {
+        self.auctions.get(auction_id)
+    }
+
+    pub fn update_auction(
+        &mut self,
+        auction_id: &str,
+        mut updater: impl FnMut(&mut Auction),
+    ) -> Option<Auction> {
+        let auction = self.auctions.get_mut(auction_id)?;
+        updater(auction);
+        Some(auction.clone())
+    }
+
+    pub fn add_api_key(&mut self, api_key: ApiKey) {
+        self.api_keys.insert(api_key.id.clone(), api_key);
+    }
+
+    // The active key with this hash, if any
+    pub fn find_api_key(&self, key_hash: &str) -> Option<&ApiKey> {
+        self.api_keys
+            .values()
+            .find(|api_key| api_key.is_active() && api_key.key_hash == key_hash)
+    }
+
+    // Every key ever issued, oldest first
+    pub fn get_api_keys(&self) -> Vec<&ApiKey> {
+        let mut api_keys: Vec<_> = self.api_keys.values().collect();
+        api_keys.sort_by_key(|api_key| api_key.created_at);
+        api_keys
+    }
+
+    pub fn revoke_api_key(&mut self, key_id: &str) -> Option<ApiKey> {
+        let api_key = self.api_keys.get_mut(key_id)?;
+        api_key
+            .revoked_at
+            .get_or_insert_with(OffsetDateTime::now_utc);
+        Some(api_key.clone())
+    }
+
     pub fn create_proof_job(
         &mut self,
         deal_id: String,
         required_amount: u64,
+        options: ProofOptions,
+        commitment_ids: Vec<String>,
     ) -> ProofGenerationJob {
-        let job = ProofGenerationJob::new(deal_id, required_amount);
+        let job = ProofGenerationJob::new(deal_id, required_amount, options, commitment_ids);
         self.proof_jobs.insert(job.id.clone(), job.clone());
         job
     }
@@ -93,21 +447,6 @@ impl Storage {
         self.proof_jobs.get(job_id)
     }
 
-    // Modified to return both job info and receipt if available
-    pub fn get_proof_job_with_receipt(
-        &self,
-        job_id: &str,
-    ) -> Option<(ProofGenerationJob, Option<&Receipt>)> {
-        self.proof_jobs.get(job_id).map(|job| {
-            let receipt = if job.status == ProofJobStatus::Completed {
-                self.get_proof(&job.deal_id)
-            } else {
-                None
-            };
-            (job.clone(), receipt)
-        })
-    }
-
     pub fn update_proof_job(
         &mut self,
         job_id: &str,
@@ -122,3 +461,107 @@ impl Storage {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rwz_pof_core::{create_signed_message, get_deterministic_signing_key};
+    use time::Duration;
+
+    fn storage() -> Storage {
+        let mut storage = Storage::new();
+        storage.add_deal(Deal::new(
+            "DEAL1".to_string(),
+            "buyer123".to_string(),
+            "seller123".to_string(),
+            100,
+            "USD".to_string(),
+            None,
+        ));
+        storage
+    }
+
+    fn commitment(
+        id: &str,
+        bank_index: u64,
+        amount: u64,
+        expires_at: Option<OffsetDateTime>,
+    ) -> StoredCommitment {
+        let signed = create_signed_message(
+            &get_deterministic_signing_key(bank_index),
+            amount,
+            "DEAL1".to_string(),
+            "buyer123".to_string(),
+        )
+        .unwrap();
+        StoredCommitment::new(id.to_string(), "DEAL1".to_string(), signed, expires_at)
+    }
+
+    fn status(storage: &mut Storage, id: &str) -> CommitmentStatus {
+        storage.get_commitment("DEAL1", id).unwrap().status
+    }
+
+    #[test]
+    fn test_commitment_statuses() {
+        let mut storage = storage();
+        assert!(storage
+            .add_commitment(commitment("a1", 0, 40, None))
+            .is_empty());
+        assert_eq!(
+            storage.get_deal("DEAL1").unwrap().state,
+            DealState::Financing
+        );
+
+        // A bank's newer commitment supersedes its older one, not other banks'
+        storage.add_commitment(commitment("b1", 1, 30, None));
+        assert_eq!(
+            storage.add_commitment(commitment("a2", 0, 50, None)),
+            vec!["a1".to_string()]
+        );
+        assert_eq!(status(&mut storage, "a1"), CommitmentStatus::Superseded);
+        assert_eq!(status(&mut storage, "b1"), CommitmentStatus::Active);
+
+        assert!(storage.withdraw_commitment("DEAL1", "b1"));
+        assert_eq!(status(&mut storage, "b1"), CommitmentStatus::Withdrawn);
+        assert!(!storage.withdraw_commitment("DEAL1", "b1"));
+        assert!(!storage.withdraw_commitment("DEAL1", "a1"));
+
+        let past = OffsetDateTime::now_utc() - Duration::seconds(1);
+        storage.add_commitment(commitment("c1", 2, 20, Some(past)));
+        assert_eq!(status(&mut storage, "c1"), CommitmentStatus::Expired);
+        assert!(!storage.withdraw_commitment("DEAL1", "c1"));
+
+        let active = storage.get_active_commitments("DEAL1");
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].message.amount, 50);
+    }
+
+    #[test]
+    fn test_proof_jobs_use_only_active_commitments() {
+        let mut storage = storage();
+        storage.add_commitment(commitment("a1", 0, 40, None));
+        storage.add_commitment(commitment("b1", 1, 30, None));
+
+        let ids = vec!["a1".to_string(), "b1".to_string()];
+        let job = storage.create_proof_job("DEAL1".to_string(), 70, Default::default(), ids);
+        let found = storage
+            .find_commitments(&job.deal_id, &job.commitment_ids)
+            .unwrap();
+        assert_eq!(found[0].message.amount, 40);
+        assert_eq!(found[1].message.amount, 30);
+
+        // Once queued, a superseded or withdrawn commitment fails the job
+        storage.add_commitment(commitment("a2", 0, 45, None));
+        assert!(storage
+            .find_commitments(&job.deal_id, &job.commitment_ids)
+            .is_none());
+
+        let ids = vec!["a2".to_string(), "b1".to_string()];
+        assert!(storage.find_commitments("DEAL1", &ids).is_some());
+        storage.withdraw_commitment("DEAL1", "b1");
+        assert!(storage.find_commitments("DEAL1", &ids).is_none());
+        assert!(storage
+            .find_commitments("DEAL1", &["unknown".to_string()])
+            .is_none());
+    }
+}
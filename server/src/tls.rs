@@ -0,0 +1,339 @@
+use crate::auth::{Principal, Role};
+use hyper::server::conn::Http;
+use hyper::service::{service_fn, Service};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use serde::Deserialize;
+use simple_asn1::{ASN1Block, OID};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use warp::{Filter, Reply};
+
+// X.520 attributes read from client certificate subjects
+const COMMON_NAME: [u64; 4] = [2, 5, 4, 3];
+const SERIAL_NUMBER: [u64; 4] = [2, 5, 4, 5];
+const ORGANIZATIONAL_UNIT: [u64; 4] = [2, 5, 4, 11];
+
+// Whether clients must present a certificate signed by the client CA
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientAuth {
+    #[default]
+    Optional, // Clients without one authenticate with a bearer credential
+    Required,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf, // PEM chain, leaf first
+    pub key: PathBuf,  // PEM private key
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+    #[serde(default)]
+    pub client_auth: ClientAuth,
+}
+
+impl TlsConfig {
+    pub fn acceptor(&self) -> Result<TlsAcceptor, String> {
+        let provider = Arc::new(ring::default_provider());
+        let certs = CertificateDer::pem_file_iter(&self.cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| format!("Failed to read {}: {}", self.cert.display(), e))?;
+        let key = PrivateKeyDer::from_pem_file(&self.key)
+            .map_err(|e| format!("Failed to read {}: {}", self.key.display(), e))?;
+
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?;
+        let builder = match &self.client_ca {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in CertificateDer::pem_file_iter(path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+                {
+                    let cert =
+                        cert.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                    roots.add(cert).map_err(|e| e.to_string())?;
+                }
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+                let verifier = match self.client_auth {
+                    ClientAuth::Optional => verifier.allow_unauthenticated(),
+                    ClientAuth::Required => verifier,
+                };
+                builder.with_client_cert_verifier(verifier.build().map_err(|e| e.to_string())?)
+            }
+            None if self.client_auth == ClientAuth::Required => {
+                return Err("Required client certificates need a client CA".to_string())
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut config = builder
+            .with_single_cert(certs, key)
+            .map_err(|e| e.to_string())?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+// The principal of a verified client certificate, added to the request's
+// extensions for `auth::authorize`
+#[derive(Debug, Clone)]
+pub struct ClientIdentity(pub Principal);
+
+// Maps a client certificate's subject to a principal: the role from its OU
+// ("lending_bank", "buyers_bank", "sellers_bank" or "admin"), and from its
+// serialNumber a lending bank's registry index or the deal party a buyer's or
+// seller's bank acts for
+pub fn client_principal(cert: &[u8]) -> Result<Principal, String> {
+    let subject = certificate_subject(cert)?;
+    let attribute = |oid: &[u64]| {
+        subject
+            .iter()
+            .find(|(attribute, _)| attribute.as_slice() == oid)
+            .map(|(_, value)| value.as_str())
+    };
+
+    let party = || {
+        attribute(&SERIAL_NUMBER)
+            .map(String::from)
+            .ok_or("Certificate without a serialNumber naming its party")
+    };
+    let role = match attribute(&ORGANIZATIONAL_UNIT) {
+        Some("lending_bank") => {
+            let bank_index = attribute(&SERIAL_NUMBER)
+                .and_then(|index| index.parse().ok())
+                .ok_or("Lending bank certificate without a numeric serialNumber")?;
+            Role::LendingBank { bank_index }
+        }
+        Some("buyers_bank") => Role::BuyersBank { buyer: party()? },
+        Some("sellers_bank") => Role::SellersBank { seller: party()? },
+        Some("admin") => Role::Admin,
+        other => return Err(format!("Unknown certificate OU {:?}", other)),
+    };
+
+    Ok(Principal {
+        subject: format!("cert:{}", attribute(&COMMON_NAME).unwrap_or_default()),
+        role,
+    })
+}
+
+// The subject's attributes as (OID, value) pairs
+fn certificate_subject(cert: &[u8]) -> Result<Vec<(Vec<u64>, String)>, String> {
+    let invalid = || "Invalid certificate".to_string();
+    let blocks = simple_asn1::from_der(cert).map_err(|e| e.to_string())?;
+    let Some(ASN1Block::Sequence(_, certificate)) = blocks.first() else {
+        return Err(invalid());
+    };
+    let Some(ASN1Block::Sequence(_, tbs)) = certificate.first() else {
+        return Err(invalid());
+    };
+    // Serial, signature algorithm, issuer, validity, then the subject. The
+    // version and extensions are the explicitly tagged fields.
+    let Some(ASN1Block::Sequence(_, names)) = tbs
+        .iter()
+        .filter(|block| !matches!(block, ASN1Block::Explicit(..)))
+        .nth(4)
+    else {
+        return Err(invalid());
+    };
+
+    let mut attributes = Vec::new();
+    for name in names {
+        let ASN1Block::Set(_, pairs) = name else {
+            return Err(invalid());
+        };
+        for pair in pairs {
+            if let ASN1Block::Sequence(_, pair) = pair {
+                if let [ASN1Block::ObjectIdentifier(_, oid), value] = pair.as_slice() {
+                    if let Some(value) = string_value(value) {
+                        attributes.push((oid_arcs(oid)?, value));
+                    }
+                }
+            }
+        }
+    }
+    Ok(attributes)
+}
+
+fn oid_arcs(oid: &OID) -> Result<Vec<u64>, String> {
+    oid.as_vec::<u64>().map_err(|e| e.to_string())
+}
+
+fn string_value(block: &ASN1Block) -> Option<String> {
+    match block {
+        ASN1Block::UTF8String(_, value)
+        | ASN1Block::PrintableString(_, value)
+        | ASN1Block::IA5String(_, value)
+        | ASN1Block::TeletexString(_, value)
+        | ASN1Block::UniversalString(_, value)
+        | ASN1Block::BMPString(_, value) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+// Serves the routes over TLS. A verified client certificate that maps to a
+// principal authenticates every request on its connection.
+pub async fn serve<F>(routes: F, addr: SocketAddr, acceptor: TlsAcceptor) -> std::io::Result<()>
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    serve_on(routes, TcpListener::bind(addr).await?, acceptor).await
+}
+
+pub async fn serve_on<F>(
+    routes: F,
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+) -> std::io::Result<()>
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let service = warp::service(routes);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let service = service.clone();
+
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    println!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+
+            let identity = match stream.get_ref().1.peer_certificates() {
+                Some([cert, ..]) => match client_principal(cert) {
+                    Ok(principal) => {
+                        println!("Client {} authenticated as {}", peer, principal.subject);
+                        Some(ClientIdentity(principal))
+                    }
+                    Err(e) => {
+                        println!("Ignoring client certificate from {}: {}", peer, e);
+                        None
+                    }
+                },
+                _ => None,
+            };
+
+            let connection = service_fn(move |mut req: hyper::Request<hyper::Body>| {
+                if let Some(identity) = identity.clone() {
+                    req.extensions_mut().insert(identity);
+                }
+                let mut service = service.clone();
+                async move {
+                    Ok::<_, Infallible>(service.call(req).await.unwrap_or_else(|e| match e {}))
+                }
+            });
+            if let Err(e) = Http::new().serve_connection(stream, connection).await {
+                println!("Connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{authorize, Access, Authenticator};
+    use crate::storage::Storage;
+    use std::sync::Mutex;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls");
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(FIXTURES).join(name)
+    }
+
+    fn pem_der(name: &str) -> Vec<u8> {
+        CertificateDer::from_pem_file(fixture(name))
+            .unwrap()
+            .to_vec()
+    }
+
+    #[test]
+    fn test_maps_certificate_subject() {
+        let principal = client_principal(&pem_der("lending-bank-1.pem")).unwrap();
+        assert_eq!(principal.role, Role::LendingBank { bank_index: 1 });
+        assert_eq!(principal.subject, "cert:LB2");
+
+        let principal = client_principal(&pem_der("sellers-bank.pem")).unwrap();
+        assert_eq!(
+            principal.role,
+            Role::SellersBank {
+                seller: "seller123".to_string()
+            }
+        );
+
+        // The server certificate names no role
+        assert!(client_principal(&pem_der("server.pem")).is_err());
+    }
+
+    fn client(identity: Option<&str>) -> reqwest::Client {
+        let ca = std::fs::read(fixture("ca.pem")).unwrap();
+        let mut builder = reqwest::Client::builder()
+            .use_rustls_tls()
+            .add_root_certificate(reqwest::Certificate::from_pem(&ca).unwrap());
+        if let Some(name) = identity {
+            let mut pem = std::fs::read(fixture(&format!("{}.pem", name))).unwrap();
+            pem.extend(std::fs::read(fixture(&format!("{}.key", name))).unwrap());
+            builder = builder.identity(reqwest::Identity::from_pem(&pem).unwrap());
+        }
+        builder.build().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mutual_tls() {
+        let auth = Arc::new(Authenticator::new(
+            Arc::new(Mutex::new(Storage::new())),
+            None,
+        ));
+        let routes = warp::path("whoami")
+            .and(authorize(auth, Access::LendingBank))
+            .map(|principal: Principal| principal.subject)
+            .recover(crate::handlers::handle_rejection);
+
+        let acceptor = TlsConfig {
+            cert: fixture("server.pem"),
+            key: fixture("server.key"),
+            client_ca: Some(fixture("ca.pem")),
+            client_auth: ClientAuth::Optional,
+        }
+        .acceptor()
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "https://localhost:{}/whoami",
+            listener.local_addr().unwrap().port()
+        );
+        tokio::spawn(serve_on(routes, listener, acceptor));
+
+        let response = client(Some("lending-bank-1"))
+            .get(&url)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "cert:LB2");
+
+        // A seller's bank certificate has the wrong role, no certificate is
+        // no credential
+        let response = client(Some("sellers-bank")).get(&url).send().await.unwrap();
+        assert_eq!(response.status(), 403);
+        let response = client(None).get(&url).send().await.unwrap();
+        assert_eq!(response.status(), 401);
+    }
+}
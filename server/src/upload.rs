@@ -0,0 +1,87 @@
+use bytes::Buf;
+use futures_util::TryStreamExt;
+use risc0_zkvm::Receipt;
+use rwz_pof_core::{ProofBundle, BUNDLE_MAGIC};
+use serde::Deserialize;
+use warp::multipart::{FormData, Part};
+
+// Largest proof upload accepted on /sb/verify, composite receipts are a few MB
+pub const MAX_UPLOAD_BYTES: u64 = 64 * 1024 * 1024;
+
+// A proof to verify, uploaded by the seller or looked up in storage
+pub enum ProofArtifact {
+    Bundle(ProofBundle),
+    Receipt(Receipt),
+}
+
+// Verification parameters sent alongside a binary or multipart upload
+#[derive(Debug, Default, Deserialize)]
+pub struct UploadParams {
+    pub policy_id: Option<String>,
+    pub bid_amount: Option<u64>,
+}
+
+impl ProofArtifact {
+    // A .pof bundle if it starts with the bundle magic, a bincode receipt otherwise
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.starts_with(BUNDLE_MAGIC) {
+            ProofBundle::from_bytes(bytes)
+                .map(Self::Bundle)
+                .map_err(|e| format!("Invalid proof bundle: {}", e))
+        } else {
+            bincode::deserialize(bytes)
+                .map(Self::Receipt)
+                .map_err(|e| format!("Invalid receipt: {}", e))
+        }
+    }
+
+    // Reads a form with a `bundle` or `receipt` file part, and optional
+    // `policy_id` and `bid_amount` fields
+    pub async fn from_form(mut form: FormData) -> Result<(Self, UploadParams), String> {
+        let mut proof = None;
+        let mut params = UploadParams::default();
+
+        // Each part must be read before the next one is requested
+        while let Some(part) = form
+            .try_next()
+            .await
+            .map_err(|e| format!("Invalid multipart form: {}", e))?
+        {
+            let name = part.name().to_string();
+            let data = read_part(part).await?;
+
+            match name.as_str() {
+                "bundle" | "receipt" => proof = Some(Self::from_bytes(&data)?),
+                "policy_id" => params.policy_id = Some(text_field(&name, &data)?),
+                "bid_amount" => {
+                    let bid_amount = text_field(&name, &data)?;
+                    params.bid_amount = Some(
+                        bid_amount
+                            .parse()
+                            .map_err(|_| format!("Invalid bid_amount {}", bid_amount))?,
+                    );
+                }
+                _ => return Err(format!("Unexpected form field {}", name)),
+            }
+        }
+
+        let proof = proof.ok_or("Form has no bundle or receipt part")?;
+        Ok((proof, params))
+    }
+}
+
+async fn read_part(part: Part) -> Result<Vec<u8>, String> {
+    part.stream()
+        .try_fold(Vec::new(), |mut data, chunk| async move {
+            data.extend_from_slice(chunk.chunk());
+            Ok(data)
+        })
+        .await
+        .map_err(|e| format!("Failed to read form field: {}", e))
+}
+
+fn text_field(name: &str, data: &[u8]) -> Result<String, String> {
+    String::from_utf8(data.to_vec())
+        .map(|value| value.trim().to_string())
+        .map_err(|_| format!("Form field {} is not text", name))
+}
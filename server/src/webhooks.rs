@@ -0,0 +1,663 @@
+use crate::auth::Role;
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use time::serde::iso8601;
+use time::OffsetDateTime;
+
+// Prefix of subscription secrets, like issued API keys
+const SECRET_PREFIX: &str = "whsec_";
+
+// Headers of every delivery
+pub const EVENT_HEADER: &str = "X-RWZ-Event";
+pub const DELIVERY_HEADER: &str = "X-RWZ-Delivery";
+pub const SIGNATURE_HEADER: &str = "X-RWZ-Signature";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventType {
+    ProofCompleted, // An asynchronous proof job finished
+    ProofFailed,
+    CommitmentCreated,
+    VerificationCompleted, // A proof was verified on /sb/verify, or failed to
+}
+
+// Where to deliver which events. Subscriptions without a deal get the events
+// of every deal. A lending bank's subscription only gets the events about its
+// own commitments.
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookSubscription {
+    pub id: String,
+    pub url: String,
+    pub deal_id: Option<String>,
+    pub events: Vec<WebhookEventType>, // Every event if empty
+    pub owner: String,                 // Subject of the principal that registered it
+    #[serde(skip)]
+    pub role: Role,  // Of the principal that registered it
+    #[serde(skip)]
+    pub secret: String, // HMAC key, shown once when registering
+    #[serde(with = "iso8601")]
+    pub created_at: OffsetDateTime,
+}
+
+// The JSON body of a delivery
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookEvent {
+    pub id: String, // Same for every attempt and subscription
+    pub event: WebhookEventType,
+    pub deal_id: Option<String>,
+    pub data: Value,
+    #[serde(with = "iso8601")]
+    pub created_at: OffsetDateTime,
+    #[serde(skip)]
+    pub bank_pubkey: Option<Vec<u8>>, // Lending bank whose commitment the event is about
+}
+
+// A delivery that failed every attempt
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetter {
+    pub subscription_id: String,
+    pub url: String,
+    pub event: WebhookEvent,
+    pub attempts: u32,
+    pub last_error: String,
+    #[serde(with = "iso8601")]
+    pub failed_at: OffsetDateTime,
+}
+
+// How often and how patiently deliveries are retried, and where they may go
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64, // Doubled after every failed attempt
+    pub max_backoff_ms: u64,
+    pub timeout_secs: u64,          // Per attempt
+    pub allowed_hosts: Vec<String>, // May be, or resolve to, loopback or private addresses
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 60_000,
+            timeout_secs: 10,
+            allowed_hosts: Vec::new(),
+        }
+    }
+}
+
+impl WebhookConfig {
+    // Wait before the attempt after `attempt`, counting from 1
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+        Duration::from_millis(
+            self.initial_backoff_ms
+                .saturating_mul(factor)
+                .min(self.max_backoff_ms),
+        )
+    }
+
+    fn allows_host(&self, host: &str) -> bool {
+        self.allowed_hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    }
+
+    // Receivers must be http or https, on a public address unless their host
+    // is allowed. Names are checked again whenever a delivery resolves them.
+    pub async fn check_target(&self, url: &str) -> Result<(), String> {
+        let url = Url::parse(url).map_err(|e| format!("{} isn't a valid URL: {}", url, e))?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(format!("{} isn't an http or https URL", url));
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| format!("{} has no host", url))?;
+        if self.allows_host(host) {
+            return Ok(());
+        }
+
+        let addrs = match literal_ip(host) {
+            Some(ip) => vec![SocketAddr::new(ip, 0)],
+            None => tokio::net::lookup_host((host, 0))
+                .await
+                .map_err(|e| format!("{} doesn't resolve: {}", host, e))?
+                .collect(),
+        };
+        check_public(host, &addrs)
+    }
+}
+
+// A URL host that is an IP address, which skips name resolution. IPv6 hosts
+// are bracketed.
+fn literal_ip(host: &str) -> Option<IpAddr> {
+    host.strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host)
+        .parse()
+        .ok()
+}
+
+// Refuses loopback, link-local, private and other non-public addresses, so
+// subscriptions can't make the server call services on its own network
+fn check_public(host: &str, addrs: &[SocketAddr]) -> Result<(), String> {
+    match addrs.iter().find(|addr| !is_public(addr.ip())) {
+        Some(addr) => Err(format!("{} is a non-public address ({})", host, addr.ip())),
+        None => Ok(()),
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || (a == 100 && (64..128).contains(&b))) // Carrier-grade NAT
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+                    || ip.is_multicast())
+            }
+        },
+    }
+}
+
+// Resolves delivery hosts, refusing names that resolve to non-public addresses
+// unless they are allowed. Checked on every connection, so a name can't be
+// pointed at an internal address after the subscription was accepted.
+struct PublicResolver {
+    config: WebhookConfig,
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        let allowed = self.config.allows_host(&host);
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if !allowed {
+                check_public(&host, &addrs)?;
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+impl WebhookSubscription {
+    pub fn new(
+        url: String,
+        deal_id: Option<String>,
+        events: Vec<WebhookEventType>,
+        owner: String,
+        role: Role,
+    ) -> Self {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            url,
+            deal_id,
+            events,
+            owner,
+            role,
+            secret: format!("{}{}", SECRET_PREFIX, hex::encode(secret)),
+            created_at: OffsetDateTime::now_utc(),
+        }
+    }
+
+    fn matches(&self, event: &WebhookEvent) -> bool {
+        (self.events.is_empty() || self.events.contains(&event.event))
+            && (self.deal_id.is_none() || self.deal_id == event.deal_id)
+            && match self.role {
+                // Not their competitors' commitments, proofs or verifications
+                Role::LendingBank { .. } => event
+                    .bank_pubkey
+                    .as_ref()
+                    .is_some_and(|pubkey| self.role.is_bank_key(pubkey)),
+                _ => true,
+            }
+    }
+}
+
+// `t=<unix seconds>,v1=<hex HMAC-SHA256 of "<t>.<body>">`. The timestamp lets
+// receivers refuse replayed deliveries.
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    format!(
+        "t={},v1={}",
+        timestamp,
+        hex::encode(mac(secret, timestamp, body).finalize().into_bytes())
+    )
+}
+
+fn mac(secret: &str, timestamp: i64, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+// Webhook subscriptions, and the deliveries that failed every attempt
+pub struct Webhooks {
+    client: reqwest::Client,
+    config: WebhookConfig,
+    subscriptions: Mutex<HashMap<String, WebhookSubscription>>, // Indexed by ID
+    dead_letters: Mutex<Vec<DeadLetter>>,
+}
+
+impl Webhooks {
+    pub fn new(config: WebhookConfig) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(config.timeout_secs))
+                .dns_resolver(Arc::new(PublicResolver {
+                    config: config.clone(),
+                }))
+                // A redirect could lead to an address the target check refused
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .expect("Failed to build the webhook HTTP client"),
+            config,
+            subscriptions: Mutex::new(HashMap::new()),
+            dead_letters: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn subscribe(&self, subscription: WebhookSubscription) {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .insert(subscription.id.clone(), subscription);
+    }
+
+    pub fn get_subscription(&self, id: &str) -> Option<WebhookSubscription> {
+        self.subscriptions.lock().unwrap().get(id).cloned()
+    }
+
+    // Oldest first
+    pub fn get_subscriptions(&self) -> Vec<WebhookSubscription> {
+        let mut subscriptions: Vec<_> = self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        subscriptions.sort_by_key(|subscription| subscription.created_at);
+        subscriptions
+    }
+
+    pub fn unsubscribe(&self, id: &str) -> Option<WebhookSubscription> {
+        self.subscriptions.lock().unwrap().remove(id)
+    }
+
+    pub fn get_dead_letters(&self) -> Vec<DeadLetter> {
+        self.dead_letters.lock().unwrap().clone()
+    }
+
+    pub async fn check_target(&self, url: &str) -> Result<(), String> {
+        self.config.check_target(url).await
+    }
+
+    // Delivers the event to every matching subscription in the background
+    pub fn notify(self: &Arc<Self>, event: WebhookEventType, deal_id: Option<&str>, data: Value) {
+        self.publish(event, deal_id, None, data);
+    }
+
+    // Like `notify`, for an event about a lending bank's commitment, which
+    // that bank's subscriptions get too
+    pub fn notify_bank(
+        self: &Arc<Self>,
+        event: WebhookEventType,
+        deal_id: Option<&str>,
+        bank_pubkey: &[u8],
+        data: Value,
+    ) {
+        self.publish(event, deal_id, Some(bank_pubkey.to_vec()), data);
+    }
+
+    fn publish(
+        self: &Arc<Self>,
+        event: WebhookEventType,
+        deal_id: Option<&str>,
+        bank_pubkey: Option<Vec<u8>>,
+        data: Value,
+    ) {
+        let event = WebhookEvent {
+            id: uuid::Uuid::new_v4().to_string(),
+            event,
+            deal_id: deal_id.map(String::from),
+            data,
+            created_at: OffsetDateTime::now_utc(),
+            bank_pubkey,
+        };
+        let subscriptions: Vec<_> = self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|subscription| subscription.matches(&event))
+            .cloned()
+            .collect();
+
+        for subscription in subscriptions {
+            let webhooks = self.clone();
+            let event = event.clone();
+            tokio::spawn(async move { webhooks.deliver(subscription, event).await });
+        }
+    }
+
+    // Retries with exponential backoff, then records a dead letter
+    async fn deliver(&self, subscription: WebhookSubscription, event: WebhookEvent) {
+        let body = match serde_json::to_vec(&event) {
+            Ok(body) => body,
+            Err(e) => return println!("Failed to encode webhook event {}: {}", event.id, e),
+        };
+
+        let mut last_error = String::new();
+        for attempt in 1..=self.config.max_attempts {
+            match self.send(&subscription, &event, &body).await {
+                Ok(()) => return,
+                Err(e) => {
+                    println!(
+                        "Webhook {} to {} failed (attempt {} of {}): {}",
+                        event.id, subscription.url, attempt, self.config.max_attempts, e
+                    );
+                    last_error = e;
+                }
+            }
+            if attempt < self.config.max_attempts {
+                tokio::time::sleep(self.config.backoff(attempt)).await;
+            }
+        }
+
+        self.dead_letters.lock().unwrap().push(DeadLetter {
+            subscription_id: subscription.id,
+            url: subscription.url,
+            event,
+            attempts: self.config.max_attempts,
+            last_error,
+            failed_at: OffsetDateTime::now_utc(),
+        });
+    }
+
+    async fn send(
+        &self,
+        subscription: &WebhookSubscription,
+        event: &WebhookEvent,
+        body: &[u8],
+    ) -> Result<(), String> {
+        // Names are checked by the resolver, but addresses aren't resolved
+        let url = Url::parse(&subscription.url).map_err(|e| e.to_string())?;
+        let host = url.host_str().unwrap_or_default();
+        if let Some(ip) = literal_ip(host).filter(|_| !self.config.allows_host(host)) {
+            check_public(host, &[SocketAddr::new(ip, 0)])?;
+        }
+
+        // Signed per attempt, so a retry carries a fresh timestamp
+        let timestamp = OffsetDateTime::now_utc().unix_timestamp();
+        let event_name = serde_json::to_value(event.event)
+            .ok()
+            .and_then(|name| name.as_str().map(String::from))
+            .unwrap_or_default();
+
+        let response = self
+            .client
+            .post(&subscription.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, event_name)
+            .header(DELIVERY_HEADER, &event.id)
+            .header(
+                SIGNATURE_HEADER,
+                sign(&subscription.secret, timestamp, body),
+            )
+            .body(body.to_vec())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Receiver answered {}", response.status()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rwz_pof_core::registry_pubkeys;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::mpsc;
+    use warp::http::{HeaderMap, StatusCode};
+    use warp::Filter;
+
+    // A local receiver failing the first `failures` requests, forwarding the
+    // others' signature header and body
+    async fn receiver(failures: usize) -> (String, mpsc::UnboundedReceiver<(String, Vec<u8>)>) {
+        let (sender, deliveries) = mpsc::unbounded_channel();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let route = warp::post()
+            .and(warp::header::headers_cloned())
+            .and(warp::body::bytes())
+            .map(move |headers: HeaderMap, body: bytes::Bytes| {
+                if requests.fetch_add(1, Ordering::SeqCst) < failures {
+                    return StatusCode::INTERNAL_SERVER_ERROR;
+                }
+                let signature = headers[SIGNATURE_HEADER].to_str().unwrap().to_string();
+                sender.send((signature, body.to_vec())).unwrap();
+                StatusCode::NO_CONTENT
+            });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (format!("http://{}/hook", addr), deliveries)
+    }
+
+    // Checks a signature header as a receiver would, accepting timestamps at
+    // most `tolerance` away from `now`
+    fn verify_signature(
+        secret: &str,
+        header: &str,
+        body: &[u8],
+        now: i64,
+        tolerance: Duration,
+    ) -> Result<(), String> {
+        let mut timestamp = None;
+        let mut signature = None;
+        for part in header.split(',') {
+            match part.trim().split_once('=') {
+                Some(("t", value)) => timestamp = value.parse::<i64>().ok(),
+                Some(("v1", value)) => signature = hex::decode(value).ok(),
+                _ => {}
+            }
+        }
+        let (Some(timestamp), Some(signature)) = (timestamp, signature) else {
+            return Err("Malformed signature header".to_string());
+        };
+        if timestamp.abs_diff(now) > tolerance.as_secs() {
+            return Err(format!("Timestamp {} is outside the tolerance", timestamp));
+        }
+        mac(secret, timestamp, body)
+            .verify_slice(&signature)
+            .map_err(|_| "Signature doesn't match".to_string())
+    }
+
+    fn fast_retries(max_attempts: u32) -> WebhookConfig {
+        WebhookConfig {
+            max_attempts,
+            initial_backoff_ms: 10,
+            max_backoff_ms: 20,
+            timeout_secs: 5,
+            allowed_hosts: vec!["127.0.0.1".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_cap() {
+        let retry = WebhookConfig::default();
+        assert_eq!(retry.backoff(1), Duration::from_secs(1));
+        assert_eq!(retry.backoff(3), Duration::from_secs(4));
+        assert_eq!(retry.backoff(40), Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_delivers_signed_events_after_retries() {
+        let (url, mut deliveries) = receiver(2).await;
+        let webhooks = Arc::new(Webhooks::new(fast_retries(3)));
+        let subscription = WebhookSubscription::new(
+            url,
+            Some("DEAL123".to_string()),
+            vec![WebhookEventType::CommitmentCreated],
+            "key:test".to_string(),
+            Role::Admin,
+        );
+        let secret = subscription.secret.clone();
+        webhooks.subscribe(subscription);
+
+        // Another deal and another event type aren't delivered
+        webhooks.notify(
+            WebhookEventType::CommitmentCreated,
+            Some("DEAL456"),
+            json!({}),
+        );
+        webhooks.notify(WebhookEventType::ProofFailed, Some("DEAL123"), json!({}));
+        webhooks.notify(
+            WebhookEventType::CommitmentCreated,
+            Some("DEAL123"),
+            json!({ "commitment_id": "abc" }),
+        );
+
+        let (signature, body) = deliveries.recv().await.unwrap();
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        verify_signature(&secret, &signature, &body, now, Duration::from_secs(300)).unwrap();
+        assert!(verify_signature(
+            "whsec_other",
+            &signature,
+            &body,
+            now,
+            Duration::from_secs(300)
+        )
+        .is_err());
+
+        let event: WebhookEvent = serde_json::from_slice(&body).unwrap();
+        assert_eq!(event.event, WebhookEventType::CommitmentCreated);
+        assert_eq!(event.data["commitment_id"], "abc");
+        assert!(webhooks.get_dead_letters().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_records_dead_letters() {
+        let (url, _deliveries) = receiver(usize::MAX).await;
+        let webhooks = Arc::new(Webhooks::new(fast_retries(2)));
+        webhooks.subscribe(WebhookSubscription::new(
+            url.clone(),
+            None,
+            Vec::new(),
+            "key:test".to_string(),
+            Role::Admin,
+        ));
+
+        webhooks.notify(WebhookEventType::ProofCompleted, Some("DEAL123"), json!({}));
+
+        for _ in 0..100 {
+            if !webhooks.get_dead_letters().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let dead_letters = webhooks.get_dead_letters();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].url, url);
+        assert_eq!(dead_letters[0].attempts, 2);
+        assert!(dead_letters[0].last_error.contains("500"));
+    }
+
+    #[tokio::test]
+    async fn test_lending_banks_only_hear_about_their_own_commitments() {
+        let (url, mut deliveries) = receiver(0).await;
+        let webhooks = Arc::new(Webhooks::new(fast_retries(1)));
+        webhooks.subscribe(WebhookSubscription::new(
+            url,
+            Some("DEAL123".to_string()),
+            Vec::new(),
+            "key:lb1".to_string(),
+            Role::LendingBank { bank_index: 0 },
+        ));
+        let pubkeys = registry_pubkeys();
+
+        // A competitor's commitment and the buyer's proofs aren't delivered
+        webhooks.notify_bank(
+            WebhookEventType::CommitmentCreated,
+            Some("DEAL123"),
+            &pubkeys[1],
+            json!({ "commitment_id": "theirs" }),
+        );
+        webhooks.notify(WebhookEventType::ProofCompleted, Some("DEAL123"), json!({}));
+        webhooks.notify_bank(
+            WebhookEventType::CommitmentCreated,
+            Some("DEAL123"),
+            &pubkeys[0],
+            json!({ "commitment_id": "own" }),
+        );
+
+        let (_, body) = deliveries.recv().await.unwrap();
+        let event: WebhookEvent = serde_json::from_slice(&body).unwrap();
+        assert_eq!(event.data["commitment_id"], "own");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(deliveries.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_refuses_internal_targets() {
+        let config = WebhookConfig::default();
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://localhost/hook",
+            "http://[::1]/hook",
+            "http://10.0.0.5/hook",
+            "http://192.168.1.1/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[fd00::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+            "ftp://203.0.114.1/hook",
+        ] {
+            assert!(config.check_target(url).await.is_err(), "{}", url);
+        }
+        config.check_target("https://8.8.8.8/hook").await.unwrap();
+
+        // Unless the host is allowed
+        let config = WebhookConfig {
+            allowed_hosts: vec!["127.0.0.1".to_string()],
+            ..Default::default()
+        };
+        config
+            .check_target("http://127.0.0.1:8080/hook")
+            .await
+            .unwrap();
+    }
+}
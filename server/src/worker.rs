@@ -1,70 +1,219 @@
-use crate::storage::{ProofJobStatus, Storage};
-use rwz_pof_core::generate_proof;
+use crate::events::{JobEventKind, JobEvents};
+use crate::storage::{ProofJobStatus, Storage, StoredProof};
+use crate::webhooks::{WebhookEventType, Webhooks};
+use risc0_zkvm::Receipt;
+use rwz_pof_core::{
+    aggregate_proof, commitment_hash, prove_commitment, receipt_size, CoreError, SignedMessage,
+};
+use serde_json::json;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
 
+/// Returns a sub-receipt for each commitment, proving only the ones not yet cached.
+/// `on_receipt` is called after each one, with whether it was cached.
+pub fn commitment_receipts(
+    commitments: &[SignedMessage],
+    storage: &Arc<Mutex<Storage>>,
+    on_receipt: impl Fn(bool),
+) -> Result<Vec<Receipt>, CoreError> {
+    let mut receipts = Vec::with_capacity(commitments.len());
+
+    for commitment in commitments {
+        let hash = commitment_hash(commitment)?;
+
+        let cached = {
+            let storage = storage.lock().unwrap();
+            storage.get_commitment_receipt(&hash).cloned()
+        };
+
+        let cached_receipt = cached.is_some();
+        let receipt = match cached {
+            Some(receipt) => receipt,
+            None => {
+                println!("Proving commitment {}", hash);
+                // Don't hold the lock while proving
+                let receipt = prove_commitment(commitment)?;
+                let mut storage = storage.lock().unwrap();
+                storage.add_commitment_receipt(hash, receipt.clone());
+                receipt
+            }
+        };
+
+        receipts.push(receipt);
+        on_receipt(cached_receipt);
+    }
+
+    Ok(receipts)
+}
+
+/// Hashes identifying the commitments a proof was generated from.
+pub fn commitment_hashes(commitments: &[SignedMessage]) -> Result<Vec<String>, CoreError> {
+    commitments.iter().map(commitment_hash).collect()
+}
+
+// Runs asynchronous proof jobs, at most `concurrency` at once. Queued jobs
+// stay pending until a slot frees up. A slot is held until the prover
+// returns, even after its job timed out, since proving can't be interrupted.
+// Every job's progress is published to `events`, and its outcome to webhook
+// subscribers.
 #[derive(Clone)]
-pub struct ProofWorker {}
+pub struct ProofWorker {
+    slots: Arc<Semaphore>,
+    job_timeout: Duration,
+    pub events: Arc<JobEvents>,
+    webhooks: Arc<Webhooks>,
+}
 
 impl ProofWorker {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(concurrency: usize, job_timeout: Duration, webhooks: Arc<Webhooks>) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(concurrency)),
+            job_timeout,
+            events: Arc::new(JobEvents::default()),
+            webhooks,
+        }
     }
 
     pub async fn process_job(&self, job_id: String, storage: Arc<Mutex<Storage>>) {
+        let deal_id = storage
+            .lock()
+            .unwrap()
+            .get_proof_job(&job_id)
+            .map(|job| job.deal_id.clone());
+
+        match self.run_job(&job_id, &storage).await {
+            Ok(proof_id) => {
+                self.webhooks.notify(
+                    WebhookEventType::ProofCompleted,
+                    deal_id.as_deref(),
+                    json!({ "job_id": job_id, "proof_id": proof_id }),
+                );
+                self.events
+                    .publish(&job_id, JobEventKind::Completed { proof_id });
+            }
+            Err(error) => {
+                println!("Proof job {} failed: {}", job_id, error);
+                storage.lock().unwrap().update_proof_job(&job_id, |job| {
+                    job.status = ProofJobStatus::Failed;
+                    job.error = Some(error.clone());
+                });
+                self.webhooks.notify(
+                    WebhookEventType::ProofFailed,
+                    deal_id.as_deref(),
+                    json!({ "job_id": job_id, "error": error }),
+                );
+                self.events.publish(&job_id, JobEventKind::Failed { error });
+            }
+        }
+    }
+
+    // Proves the job and stores the proof, returning its ID
+    async fn run_job(&self, job_id: &str, storage: &Arc<Mutex<Storage>>) -> Result<String, String> {
+        let slot = self
+            .slots
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("Worker semaphore closed");
+
         // Update job status to InProgress
         {
             let mut storage = storage.lock().unwrap();
-            storage.update_proof_job(&job_id, |job| {
+            storage.update_proof_job(job_id, |job| {
                 job.status = ProofJobStatus::InProgress;
             });
         }
 
-        // Get job details and commitments
-        let (deal_id, required_amount, commitments) = {
-            let storage = storage.lock().unwrap();
-            let job = storage.get_proof_job(&job_id).unwrap();
-            let commitments = storage
-                .get_commitments(&job.deal_id)
-                .map(|c| c.clone())
-                .unwrap_or_default();
-            (job.deal_id.clone(), job.required_amount, commitments)
+        // Get job details and the commitments chosen for it
+        let (deal_id, required_amount, options, commitments) = {
+            let mut storage = storage.lock().unwrap();
+            let job = storage.get_proof_job(job_id).unwrap().clone();
+            let commitments = storage.find_commitments(&job.deal_id, &job.commitment_ids);
+            (
+                job.deal_id.clone(),
+                job.required_amount,
+                job.options.clone(),
+                commitments,
+            )
         };
 
-        if commitments.len() < 2 {
-            let mut storage = storage.lock().unwrap();
-            storage.update_proof_job(&job_id, |job| {
-                job.status = ProofJobStatus::Failed;
-                job.error = Some("Not enough commitments".to_string());
-            });
-            return;
-        }
+        let selected =
+            commitments.ok_or("Selected commitments are no longer active".to_string())?;
+        let steps = selected.len() + 1;
+        self.events
+            .publish(job_id, JobEventKind::ProvingStarted { steps });
+
+        // Generate proof, reusing cached commitment sub-receipts. Proving
+        // blocks, so it runs off the async runtime.
+        let proving = {
+            let storage = storage.clone();
+            let options = options.clone();
+            let events = self.events.clone();
+            let job_id = job_id.to_string();
+            tokio::task::spawn_blocking(move || {
+                // Released when proving ends rather than when the job times out,
+                // so an abandoned proof still counts against the concurrency
+                let _slot = slot;
+                let step = AtomicUsize::new(0);
+                let step_proven = |stage, cached| {
+                    events.publish(
+                        &job_id,
+                        JobEventKind::StepProven {
+                            step: step.fetch_add(1, Ordering::Relaxed) + 1,
+                            steps,
+                            stage,
+                            cached,
+                        },
+                    )
+                };
+
+                commitment_hashes(&selected).and_then(|hashes| {
+                    let receipts = commitment_receipts(&selected, &storage, |cached| {
+                        step_proven("commitment", cached)
+                    })?;
+                    let proof = aggregate_proof(&receipts, required_amount, &options)?;
+                    step_proven("aggregation", false);
+                    Ok((hashes, proof))
+                })
+            })
+        };
+        // A timeout only abandons the job: the proof keeps its CPU and its slot
+        // until it runs to completion, then its result is dropped
+        let (hashes, (receipt, deal_info, verified_amount)) =
+            match tokio::time::timeout(self.job_timeout, proving).await {
+                Ok(Ok(result)) => result.map_err(|e| e.to_string())?,
+                Ok(Err(e)) => return Err(format!("Proving task failed: {}", e)),
+                Err(_) => return Err(format!("Timed out after {}s", self.job_timeout.as_secs())),
+            };
+
+        let size = receipt_size(&receipt).ok();
+        println!(
+            "Proof for job {} completed: {:?} receipt, {:?} bytes",
+            job_id, options.receipt_kind, size
+        );
 
-        // Generate proof
-        let result = generate_proof(
-            commitments[0].clone(),
-            commitments[1].clone(),
+        let proof = StoredProof::new(
+            deal_id,
+            Some(job_id.to_string()),
             required_amount,
+            hashes,
+            options,
+            receipt,
         );
 
-        // Process the result outside the closure first
-        match result {
-            Ok((receipt, deal_info, verified_amount)) => {
-                let mut storage = storage.lock().unwrap();
-                // First store the receipt
-                storage.add_proof(deal_id.clone(), receipt);
-                // Then update the job
-                storage.update_proof_job(&job_id, |job| {
-                    job.status = ProofJobStatus::Completed;
-                    job.proof = Some((deal_info.clone(), verified_amount));
-                });
-            }
-            Err(e) => {
-                let mut storage = storage.lock().unwrap();
-                storage.update_proof_job(&job_id, |job| {
-                    job.status = ProofJobStatus::Failed;
-                    job.error = Some(e.to_string());
-                });
-            }
-        }
+        let mut storage = storage.lock().unwrap();
+        // First store the proof
+        let proof_id = proof.id.clone();
+        storage.add_proof(proof);
+        // Then update the job
+        storage.update_proof_job(job_id, |job| {
+            job.status = ProofJobStatus::Completed;
+            job.proof = Some((deal_info.clone(), verified_amount));
+            job.proof_id = Some(proof_id.clone());
+            job.receipt_size = size;
+        });
+        Ok(proof_id)
     }
 }
@@ -0,0 +1,261 @@
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use k256::ecdsa::signature::{Signer, Verifier};
+use k256::ecdsa::{Signature, SigningKey, VerifyingKey};
+use risc0_zkvm::sha::{Impl, Sha256};
+use risc0_zkvm::Receipt;
+use serde::{Deserialize, Serialize};
+
+use crate::decode_journal;
+use crate::types::{DealInfo, Result, VerifierError};
+
+// File header: magic followed by the little-endian format version
+pub const BUNDLE_MAGIC: &[u8; 4] = b"RPOF";
+pub const BUNDLE_VERSION: u32 = 1;
+pub const BUNDLE_EXTENSION: &str = "pof";
+
+/// A proof of financing as handed to the Seller's Bank, with everything
+/// needed to check it offline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProofBundle {
+    pub image_id: [u32; 8],
+    pub deal_info: DealInfo,
+    pub verified_amount: u64,
+    pub registry_root: String,
+    pub created_at: u64, // Unix timestamp in seconds
+    pub prover_version: String,
+    pub receipt: Receipt,
+    pub signature: Option<BundleSignature>,
+}
+
+// Signature by the buyer's bank over the bundle contents
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleSignature {
+    pub pubkey: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl ProofBundle {
+    /// Wraps a receipt of the aggregation guest with the given image ID,
+    /// decoding its public journal.
+    pub fn new(receipt: Receipt, image_id: [u32; 8], registry_root: String) -> Result<Self> {
+        let journal = decode_journal(&receipt)?;
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        Ok(Self {
+            image_id,
+            deal_info: journal.deal_info,
+            verified_amount: journal.verified_amount,
+            registry_root,
+            created_at,
+            prover_version: format!(
+                "rwz-pof {} / risc0-zkvm {}",
+                env!("CARGO_PKG_VERSION"),
+                risc0_zkvm::VERSION
+            ),
+            receipt,
+            signature: None,
+        })
+    }
+
+    // Digest over every field except the signature
+    fn signing_digest(&self) -> Result<Vec<u8>> {
+        let unsigned = Self {
+            signature: None,
+            ..self.clone()
+        };
+        let bytes = bincode::serialize(&unsigned)?;
+        Ok(Impl::hash_bytes(&bytes).as_bytes().to_vec())
+    }
+
+    pub fn sign(&mut self, signing_key: &SigningKey) -> Result<()> {
+        let digest = self.signing_digest()?;
+        let signature: Signature = signing_key.sign(&digest);
+
+        self.signature = Some(BundleSignature {
+            pubkey: VerifyingKey::from(signing_key).to_sec1_bytes().to_vec(),
+            signature: signature.to_bytes().to_vec(),
+        });
+        Ok(())
+    }
+
+    /// Checks the buyer's bank signature, if any. Returns the signer's public key.
+    pub fn verify_signature(&self) -> Result<Option<Vec<u8>>> {
+        let Some(signed) = &self.signature else {
+            return Ok(None);
+        };
+
+        let verifying_key = VerifyingKey::from_sec1_bytes(&signed.pubkey)
+            .map_err(|e| VerifierError::SignatureError(format!("Invalid signer key: {}", e)))?;
+        let signature = Signature::from_slice(&signed.signature)
+            .map_err(|e| VerifierError::SignatureError(format!("Invalid signature: {}", e)))?;
+
+        verifying_key
+            .verify(&self.signing_digest()?, &signature)
+            .map_err(|_| VerifierError::SignatureError("Signature doesn't match".to_string()))?;
+
+        Ok(Some(signed.pubkey.clone()))
+    }
+
+    pub fn write_to(&self, mut writer: impl Write) -> Result<()> {
+        writer.write_all(BUNDLE_MAGIC)?;
+        writer.write_all(&BUNDLE_VERSION.to_le_bytes())?;
+        bincode::serialize_into(writer, self)?;
+        Ok(())
+    }
+
+    pub fn read_from(mut reader: impl Read) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != BUNDLE_MAGIC {
+            return Err(VerifierError::BundleError("Not a proof bundle".to_string()));
+        }
+
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != BUNDLE_VERSION {
+            return Err(VerifierError::BundleError(format!(
+                "Unsupported bundle version {}",
+                version
+            )));
+        }
+
+        Ok(bincode::deserialize_from(reader)?)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::read_from(bytes)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let file = std::fs::File::create(path)?;
+        self.write_to(std::io::BufWriter::new(file))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Self::read_from(std::io::BufReader::new(file))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::types::PofJournal;
+    use crate::{REGISTRY_ROOT, RWZ_POF_COMMITMENT_ID, RWZ_POF_GUEST_ID};
+    use risc0_zkvm::{FakeReceipt, InnerReceipt, ReceiptClaim};
+
+    // A receipt of the given guest with the given journal, without running the prover
+    pub(crate) fn fake_receipt_for(image_id: [u32; 8], journal: &impl Serialize) -> Receipt {
+        let words = risc0_zkvm::serde::to_vec(journal).unwrap();
+        let journal: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+
+        let claim = ReceiptClaim::ok(image_id, journal.clone());
+        Receipt::new(InnerReceipt::Fake(FakeReceipt::new(claim)), journal)
+    }
+
+    pub(crate) fn fake_receipt(amount: u64) -> Receipt {
+        fake_receipt_for(
+            RWZ_POF_GUEST_ID,
+            &PofJournal {
+                deal_info: fake_deal_info(amount),
+                verified_amount: amount,
+                commitment_image_id: RWZ_POF_COMMITMENT_ID,
+                bank_count: 2,
+                recipient: Some("SB1".into()),
+                challenge: Some("nonce-1".into()),
+                nullifiers: vec![[1; 32], [2; 32]],
+                requirement_hash: Some([3; 32]),
+                previous_journal_digest: None,
+            },
+        )
+    }
+
+    pub(crate) fn fake_deal_info(amount: u64) -> DealInfo {
+        DealInfo {
+            amount,
+            deal_id: "DEAL123".into(),
+            buyer: "buyer123".into(),
+        }
+    }
+
+    pub(crate) fn fake_bundle(amount: u64) -> ProofBundle {
+        ProofBundle::new(
+            fake_receipt(amount),
+            RWZ_POF_GUEST_ID,
+            REGISTRY_ROOT.to_string(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let bundle = fake_bundle(60);
+        let bytes = bundle.to_bytes().unwrap();
+        assert_eq!(&bytes[..4], BUNDLE_MAGIC);
+
+        let decoded = ProofBundle::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.deal_info, bundle.deal_info);
+        assert_eq!(decoded.verified_amount, 60);
+        assert_eq!(decoded.image_id, RWZ_POF_GUEST_ID);
+        assert_eq!(decoded.registry_root, REGISTRY_ROOT);
+        assert_eq!(decoded.created_at, bundle.created_at);
+        assert_eq!(decoded.receipt.journal.bytes, bundle.receipt.journal.bytes);
+        assert!(decoded.signature.is_none());
+    }
+
+    #[test]
+    fn test_bundle_file_round_trip() {
+        let path = std::env::temp_dir().join(format!(
+            "rwz-pof-test-{}.{}",
+            std::process::id(),
+            BUNDLE_EXTENSION
+        ));
+
+        let bundle = fake_bundle(60);
+        bundle.save(&path).unwrap();
+        let loaded = ProofBundle::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.deal_info, bundle.deal_info);
+    }
+
+    #[test]
+    fn test_signed_bundle() {
+        let key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let mut bundle = fake_bundle(60);
+        bundle.sign(&key).unwrap();
+
+        let decoded = ProofBundle::from_bytes(&bundle.to_bytes().unwrap()).unwrap();
+        let signer = decoded.verify_signature().unwrap().unwrap();
+        assert_eq!(signer, VerifyingKey::from(&key).to_sec1_bytes().to_vec());
+
+        // Changing the public metadata invalidates the signature
+        let mut tampered = decoded.clone();
+        tampered.verified_amount = 100;
+        assert!(tampered.verify_signature().is_err());
+    }
+
+    #[test]
+    fn test_rejects_unknown_format() {
+        let mut bytes = fake_bundle(60).to_bytes().unwrap();
+
+        bytes[4] = 99;
+        assert!(ProofBundle::from_bytes(&bytes).is_err());
+
+        bytes[0] = b'X';
+        assert!(ProofBundle::from_bytes(&bytes).is_err());
+    }
+}
@@ -0,0 +1,20 @@
+pub mod bundle;
+pub mod image_ids;
+pub mod policy;
+pub mod requirement;
+pub mod revision;
+pub mod types;
+pub mod verify;
+pub mod versions;
+
+pub use bundle::{BundleSignature, ProofBundle, BUNDLE_EXTENSION, BUNDLE_MAGIC, BUNDLE_VERSION};
+pub use image_ids::{REGISTRY_ROOT, RWZ_POF_COMMITMENT_ID, RWZ_POF_GUEST_ID};
+pub use policy::{AcceptancePolicy, PolicyVerdict, RuleResult};
+pub use requirement::{FinancingRequirement, SignedRequirement};
+pub use revision::{check_revision, journal_digest};
+pub use types::{DealInfo, PofJournal, Result, VerifiedDeal, VerifierError};
+pub use verify::{decode_journal, verify_bundle, verify_receipt, VerificationPolicy};
+pub use versions::{previous_guest_versions, GuestVersion, JournalFormat, GUEST_VERSION};
+
+// Re-export the receipt type so verifiers don't need a direct risc0 dependency
+pub use risc0_zkvm::Receipt;
@@ -0,0 +1,295 @@
+use std::path::Path;
+
+use risc0_zkvm::sha::Digest;
+use serde::{Deserialize, Serialize};
+
+use crate::types::{Result, VerifiedDeal, VerifierError};
+
+/// A Seller's Bank's acceptance rules for verified proofs, loaded from TOML
+/// or JSON. Rules that are left out aren't checked. The registry rule reads the
+/// registry of the commitment guest named in the verified journal, and the
+/// freshness rule only counts an authenticated creation time.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AcceptancePolicy {
+    pub id: String,
+    pub min_amount_percent: Option<u64>, // Of the bid amount
+    #[serde(default)]
+    pub accepted_image_ids: Vec<String>, // Hex
+    pub registry_root: Option<String>,
+    pub min_bank_count: Option<u32>,
+    pub max_age_secs: Option<u64>,
+    pub recipient: Option<String>,
+}
+
+// Outcome of a single rule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleResult {
+    pub rule: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+/// Every rule of a policy checked against a verified deal. The deal is
+/// accepted only if all of them passed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PolicyVerdict {
+    pub policy_id: String,
+    pub accepted: bool,
+    pub rules: Vec<RuleResult>,
+}
+
+impl AcceptancePolicy {
+    pub fn from_toml_str(s: &str) -> Result<Self> {
+        toml::from_str(s).map_err(|e| VerifierError::PolicyError(e.to_string()))
+    }
+
+    pub fn from_json_str(s: &str) -> Result<Self> {
+        serde_json::from_str(s).map_err(|e| VerifierError::PolicyError(e.to_string()))
+    }
+
+    /// Loads a policy file, as JSON if it has a `.json` extension and as
+    /// TOML otherwise.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        let policy = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Self::from_json_str(&contents),
+            _ => Self::from_toml_str(&contents),
+        };
+        policy.map_err(|e| VerifierError::PolicyError(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Checks a verified deal against every rule. `bid_amount` is what the
+    /// buyer offered and `now` the current Unix time in seconds.
+    pub fn evaluate(
+        &self,
+        deal: &VerifiedDeal,
+        bid_amount: Option<u64>,
+        now: u64,
+    ) -> PolicyVerdict {
+        let mut rules = Vec::new();
+
+        if let Some(percent) = self.min_amount_percent {
+            rules.push(match bid_amount {
+                Some(bid) => {
+                    let required = (bid as u128 * percent as u128).div_ceil(100) as u64;
+                    RuleResult::check(
+                        "min_amount",
+                        deal.verified_amount >= required,
+                        format!(
+                            "verified {} of required {} ({}% of bid {})",
+                            deal.verified_amount, required, percent, bid
+                        ),
+                    )
+                }
+                None => RuleResult::check("min_amount", false, "no bid amount given".to_string()),
+            });
+        }
+
+        if !self.accepted_image_ids.is_empty() {
+            let image_id = Digest::from(deal.image_id).to_string();
+            rules.push(RuleResult::check(
+                "image_id",
+                self.accepted_image_ids
+                    .iter()
+                    .any(|id| id.eq_ignore_ascii_case(&image_id)),
+                format!("image ID {}", image_id),
+            ));
+        }
+
+        if let Some(root) = &self.registry_root {
+            rules.push(RuleResult::check(
+                "registry_root",
+                deal.registry_root
+                    .as_ref()
+                    .is_some_and(|registry_root| root.eq_ignore_ascii_case(registry_root)),
+                match &deal.registry_root {
+                    Some(registry_root) => format!("registry root {}", registry_root),
+                    None => "registry of the commitment guest is unknown".to_string(),
+                },
+            ));
+        }
+
+        if let Some(min) = self.min_bank_count {
+            rules.push(RuleResult::check(
+                "min_bank_count",
+                deal.bank_count >= min,
+                format!("{} lending banks, {} required", deal.bank_count, min),
+            ));
+        }
+
+        if let Some(max_age) = self.max_age_secs {
+            rules.push(match deal.created_at {
+                Some(created_at) => {
+                    let age = now.saturating_sub(created_at);
+                    RuleResult::check(
+                        "freshness",
+                        age <= max_age,
+                        format!("created {}s ago, at most {}s allowed", age, max_age),
+                    )
+                }
+                None => RuleResult::check(
+                    "freshness",
+                    false,
+                    "creation time isn't authenticated, sign the bundle".to_string(),
+                ),
+            });
+        }
+
+        if let Some(recipient) = &self.recipient {
+            rules.push(RuleResult::check(
+                "recipient",
+                deal.recipient.as_ref() == Some(recipient),
+                match &deal.recipient {
+                    Some(bound) => format!("proof bound to {}", bound),
+                    None => "proof isn't bound to a recipient".to_string(),
+                },
+            ));
+        }
+
+        PolicyVerdict {
+            policy_id: self.id.clone(),
+            accepted: rules.iter().all(|rule| rule.passed),
+            rules,
+        }
+    }
+}
+
+impl RuleResult {
+    fn check(rule: &str, passed: bool, detail: String) -> Self {
+        Self {
+            rule: rule.to_string(),
+            passed,
+            detail,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DealInfo;
+    use crate::REGISTRY_ROOT;
+
+    fn verified_deal() -> VerifiedDeal {
+        VerifiedDeal {
+            deal_info: DealInfo {
+                amount: 60,
+                deal_id: "DEAL123".into(),
+                buyer: "buyer123".into(),
+            },
+            verified_amount: 60,
+            bank_count: 2,
+            recipient: Some("SB1".into()),
+            challenge: None,
+            nullifiers: Vec::new(),
+            requirement_hash: None,
+            journal_digest: [0; 32],
+            previous_journal_digest: None,
+            image_id: [7; 8],
+            guest_version: "v3".into(),
+            registry_root: Some(REGISTRY_ROOT.to_string()),
+            created_at: Some(1_000),
+            signer: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_toml_and_json() {
+        let toml = r#"
+            id = "sb1-default"
+            min_amount_percent = 100
+            min_bank_count = 2
+            recipient = "SB1"
+        "#;
+        let json = r#"{"id": "sb1-default", "min_amount_percent": 100,
+            "min_bank_count": 2, "recipient": "SB1"}"#;
+
+        let policy = AcceptancePolicy::from_toml_str(toml).unwrap();
+        assert_eq!(policy, AcceptancePolicy::from_json_str(json).unwrap());
+        assert_eq!(policy.min_bank_count, Some(2));
+        assert!(policy.accepted_image_ids.is_empty());
+
+        assert!(AcceptancePolicy::from_toml_str("id = \"x\"\nmin_banks = 2").is_err());
+        let policy = AcceptancePolicy::from_toml_str("id = \"x\"\nmax_age_secs = 60").unwrap();
+        assert_eq!(policy.max_age_secs, Some(60));
+    }
+
+    #[test]
+    fn test_evaluate_accepts() {
+        let policy = AcceptancePolicy {
+            id: "sb1".into(),
+            min_amount_percent: Some(50),
+            accepted_image_ids: vec![Digest::from([7u32; 8]).to_string()],
+            registry_root: Some(REGISTRY_ROOT.to_string()),
+            min_bank_count: Some(2),
+            max_age_secs: Some(60),
+            recipient: Some("SB1".into()),
+        };
+
+        let verdict = policy.evaluate(&verified_deal(), Some(120), 1_030);
+        assert!(verdict.accepted, "{:?}", verdict);
+        assert_eq!(verdict.rules.len(), 6);
+    }
+
+    #[test]
+    fn test_evaluate_lists_every_failure() {
+        let policy = AcceptancePolicy {
+            id: "strict".into(),
+            min_amount_percent: Some(100),
+            registry_root: Some("other".into()),
+            min_bank_count: Some(3),
+            max_age_secs: Some(60),
+            recipient: Some("SB2".into()),
+            ..Default::default()
+        };
+
+        let verdict = policy.evaluate(&verified_deal(), Some(80), 2_000);
+        assert!(!verdict.accepted);
+
+        let failed: Vec<_> = verdict
+            .rules
+            .iter()
+            .filter(|rule| !rule.passed)
+            .map(|rule| rule.rule.as_str())
+            .collect();
+        assert_eq!(
+            failed,
+            [
+                "min_amount",
+                "registry_root",
+                "min_bank_count",
+                "freshness",
+                "recipient"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_freshness_needs_authenticated_creation_time() {
+        let policy = AcceptancePolicy {
+            id: "sb1".into(),
+            max_age_secs: Some(60),
+            ..Default::default()
+        };
+        let unsigned = VerifiedDeal {
+            created_at: None,
+            ..verified_deal()
+        };
+
+        assert!(policy.evaluate(&verified_deal(), None, 1_030).accepted);
+        assert!(!policy.evaluate(&unsigned, None, 1_000).accepted);
+    }
+
+    #[test]
+    fn test_min_amount_without_bid_fails() {
+        let policy = AcceptancePolicy {
+            id: "sb1".into(),
+            min_amount_percent: Some(100),
+            ..Default::default()
+        };
+
+        assert!(!policy.evaluate(&verified_deal(), None, 1_000).accepted);
+    }
+}
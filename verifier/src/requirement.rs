@@ -0,0 +1,98 @@
+use k256::ecdsa::signature::{Signer, Verifier};
+use k256::ecdsa::{Signature, SigningKey, VerifyingKey};
+use risc0_zkvm::sha::{Impl, Sha256};
+use serde::{Deserialize, Serialize};
+
+use crate::types::{Result, VerifierError};
+
+/// The financing a seller requires for an auction and deal. Buyers prove
+/// against it instead of picking their own amount.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FinancingRequirement {
+    pub auction_id: String,
+    pub deal_id: String,
+    pub amount: u64,
+}
+
+/// A requirement signed by the Seller's Bank. The aggregation guest checks
+/// the signature and commits `hash()`, so the seller can tell its own
+/// requirement was met.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedRequirement {
+    pub pubkey: Vec<u8>, // SEC1
+    pub requirement: FinancingRequirement,
+    pub signature: Vec<u8>, // Over the bincode-encoded requirement
+}
+
+impl SignedRequirement {
+    pub fn sign(key: &SigningKey, requirement: FinancingRequirement) -> Result<Self> {
+        let signature: Signature = key.sign(&bincode::serialize(&requirement)?);
+        Ok(Self {
+            pubkey: VerifyingKey::from(key).to_sec1_bytes().to_vec(),
+            requirement,
+            signature: signature.to_bytes().to_vec(),
+        })
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        let key = VerifyingKey::from_sec1_bytes(&self.pubkey)
+            .map_err(|e| VerifierError::RequirementError(format!("Invalid seller key: {}", e)))?;
+        let signature = Signature::from_slice(&self.signature)
+            .map_err(|e| VerifierError::RequirementError(e.to_string()))?;
+        key.verify(&bincode::serialize(&self.requirement)?, &signature)
+            .map_err(|_| VerifierError::RequirementError("Signature is invalid".to_string()))
+    }
+
+    /// SHA-256 over the bincode encoding of `(pubkey, requirement)`, as
+    /// committed by the guest. Covers the seller's key, so a requirement
+    /// signed by anyone else hashes differently.
+    pub fn hash(&self) -> Result<[u8; 32]> {
+        let bytes = bincode::serialize(&(&self.pubkey, &self.requirement))?;
+        Ok(Impl::hash_bytes(&bytes)
+            .as_bytes()
+            .try_into()
+            .expect("SHA-256 digest is 32 bytes"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requirement(amount: u64) -> FinancingRequirement {
+        FinancingRequirement {
+            auction_id: "AUCTION1".into(),
+            deal_id: "DEAL123".into(),
+            amount,
+        }
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = SigningKey::from_slice(&[7; 32]).unwrap();
+        let signed = SignedRequirement::sign(&key, requirement(60)).unwrap();
+        signed.verify().unwrap();
+
+        let mut raised = signed.clone();
+        raised.requirement.amount = 50;
+        assert!(raised.verify().is_err());
+    }
+
+    #[test]
+    fn test_hash_covers_the_seller_key() {
+        let seller = SigningKey::from_slice(&[7; 32]).unwrap();
+        let other = SigningKey::from_slice(&[8; 32]).unwrap();
+
+        let signed = SignedRequirement::sign(&seller, requirement(60)).unwrap();
+        let forged = SignedRequirement::sign(&other, requirement(60)).unwrap();
+
+        assert_eq!(
+            signed.hash().unwrap(),
+            SignedRequirement::sign(&seller, requirement(60))
+                .unwrap()
+                .hash()
+                .unwrap()
+        );
+        assert_ne!(signed.hash().unwrap(), forged.hash().unwrap());
+    }
+}
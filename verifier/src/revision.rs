@@ -0,0 +1,109 @@
+use risc0_zkvm::sha::{Impl, Sha256};
+use risc0_zkvm::Receipt;
+
+use crate::types::{Result, VerifiedDeal, VerifierError};
+
+/// SHA-256 of a receipt's journal bytes. A revision proof commits the digest
+/// of the proof it raises, which the guest computes the same way.
+pub fn journal_digest(receipt: &Receipt) -> [u8; 32] {
+    Impl::hash_bytes(&receipt.journal.bytes)
+        .as_bytes()
+        .try_into()
+        .expect("SHA-256 digest is 32 bytes")
+}
+
+/// Checks that `revision` raises `previous`, both already verified: it commits
+/// the digest of `previous`'s journal, for the same deal and buyer, and proves
+/// a higher amount. The guest checks the same, this guards against a receipt
+/// from a guest that didn't.
+pub fn check_revision(previous: &VerifiedDeal, revision: &VerifiedDeal) -> Result<()> {
+    if revision.previous_journal_digest != Some(previous.journal_digest) {
+        return Err(VerifierError::RevisionError(
+            "Proof doesn't revise the previous proof".to_string(),
+        ));
+    }
+    if revision.deal_info.deal_id != previous.deal_info.deal_id {
+        return Err(VerifierError::RevisionError(format!(
+            "Revision is for deal {}, not {}",
+            revision.deal_info.deal_id, previous.deal_info.deal_id
+        )));
+    }
+    if revision.deal_info.buyer != previous.deal_info.buyer {
+        return Err(VerifierError::RevisionError(format!(
+            "Revision is from buyer {}, not {}",
+            revision.deal_info.buyer, previous.deal_info.buyer
+        )));
+    }
+    if revision.verified_amount <= previous.verified_amount {
+        return Err(VerifierError::RevisionError(format!(
+            "Revised amount {} doesn't exceed {}",
+            revision.verified_amount, previous.verified_amount
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundle::tests::{fake_deal_info, fake_receipt, fake_receipt_for};
+    use crate::image_ids::{RWZ_POF_COMMITMENT_ID, RWZ_POF_GUEST_ID};
+    use crate::types::PofJournal;
+    use crate::verify::decode_journal;
+    use crate::versions::GuestVersion;
+
+    fn verified(receipt: &Receipt) -> VerifiedDeal {
+        VerifiedDeal::new(
+            &GuestVersion::current(),
+            decode_journal(receipt).unwrap(),
+            journal_digest(receipt),
+            None,
+            None,
+        )
+    }
+
+    fn revision_of(previous: &Receipt, amount: u64) -> Receipt {
+        fake_receipt_for(
+            RWZ_POF_GUEST_ID,
+            &PofJournal {
+                deal_info: fake_deal_info(amount),
+                verified_amount: amount,
+                commitment_image_id: RWZ_POF_COMMITMENT_ID,
+                bank_count: 2,
+                recipient: None,
+                challenge: None,
+                nullifiers: Vec::new(),
+                requirement_hash: None,
+                previous_journal_digest: Some(journal_digest(previous)),
+            },
+        )
+    }
+
+    #[test]
+    fn test_check_revision() {
+        let first = fake_receipt(60);
+        let raised = revision_of(&first, 70);
+
+        check_revision(&verified(&first), &verified(&raised)).unwrap();
+
+        // Same amount isn't a raise
+        let same = revision_of(&first, 60);
+        assert!(check_revision(&verified(&first), &verified(&same)).is_err());
+
+        // Links to another proof
+        let other = fake_receipt(50);
+        assert!(check_revision(&verified(&other), &verified(&raised)).is_err());
+    }
+
+    #[test]
+    fn test_rejects_another_buyer() {
+        let first = fake_receipt(60);
+        let mut raised = verified(&revision_of(&first, 70));
+        raised.deal_info.buyer = "buyer456".into();
+
+        assert!(matches!(
+            check_revision(&verified(&first), &raised),
+            Err(VerifierError::RevisionError(_))
+        ));
+    }
+}
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+
+use crate::versions::GuestVersion;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DealInfo {
+    pub amount: u64,
+    pub deal_id: String,
+    pub buyer: String,
+}
+
+// Public output of the aggregation guest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PofJournal {
+    pub deal_info: DealInfo,
+    pub verified_amount: u64,
+    pub commitment_image_id: [u32; 8],
+    pub bank_count: u32,
+    pub recipient: Option<String>, // Seller's Bank the proof was made for
+    pub challenge: Option<String>, // Auction challenge nonce the proof answers
+    pub nullifiers: Vec<[u8; 32]>, // One per lending bank, the same across buyers of a deal
+    pub requirement_hash: Option<[u8; 32]>, // `SignedRequirement::hash` of the seller's requirement
+    pub previous_journal_digest: Option<[u8; 32]>, // `journal_digest` of the proof this one revises
+}
+
+// What the Seller's Bank learns from a proof that passed verification
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifiedDeal {
+    pub deal_info: DealInfo,
+    pub verified_amount: u64,
+    pub bank_count: u32,
+    pub recipient: Option<String>,
+    pub challenge: Option<String>,
+    pub nullifiers: Vec<[u8; 32]>,
+    pub requirement_hash: Option<[u8; 32]>,
+    pub journal_digest: [u8; 32], // Links a revision proof to this one
+    pub previous_journal_digest: Option<[u8; 32]>,
+    pub image_id: [u32; 8],
+    pub guest_version: String, // Label of the accepted guest that produced the receipt
+    pub registry_root: Option<String>, // Of the commitment guest named in the journal
+    pub created_at: Option<u64>, // Only if authenticated, by the bundle signature or the server's record
+    pub signer: Option<Vec<u8>>, // Buyer's bank public key, if the bundle was signed
+}
+
+impl VerifiedDeal {
+    pub fn new(
+        version: &GuestVersion,
+        journal: PofJournal,
+        journal_digest: [u8; 32],
+        created_at: Option<u64>,
+        signer: Option<Vec<u8>>,
+    ) -> Self {
+        let registry_root = version.registry_root_for(&journal).map(str::to_string);
+        Self {
+            deal_info: journal.deal_info,
+            verified_amount: journal.verified_amount,
+            bank_count: journal.bank_count,
+            recipient: journal.recipient,
+            challenge: journal.challenge,
+            nullifiers: journal.nullifiers,
+            requirement_hash: journal.requirement_hash,
+            journal_digest,
+            previous_journal_digest: journal.previous_journal_digest,
+            image_id: version.image_id,
+            guest_version: version.label.clone(),
+            registry_root,
+            created_at,
+            signer,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum VerifierError {
+    #[error("Proof bundle error: {0}")]
+    BundleError(String),
+
+    #[error("Image ID not accepted: {0}")]
+    ImageNotAccepted(String),
+
+    #[error("Guest version {0} is deprecated")]
+    ImageDeprecated(String),
+
+    #[error("Proof was aggregated from an unexpected commitment guest: {0}")]
+    CommitmentImageMismatch(String),
+
+    #[error("Registry root not accepted: {0}")]
+    RegistryNotAccepted(String),
+
+    #[error("Receipt verification failed: {0}")]
+    ReceiptError(String),
+
+    #[error("Bundle signature error: {0}")]
+    SignatureError(String),
+
+    #[error("Financing requirement error: {0}")]
+    RequirementError(String),
+
+    #[error("Revision error: {0}")]
+    RevisionError(String),
+
+    #[error("Policy error: {0}")]
+    PolicyError(String),
+
+    #[error("Serialization error: {0}")]
+    SerializationError(#[from] bincode::Error),
+
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, VerifierError>;
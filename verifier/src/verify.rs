@@ -0,0 +1,292 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use risc0_zkvm::sha::{Digest, Digestible};
+use risc0_zkvm::Receipt;
+
+use crate::bundle::ProofBundle;
+use crate::image_ids::REGISTRY_ROOT;
+use crate::revision::journal_digest;
+use crate::types::{PofJournal, Result, VerifiedDeal, VerifierError};
+use crate::versions::{previous_guest_versions, GuestVersion, JournalFormat};
+
+/// What a verifier accepts. The default accepts the embedded guest and any
+/// previous guests that aren't deprecated yet, the embedded registry root,
+/// and doesn't require a signed bundle. The registry root is the one compiled
+/// into the commitment guest a verified journal names.
+#[derive(Debug, Clone)]
+pub struct VerificationPolicy {
+    pub guest_versions: Vec<GuestVersion>,
+    pub accepted_registry_roots: Vec<String>,
+    pub required_signer: Option<Vec<u8>>,
+}
+
+impl Default for VerificationPolicy {
+    fn default() -> Self {
+        let mut guest_versions = vec![GuestVersion::current()];
+        guest_versions.extend(previous_guest_versions());
+
+        Self {
+            guest_versions,
+            accepted_registry_roots: vec![REGISTRY_ROOT.to_string()],
+            required_signer: None,
+        }
+    }
+}
+
+/// Decodes the current aggregation guest's journal: the public deal info, the
+/// proven amount, the image ID of the commitment guest it aggregated, the
+/// number of lending banks and the recipient the proof is bound to.
+pub fn decode_journal(receipt: &Receipt) -> Result<PofJournal> {
+    JournalFormat::V6.decode(receipt)
+}
+
+/// Verifies a receipt against the accepted guest version whose image ID it
+/// claims and checks its registry, returning that version and its journal in
+/// the current layout.
+pub fn verify_receipt(
+    receipt: &Receipt,
+    policy: &VerificationPolicy,
+) -> Result<(GuestVersion, PofJournal)> {
+    let version = claimed_version(receipt, policy)?;
+    if version.is_deprecated(unix_now()) {
+        return Err(VerifierError::ImageDeprecated(version.label.clone()));
+    }
+
+    receipt
+        .verify(version.image_id)
+        .map_err(|e| VerifierError::ReceiptError(e.to_string()))?;
+
+    let journal = decode_verified_journal(version, receipt)?;
+    check_registry(version, &journal, policy)?;
+    Ok((version.clone(), journal))
+}
+
+// The accepted guest version matching the image ID in the receipt's claim.
+// Nothing is trusted until the receipt verifies against it.
+fn claimed_version<'a>(
+    receipt: &Receipt,
+    policy: &'a VerificationPolicy,
+) -> Result<&'a GuestVersion> {
+    let claim = receipt
+        .claim()
+        .map_err(|e| VerifierError::ReceiptError(e.to_string()))?;
+    let image_id = claim
+        .as_value()
+        .map_err(|e| VerifierError::ReceiptError(e.to_string()))?
+        .pre
+        .digest();
+
+    policy
+        .guest_versions
+        .iter()
+        .find(|version| Digest::from(version.image_id) == image_id)
+        .ok_or_else(|| VerifierError::ImageNotAccepted(image_id.to_string()))
+}
+
+/// Checks a bundle against the policy and verifies its receipt. Nothing from
+/// the bundle's metadata is trusted unless it matches the verified journal.
+pub fn verify_bundle(bundle: &ProofBundle, policy: &VerificationPolicy) -> Result<VerifiedDeal> {
+    let version = policy
+        .guest_versions
+        .iter()
+        .find(|version| version.image_id == bundle.image_id)
+        .ok_or_else(|| VerifierError::ImageNotAccepted(format!("{:?}", bundle.image_id)))?;
+
+    if version.is_deprecated(unix_now()) {
+        return Err(VerifierError::ImageDeprecated(version.label.clone()));
+    }
+
+    bundle
+        .receipt
+        .verify(bundle.image_id)
+        .map_err(|e| VerifierError::ReceiptError(e.to_string()))?;
+
+    let journal = decode_verified_journal(version, &bundle.receipt)?;
+    let registry_root = check_registry(version, &journal, policy)?;
+    if journal.deal_info != bundle.deal_info
+        || journal.verified_amount != bundle.verified_amount
+        || !registry_root.eq_ignore_ascii_case(&bundle.registry_root)
+    {
+        return Err(VerifierError::BundleError(
+            "Bundle metadata doesn't match the receipt journal".to_string(),
+        ));
+    }
+
+    let signer = bundle.verify_signature()?;
+    if let Some(required) = &policy.required_signer {
+        if signer.as_ref() != Some(required) {
+            return Err(VerifierError::SignatureError(
+                "Bundle is not signed by the required buyer's bank".to_string(),
+            ));
+        }
+    }
+
+    // The creation time is covered by the signature, so only a signed bundle's counts
+    let created_at = signer.as_ref().map(|_| bundle.created_at);
+    Ok(VerifiedDeal::new(
+        version,
+        journal,
+        journal_digest(&bundle.receipt),
+        created_at,
+        signer,
+    ))
+}
+
+// The registry compiled into the commitment guest the verified journal names,
+// rejected unless the policy accepts it. The bundle's registry root is only a
+// label and isn't trusted.
+fn check_registry<'a>(
+    version: &'a GuestVersion,
+    journal: &PofJournal,
+    policy: &VerificationPolicy,
+) -> Result<&'a str> {
+    let registry_root = version.registry_root_for(journal).ok_or_else(|| {
+        VerifierError::RegistryNotAccepted(format!(
+            "unknown registry of commitment guest {}",
+            Digest::from(journal.commitment_image_id)
+        ))
+    })?;
+
+    if !policy
+        .accepted_registry_roots
+        .iter()
+        .any(|root| root.eq_ignore_ascii_case(registry_root))
+    {
+        return Err(VerifierError::RegistryNotAccepted(
+            registry_root.to_string(),
+        ));
+    }
+    Ok(registry_root)
+}
+
+// Decodes the journal of a receipt verified against `version` with its
+// decoder, checking it aggregated the version's commitment guest
+fn decode_verified_journal(version: &GuestVersion, receipt: &Receipt) -> Result<PofJournal> {
+    let journal = version.journal.decode(receipt)?;
+
+    if let Some(commitment_image_id) = version.commitment_image_id {
+        if journal.commitment_image_id != commitment_image_id {
+            return Err(VerifierError::CommitmentImageMismatch(format!(
+                "{:?}",
+                journal.commitment_image_id
+            )));
+        }
+    }
+
+    Ok(journal)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundle::tests::{fake_bundle, fake_receipt, fake_receipt_for};
+    use crate::image_ids::RWZ_POF_COMMITMENT_ID;
+
+    #[test]
+    fn test_rejects_unaccepted_image_id() {
+        let bundle = fake_bundle(60);
+        let policy = VerificationPolicy {
+            guest_versions: vec![GuestVersion {
+                image_id: [1; 8],
+                ..GuestVersion::current()
+            }],
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            verify_bundle(&bundle, &policy),
+            Err(VerifierError::ImageNotAccepted(_))
+        ));
+    }
+
+    #[test]
+    fn test_rejects_unaccepted_registry() {
+        let journal = decode_journal(&fake_receipt(60)).unwrap();
+        let version = GuestVersion::current();
+        assert_eq!(
+            check_registry(&version, &journal, &VerificationPolicy::default()).unwrap(),
+            REGISTRY_ROOT
+        );
+
+        let policy = VerificationPolicy {
+            accepted_registry_roots: vec!["other".to_string()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            check_registry(&version, &journal, &policy),
+            Err(VerifierError::RegistryNotAccepted(_))
+        ));
+
+        // The registry is unknown unless the journal names the pinned commitment guest
+        let unpinned = GuestVersion {
+            commitment_image_id: None,
+            ..GuestVersion::current()
+        };
+        assert!(matches!(
+            check_registry(&unpinned, &journal, &VerificationPolicy::default()),
+            Err(VerifierError::RegistryNotAccepted(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_journal() {
+        let bundle = fake_bundle(60);
+        let journal = decode_journal(&bundle.receipt).unwrap();
+
+        assert_eq!(journal.deal_info.deal_id, "DEAL123");
+        assert_eq!(journal.verified_amount, 60);
+        assert_eq!(journal.commitment_image_id, RWZ_POF_COMMITMENT_ID);
+        assert_eq!(journal.bank_count, 2);
+        assert_eq!(journal.recipient.as_deref(), Some("SB1"));
+    }
+
+    #[test]
+    fn test_rejects_deprecated_guest() {
+        let bundle = fake_bundle(60);
+        let policy = VerificationPolicy {
+            guest_versions: vec![GuestVersion {
+                deprecated_at: Some(0),
+                ..GuestVersion::current()
+            }],
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            verify_bundle(&bundle, &policy),
+            Err(VerifierError::ImageDeprecated(_))
+        ));
+    }
+
+    #[test]
+    fn test_routes_receipt_to_claimed_version() {
+        let previous = GuestVersion {
+            label: "v5".to_string(),
+            image_id: [2; 8],
+            ..GuestVersion::current()
+        };
+        let policy = VerificationPolicy {
+            guest_versions: vec![GuestVersion::current(), previous],
+            ..Default::default()
+        };
+
+        let receipt = fake_receipt_for([2; 8], &decode_journal(&fake_receipt(60)).unwrap());
+        assert_eq!(claimed_version(&receipt, &policy).unwrap().label, "v5");
+
+        let unknown = fake_receipt_for([9; 8], &decode_journal(&fake_receipt(60)).unwrap());
+        assert!(matches!(
+            claimed_version(&unknown, &policy),
+            Err(VerifierError::ImageNotAccepted(_))
+        ));
+        assert!(matches!(
+            verify_receipt(&unknown, &policy),
+            Err(VerifierError::ImageNotAccepted(_))
+        ));
+    }
+}
@@ -0,0 +1,90 @@
+use risc0_zkvm::Receipt;
+
+use crate::image_ids::{REGISTRY_ROOT, RWZ_POF_COMMITMENT_ID, RWZ_POF_GUEST_ID};
+use crate::types::{PofJournal, Result, VerifierError};
+
+/// Label of the guest built from this tree.
+pub const GUEST_VERSION: &str = "v6";
+
+/// Layout of the aggregation guest's journal. When a guest changes it, add a
+/// variant decoding the outgoing layout and register the outgoing guest in
+/// `previous_guest_versions` with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalFormat {
+    /// `PofJournal`, with the journal digest of the proof a revision raises
+    V6,
+}
+
+impl JournalFormat {
+    /// Decodes a journal of this format into the current layout.
+    pub fn decode(&self, receipt: &Receipt) -> Result<PofJournal> {
+        let journal = match self {
+            Self::V6 => receipt.journal.decode::<PofJournal>(),
+        };
+        journal.map_err(|e| VerifierError::ReceiptError(format!("Failed to decode journal: {}", e)))
+    }
+}
+
+/// An aggregation guest a verifier accepts receipts from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuestVersion {
+    pub label: String,
+    pub image_id: [u32; 8],
+    pub commitment_image_id: Option<[u32; 8]>, // None skips the check
+    pub registry_root: Option<String>,         // Compiled into the commitment guest
+    pub journal: JournalFormat,
+    pub deprecated_at: Option<u64>, // Unix timestamp from which receipts are rejected
+}
+
+impl GuestVersion {
+    /// The guest whose image IDs are embedded in this crate.
+    pub fn current() -> Self {
+        Self {
+            label: GUEST_VERSION.to_string(),
+            image_id: RWZ_POF_GUEST_ID,
+            commitment_image_id: Some(RWZ_POF_COMMITMENT_ID),
+            registry_root: Some(REGISTRY_ROOT.to_string()),
+            journal: JournalFormat::V6,
+            deprecated_at: None,
+        }
+    }
+
+    pub fn is_deprecated(&self, now: u64) -> bool {
+        self.deprecated_at
+            .is_some_and(|deprecated_at| now >= deprecated_at)
+    }
+
+    /// The registry a journal's lending banks were checked against: the one
+    /// compiled into the commitment guest the journal names. Known only if
+    /// that is the commitment guest this version pins.
+    pub fn registry_root_for(&self, journal: &PofJournal) -> Option<&str> {
+        self.commitment_image_id
+            .filter(|image_id| *image_id == journal.commitment_image_id)
+            .and(self.registry_root.as_deref())
+    }
+}
+
+/// Guests replaced by an upgrade, still accepted until their deprecation date
+/// so outstanding receipts stay valid. Before regenerating `image_ids.rs` for
+/// a changed guest, add the outgoing image IDs here. None so far: no receipts
+/// were issued under earlier embedded image IDs.
+pub fn previous_guest_versions() -> Vec<GuestVersion> {
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deprecation() {
+        let version = GuestVersion {
+            deprecated_at: Some(1_000),
+            ..GuestVersion::current()
+        };
+
+        assert!(!version.is_deprecated(999));
+        assert!(version.is_deprecated(1_000));
+        assert!(!GuestVersion::current().is_deprecated(u64::MAX));
+    }
+}